@@ -0,0 +1,15 @@
+-- Every track that started playing in a guild
+CREATE TABLE IF NOT EXISTS play_history (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    requester_id BIGINT UNSIGNED NULL,
+    title VARCHAR(255) NOT NULL,
+    uri VARCHAR(512) NOT NULL,
+    length_ms BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    started_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- NULL until the track finishes
+    played_ms BIGINT UNSIGNED NULL,
+    skipped TINYINT(1) NULL,
+    PRIMARY KEY (id),
+    KEY play_history_guild_started (guild_id, started_at)
+);
@@ -0,0 +1,8 @@
+-- Per guild configuration. One row per guild, created on first change
+CREATE TABLE IF NOT EXISTS guild_settings (
+    guild_id BIGINT UNSIGNED NOT NULL,
+    -- Channel the weekly music summary is posted in
+    stats_channel_id BIGINT UNSIGNED NULL,
+    stats_summary_sent_at DATETIME NULL,
+    PRIMARY KEY (guild_id)
+);
@@ -0,0 +1 @@
+ALTER TABLE guild_settings ADD COLUMN autoplay TINYINT(1) NOT NULL DEFAULT 0;
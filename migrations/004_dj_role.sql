@@ -0,0 +1,2 @@
+-- Members with this role can reorder the queue. NULL means everyone can
+ALTER TABLE guild_settings ADD COLUMN dj_role_id BIGINT UNSIGNED NULL;
@@ -0,0 +1,21 @@
+-- Tracks waiting to be downloaded into the jam library, see features/downloads.rs
+CREATE TABLE IF NOT EXISTS download_jobs (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    -- What was passed to /j, searched on youtube
+    query VARCHAR(255) NOT NULL,
+    status ENUM('pending', 'running', 'done', 'failed') NOT NULL DEFAULT 'pending',
+    attempts INT UNSIGNED NOT NULL DEFAULT 0,
+    -- Failed jobs wait here before being picked up again
+    next_attempt_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    error TEXT NULL,
+    -- Set once the job is done
+    title VARCHAR(255) NULL,
+    ext VARCHAR(16) NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    -- The same query is only downloaded once per guild
+    UNIQUE KEY download_jobs_guild_query (guild_id, query),
+    KEY download_jobs_status (status, next_attempt_at)
+);
@@ -0,0 +1,24 @@
+-- Audio files in the media store (MEDIA_ROOT), see helpers/media_store.rs. Files are stored as
+-- <root>/<first 2 characters of checksum>/<checksum>.<ext>
+CREATE TABLE IF NOT EXISTS media_files (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    -- "<extractor>:<id>", e.g. "youtube:dQw4w9WgXcQ"
+    source_id VARCHAR(255) NOT NULL,
+    source_url VARCHAR(512) NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    duration_ms BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    codec VARCHAR(32) NOT NULL,
+    ext VARCHAR(16) NOT NULL,
+    size_bytes BIGINT UNSIGNED NOT NULL,
+    -- sha256 of the file, hex encoded
+    checksum CHAR(64) NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY media_files_checksum (checksum),
+    KEY media_files_source (source_id)
+);
+
+-- NULL for tracks downloaded before the media store, those are looked up as
+-- <root>/<audio_name>.<ext> and have to be moved there by hand
+ALTER TABLE jam_it ADD COLUMN media_id BIGINT UNSIGNED NULL;
+ALTER TABLE download_jobs ADD COLUMN media_id BIGINT UNSIGNED NULL;
@@ -0,0 +1,5 @@
+-- Least recently used files are evicted first when a quota is reached, see features/storage.rs
+ALTER TABLE media_files ADD COLUMN last_used_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE media_files ADD KEY media_files_last_used (last_used_at);
+ALTER TABLE jam_it ADD KEY jam_it_media (media_id);
+ALTER TABLE download_jobs ADD KEY download_jobs_media (media_id);
@@ -0,0 +1,6 @@
+-- Who added a jam track and when. NULL for tracks added before this was recorded
+ALTER TABLE jam_it ADD COLUMN added_by BIGINT UNSIGNED NULL;
+ALTER TABLE jam_it ADD COLUMN added_at DATETIME NULL;
+-- Only new rows get a date, existing ones keep NULL
+ALTER TABLE jam_it MODIFY added_at DATETIME NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE download_jobs ADD COLUMN requester_id BIGINT UNSIGNED NULL;
@@ -0,0 +1,11 @@
+-- Thumbs up (1) or down (-1) given to a jam track, one per user
+CREATE TABLE IF NOT EXISTS jam_ratings (
+    jam_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    rating TINYINT NOT NULL,
+    rated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (jam_id, user_id)
+);
+
+-- Recently played tracks are picked less often by the jam button
+ALTER TABLE jam_it ADD COLUMN last_played_at DATETIME NULL;
@@ -0,0 +1,10 @@
+-- Entrance themes. The table predates the migrations, this only creates it on new installs
+CREATE TABLE IF NOT EXISTS guild_user_boss_music (
+    user_id BIGINT UNSIGNED NOT NULL,
+    -- File name in the boss_music directory of the media store
+    song_name VARCHAR(255) NOT NULL,
+    PRIMARY KEY (user_id)
+);
+
+-- Themes play when members join a voice channel unless turned off
+ALTER TABLE guild_settings ADD COLUMN boss_music_enabled TINYINT(1) NOT NULL DEFAULT 1;
@@ -0,0 +1,13 @@
+-- Moderators have to approve new entrance themes before they play
+ALTER TABLE guild_settings ADD COLUMN boss_music_approval TINYINT(1) NOT NULL DEFAULT 0;
+
+-- Themes waiting for approval, one per member and guild. The file is in the boss_music directory
+CREATE TABLE IF NOT EXISTS boss_music_submissions (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    song_name VARCHAR(255) NOT NULL,
+    submitted_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY guild_user (guild_id, user_id)
+);
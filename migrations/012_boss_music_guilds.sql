@@ -0,0 +1,23 @@
+-- Themes per guild. guild_id 0 holds the default theme of a user, played where they have none
+CREATE TABLE IF NOT EXISTS boss_music_themes (
+    guild_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    -- File name in the boss_music directory of the media store
+    song_name VARCHAR(255) NOT NULL,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    PRIMARY KEY (guild_id, user_id)
+);
+
+-- Themes used to be global, they become the defaults of their users. Their files are copied
+-- from LEGACY_BOSS_MUSIC_DIR when the bot starts
+INSERT IGNORE INTO boss_music_themes (guild_id, user_id, song_name)
+SELECT 0, user_id, song_name FROM guild_user_boss_music;
+
+DROP TABLE guild_user_boss_music;
+
+-- Voice channels that play themes. No rows means every channel of the guild does
+CREATE TABLE IF NOT EXISTS boss_music_channels (
+    guild_id BIGINT UNSIGNED NOT NULL,
+    channel_id BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (guild_id, channel_id)
+);
@@ -0,0 +1,2 @@
+-- Guilds that opted in to keeping the last minutes of voice audio for /clip
+ALTER TABLE guild_settings ADD COLUMN clip_buffer_enabled TINYINT(1) NOT NULL DEFAULT 0;
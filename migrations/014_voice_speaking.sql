@@ -0,0 +1,12 @@
+-- Time spent in voice channels the bot listened to, and how much of it each member spoke
+CREATE TABLE IF NOT EXISTS voice_speaking_sessions (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    channel_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    joined_at DATETIME NOT NULL,
+    left_at DATETIME NOT NULL,
+    speaking_ms BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    PRIMARY KEY (id),
+    KEY guild_joined (guild_id, joined_at)
+);
@@ -0,0 +1,30 @@
+-- Every voice state change: joins, leaves, moves, mutes, deafens, streams and video
+CREATE TABLE IF NOT EXISTS voice_events (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    channel_id BIGINT UNSIGNED NULL,
+    -- The channel left by a leave or a move
+    old_channel_id BIGINT UNSIGNED NULL,
+    -- The new state of a toggle such as mute
+    enabled TINYINT(1) NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    KEY guild_user (guild_id, user_id, created_at)
+);
+
+-- Time in a voice channel built from joins and leaves, a move ends one session and starts another
+CREATE TABLE IF NOT EXISTS voice_sessions (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    channel_id BIGINT UNSIGNED NOT NULL,
+    joined_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    left_at DATETIME NULL,
+    PRIMARY KEY (id),
+    KEY guild_user (guild_id, user_id, joined_at)
+);
+
+-- Moves and disconnects are posted there
+ALTER TABLE guild_settings ADD COLUMN voice_log_channel_id BIGINT UNSIGNED NULL;
@@ -0,0 +1,11 @@
+-- Short clips of the guild soundboard. The file is in the soundboard directory of the media store
+CREATE TABLE IF NOT EXISTS soundboard_clips (
+    id BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+    guild_id BIGINT UNSIGNED NOT NULL,
+    name VARCHAR(32) NOT NULL,
+    file_name VARCHAR(255) NOT NULL,
+    added_by BIGINT UNSIGNED NOT NULL,
+    added_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (id),
+    UNIQUE KEY guild_name (guild_id, name)
+);
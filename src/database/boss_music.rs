@@ -0,0 +1,298 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BossMusicSubmission {
+    pub id: u64,
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub song_name: String,
+}
+
+type SubmissionRow = (u64, u64, u64, String);
+
+fn submission_from_row((id, guild_id, user_id, song_name): SubmissionRow) -> BossMusicSubmission {
+    BossMusicSubmission {
+        id,
+        guild_id,
+        user_id,
+        song_name,
+    }
+}
+
+/// The theme of a user in a guild. Guild 0 holds their default theme
+pub async fn get_boss_music_theme(pool: &Pool, guild_id: u64, user_id: u64) -> Option<String> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT song_name FROM boss_music_themes WHERE guild_id = ? AND user_id = ?",
+            (guild_id, user_id),
+        )
+        .await
+    {
+        Ok(song_name) => song_name,
+        Err(err) => {
+            println!("Error with get_boss_music_theme query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn set_boss_music_theme(pool: &Pool, guild_id: u64, user_id: u64, song_name: &str) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO boss_music_themes (guild_id, user_id, song_name) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE song_name = VALUES(song_name)",
+            (guild_id, user_id, song_name),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_boss_music_theme query: {}", err)
+        }
+    }
+}
+
+pub async fn remove_boss_music_theme(pool: &Pool, guild_id: u64, user_id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "DELETE FROM boss_music_themes WHERE guild_id = ? AND user_id = ?",
+            (guild_id, user_id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with remove_boss_music_theme query: {}", err)
+        }
+    }
+}
+
+/// The users with a theme of their own in the guild, defaults are left out
+pub async fn get_guild_boss_music_users(pool: &Pool, guild_id: u64) -> Vec<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT user_id FROM boss_music_themes WHERE guild_id = ? ORDER BY updated_at DESC",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(users) => users,
+        Err(err) => {
+            println!("Error with get_guild_boss_music_users query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Every file used as a theme, in any guild
+pub async fn get_all_boss_music_songs(pool: &Pool) -> Vec<String> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec("SELECT DISTINCT song_name FROM boss_music_themes", ())
+        .await
+    {
+        Ok(songs) => songs,
+        Err(err) => {
+            println!("Error with get_all_boss_music_songs query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// The voice channels that play themes. Empty means all of them
+pub async fn get_boss_music_channels(pool: &Pool, guild_id: u64) -> Vec<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT channel_id FROM boss_music_channels WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(channels) => channels,
+        Err(err) => {
+            println!("Error with get_boss_music_channels query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn add_boss_music_channel(pool: &Pool, guild_id: u64, channel_id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT IGNORE INTO boss_music_channels (guild_id, channel_id) VALUES (?, ?)",
+            (guild_id, channel_id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with add_boss_music_channel query: {}", err)
+        }
+    }
+}
+
+/// Returns true if the channel was in the list
+pub async fn remove_boss_music_channel(pool: &Pool, guild_id: u64, channel_id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "DELETE FROM boss_music_channels WHERE guild_id = ? AND channel_id = ?",
+            (guild_id, channel_id),
+        )
+        .await
+    {
+        Ok(_) => conn.affected_rows() > 0,
+        Err(err) => {
+            println!("Error with remove_boss_music_channel query: {}", err);
+            false
+        }
+    }
+}
+
+pub async fn clear_boss_music_channels(pool: &Pool, guild_id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "DELETE FROM boss_music_channels WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with clear_boss_music_channels query: {}", err)
+        }
+    }
+}
+
+/// Replaces the clip the member has waiting in the guild
+pub async fn add_boss_music_submission(pool: &Pool, guild_id: u64, user_id: u64, song_name: &str) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO boss_music_submissions (guild_id, user_id, song_name) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE song_name = VALUES(song_name), submitted_at = NOW()",
+            (guild_id, user_id, song_name),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with add_boss_music_submission query: {}", err)
+        }
+    }
+}
+
+pub async fn get_boss_music_submission(pool: &Pool, id: u64) -> Option<BossMusicSubmission> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, guild_id, user_id, song_name FROM boss_music_submissions WHERE id = ?",
+            (id,),
+        )
+        .await;
+
+    match result {
+        Ok(submission) => submission.map(submission_from_row),
+        Err(err) => {
+            println!("Error with get_boss_music_submission query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn get_user_boss_music_submission(
+    pool: &Pool,
+    guild_id: u64,
+    user_id: u64,
+) -> Option<BossMusicSubmission> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, guild_id, user_id, song_name FROM boss_music_submissions WHERE guild_id = ? AND user_id = ?",
+            (guild_id, user_id),
+        )
+        .await;
+
+    match result {
+        Ok(submission) => submission.map(submission_from_row),
+        Err(err) => {
+            println!("Error with get_user_boss_music_submission query: {}", err);
+            None
+        }
+    }
+}
+
+/// The oldest clip waiting for approval in the guild
+pub async fn get_next_boss_music_submission(
+    pool: &Pool,
+    guild_id: u64,
+) -> Option<BossMusicSubmission> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, guild_id, user_id, song_name FROM boss_music_submissions WHERE guild_id = ? ORDER BY submitted_at, id LIMIT 1",
+            (guild_id,),
+        )
+        .await;
+
+    match result {
+        Ok(submission) => submission.map(submission_from_row),
+        Err(err) => {
+            println!("Error with get_next_boss_music_submission query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn count_boss_music_submissions(pool: &Pool, guild_id: u64) -> u64 {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT COUNT(*) FROM boss_music_submissions WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(count) => count.unwrap_or(0),
+        Err(err) => {
+            println!("Error with count_boss_music_submissions query: {}", err);
+            0
+        }
+    }
+}
+
+pub async fn remove_boss_music_submission(pool: &Pool, id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop("DELETE FROM boss_music_submissions WHERE id = ?", (id,))
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with remove_boss_music_submission query: {}", err)
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::{get_conn_from_pool, media::MediaFile};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DownloadJob {
+    pub id: u64,
+    pub guild_id: u64,
+    pub requester_id: Option<u64>,
+    pub query: String,
+    /// One of pending, running, done or failed
+    pub status: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Adds a download for `query` unless the guild already has one. A failed job for the same query
+/// is reset so it gets another go. Returns true when there is new work for the workers
+pub async fn enqueue_download(
+    pool: &Pool,
+    guild_id: u64,
+    requester_id: Option<u64>,
+    query: &str,
+) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    // Assignments run left to right, status has to be updated last
+    match conn
+        .exec_drop(
+            "INSERT INTO download_jobs (guild_id, requester_id, query) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE attempts = IF(status = 'failed', 0, attempts), error = IF(status = 'failed', NULL, error), next_attempt_at = IF(status = 'failed', NOW(), next_attempt_at), status = IF(status = 'failed', 'pending', status)",
+            (guild_id, requester_id, query),
+        )
+        .await
+    {
+        Ok(_) => conn.affected_rows() > 0,
+        Err(err) => {
+            println!("Error with enqueue_download query: {}", err);
+            false
+        }
+    }
+}
+
+/// Marks the oldest due job as running and returns it
+pub async fn claim_next_download(pool: &Pool) -> Option<DownloadJob> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    loop {
+        let id: Option<u64> = match conn
+            .query_first(
+                "SELECT id FROM download_jobs WHERE status = 'pending' AND next_attempt_at <= NOW() ORDER BY id LIMIT 1",
+            )
+            .await
+        {
+            Ok(id) => id,
+            Err(err) => {
+                println!("Error with claim_next_download query: {}", err);
+                return None;
+            }
+        };
+        let id = id?;
+
+        match conn
+            .exec_drop(
+                "UPDATE download_jobs SET status = 'running', attempts = attempts + 1 WHERE id = ? AND status = 'pending'",
+                (id,),
+            )
+            .await
+        {
+            Ok(_) => {}
+            Err(err) => {
+                println!("Error with claim_next_download query: {}", err);
+                return None;
+            }
+        }
+
+        // Another worker got to it first
+        if conn.affected_rows() == 0 {
+            continue;
+        }
+
+        return get_download(pool, id).await;
+    }
+}
+
+pub async fn get_download(pool: &Pool, id: u64) -> Option<DownloadJob> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, guild_id, requester_id, query, status, attempts, error, title FROM download_jobs WHERE id = ?",
+            (id,),
+        )
+        .await;
+
+    match result {
+        Ok(job) => job.map(
+            |(id, guild_id, requester_id, query, status, attempts, error, title)| DownloadJob {
+                id,
+                guild_id,
+                requester_id,
+                query,
+                status,
+                attempts,
+                error,
+                title,
+            },
+        ),
+        Err(err) => {
+            println!("Error with get_download query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn finish_download(pool: &Pool, id: u64, media: &MediaFile) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE download_jobs SET status = 'done', error = NULL, title = ?, ext = ?, media_id = ? WHERE id = ?",
+            (&media.title, &media.ext, media.id, id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with finish_download query: {}", err)
+        }
+    }
+}
+
+/// Puts a job back in the queue, it will not be picked up for `delay_secs`
+pub async fn retry_download(pool: &Pool, id: u64, error: &str, delay_secs: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE download_jobs SET status = 'pending', error = ?, next_attempt_at = NOW() + INTERVAL ? SECOND WHERE id = ?",
+            (error, delay_secs, id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with retry_download query: {}", err)
+        }
+    }
+}
+
+pub async fn fail_download(pool: &Pool, id: u64, error: &str) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE download_jobs SET status = 'failed', error = ? WHERE id = ?",
+            (error, id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with fail_download query: {}", err)
+        }
+    }
+}
+
+/// Jobs left running by a previous process will never finish, queue them again
+pub async fn reset_running_downloads(pool: &Pool) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .query_drop("UPDATE download_jobs SET status = 'pending' WHERE status = 'running'")
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with reset_running_downloads query: {}", err)
+        }
+    }
+}
+
+/// Returns the jobs of a guild, most recently updated first
+pub async fn get_downloads(pool: &Pool, guild_id: u64, limit: u64) -> Vec<DownloadJob> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_map(
+            "SELECT id, guild_id, requester_id, query, status, attempts, error, title FROM download_jobs WHERE guild_id = ? ORDER BY updated_at DESC, id DESC LIMIT ?",
+            (guild_id, limit),
+            |(id, guild_id, requester_id, query, status, attempts, error, title)| DownloadJob {
+                id,
+                guild_id,
+                requester_id,
+                query,
+                status,
+                attempts,
+                error,
+                title,
+            },
+        )
+        .await;
+
+    match result {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            println!("Error with get_downloads query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns (status, count) for the jobs of a guild
+pub async fn count_downloads_by_status(pool: &Pool, guild_id: u64) -> Vec<(String, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT status, COUNT(*) FROM download_jobs WHERE guild_id = ? GROUP BY status",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with count_downloads_by_status query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// The file a finished download of `query` produced, if it is still in the store
+pub async fn get_downloaded_media_id(pool: &Pool, guild_id: u64, query: &str) -> Option<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT media_id FROM download_jobs WHERE guild_id = ? AND query = ? AND status = 'done' AND media_id IS NOT NULL",
+            (guild_id, query),
+        )
+        .await
+    {
+        Ok(media_id) => media_id,
+        Err(err) => {
+            println!("Error with get_downloaded_media_id query: {}", err);
+            None
+        }
+    }
+}
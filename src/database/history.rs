@@ -0,0 +1,287 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PlayHistory {
+    pub id: u64,
+    pub requester_id: Option<u64>,
+    pub title: String,
+    pub uri: String,
+    /// Unix timestamp (seconds)
+    pub started_at: i64,
+    pub skipped: Option<bool>,
+}
+
+/// Records a track that just started playing. Returns the id of the new row
+pub async fn add_play_history(
+    pool: &Pool,
+    guild_id: u64,
+    requester_id: Option<u64>,
+    title: &str,
+    uri: &str,
+    length_ms: u64,
+) -> Option<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO play_history (guild_id, requester_id, title, uri, length_ms) VALUES (?, ?, ?, ?, ?)",
+            (guild_id, requester_id, title, uri, length_ms),
+        )
+        .await
+    {
+        Ok(_) => conn.last_insert_id(),
+        Err(err) => {
+            println!("Error with add_play_history query: {}", err);
+            None
+        }
+    }
+}
+
+/// Marks a history entry as finished. The played duration is capped to the length of the track
+pub async fn finish_play_history(pool: &Pool, id: u64, played_ms: u64, skipped: bool) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE play_history SET played_ms = IF(length_ms = 0, ?, LEAST(?, length_ms)), skipped = ? WHERE id = ?",
+            (played_ms, played_ms, skipped, id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with finish_play_history query: {}", err)
+        }
+    }
+}
+
+/// Returns the entries of a guild, newest first
+pub async fn get_play_history(
+    pool: &Pool,
+    guild_id: u64,
+    offset: u64,
+    limit: u64,
+) -> Vec<PlayHistory> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_map(
+            "SELECT id, requester_id, title, uri, UNIX_TIMESTAMP(started_at), skipped FROM play_history WHERE guild_id = ? ORDER BY id DESC LIMIT ? OFFSET ?",
+            (guild_id, limit, offset),
+            |(id, requester_id, title, uri, started_at, skipped)| PlayHistory {
+                id,
+                requester_id,
+                title,
+                uri,
+                started_at,
+                skipped,
+            },
+        )
+        .await;
+
+    match result {
+        Ok(history) => history,
+        Err(err) => {
+            println!("Error with get_play_history query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn count_play_history(pool: &Pool, guild_id: u64) -> u64 {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT COUNT(*) FROM play_history WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(count) => count.unwrap_or(0),
+        Err(err) => {
+            println!("Error with count_play_history query: {}", err);
+            0
+        }
+    }
+}
+
+pub async fn get_play_history_entry(pool: &Pool, guild_id: u64, id: u64) -> Option<PlayHistory> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, requester_id, title, uri, UNIX_TIMESTAMP(started_at), skipped FROM play_history WHERE guild_id = ? AND id = ?",
+            (guild_id, id),
+        )
+        .await;
+
+    match result {
+        Ok(entry) => entry.map(
+            |(id, requester_id, title, uri, started_at, skipped)| PlayHistory {
+                id,
+                requester_id,
+                title,
+                uri,
+                started_at,
+                skipped,
+            },
+        ),
+        Err(err) => {
+            println!("Error with get_play_history_entry query: {}", err);
+            None
+        }
+    }
+}
+
+/// Distinct (title, uri) pairs of previously played tracks whose title contains `partial`.
+/// Most played first
+pub async fn search_play_history_titles(
+    pool: &Pool,
+    guild_id: u64,
+    partial: &str,
+    limit: u64,
+) -> Vec<(String, String)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec(
+            "SELECT title, uri FROM play_history WHERE guild_id = ? AND title LIKE CONCAT('%', ?, '%') GROUP BY title, uri ORDER BY COUNT(*) DESC LIMIT ?",
+            (guild_id, partial, limit),
+        )
+        .await;
+
+    match result {
+        Ok(titles) => titles,
+        Err(err) => {
+            println!("Error with search_play_history_titles query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+// The `since` arguments below are unix timestamps. 0 means all-time
+pub async fn get_top_tracks(
+    pool: &Pool,
+    guild_id: u64,
+    since: i64,
+    limit: u64,
+) -> Vec<(String, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT title, CAST(COUNT(*) AS UNSIGNED) AS plays FROM play_history WHERE guild_id = ? AND started_at >= FROM_UNIXTIME(?) GROUP BY title, uri ORDER BY plays DESC LIMIT ?",
+            (guild_id, since, limit),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with get_top_tracks query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn get_top_requesters(
+    pool: &Pool,
+    guild_id: u64,
+    since: i64,
+    limit: u64,
+) -> Vec<(u64, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT requester_id, CAST(COUNT(*) AS UNSIGNED) AS plays FROM play_history WHERE guild_id = ? AND started_at >= FROM_UNIXTIME(?) AND requester_id IS NOT NULL GROUP BY requester_id ORDER BY plays DESC LIMIT ?",
+            (guild_id, since, limit),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with get_top_requesters query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn get_total_played_ms(pool: &Pool, guild_id: u64, since: i64) -> u64 {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT CAST(COALESCE(SUM(played_ms), 0) AS UNSIGNED) FROM play_history WHERE guild_id = ? AND started_at >= FROM_UNIXTIME(?)",
+            (guild_id, since),
+        )
+        .await
+    {
+        Ok(total) => total.unwrap_or(0),
+        Err(err) => {
+            println!("Error with get_total_played_ms query: {}", err);
+            0
+        }
+    }
+}
+
+pub async fn get_most_skipped(
+    pool: &Pool,
+    guild_id: u64,
+    since: i64,
+    limit: u64,
+) -> Vec<(String, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT title, CAST(SUM(skipped) AS UNSIGNED) AS skips FROM play_history WHERE guild_id = ? AND started_at >= FROM_UNIXTIME(?) AND skipped = 1 GROUP BY title, uri ORDER BY skips DESC LIMIT ?",
+            (guild_id, since, limit),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with get_most_skipped query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Picks a random track for autoplay out of the guild's play history and `jam_it` library.
+/// Tracks are weighted by how many times they were played to the end, plays that never finished
+/// count for nothing, and anything from the last `avoid_recent` plays is left out. Returns (title, uri), the uri is empty for `jam_it`
+/// tracks that were never played through lavalink
+pub async fn pick_radio_track(
+    pool: &Pool,
+    guild_id: u64,
+    avoid_recent: u64,
+) -> Option<(String, String)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    // -LOG(1 - RAND()) / weight is a weighted random key, the smallest one wins
+    match conn
+        .exec_first(
+            "SELECT title, uri FROM (
+                SELECT title, MAX(uri) AS uri, COALESCE(SUM(skipped = 0), 0) + 1 AS weight FROM play_history WHERE guild_id = ? AND uri <> '' GROUP BY title
+                UNION ALL
+                SELECT audio_name AS title, '' AS uri, 1 AS weight FROM jam_it WHERE guild_id = ?
+            ) candidates
+            WHERE title NOT IN (
+                SELECT title FROM (SELECT title FROM play_history WHERE guild_id = ? ORDER BY id DESC LIMIT ?) recent
+            )
+            ORDER BY -LOG(1 - RAND()) / weight LIMIT 1",
+            (guild_id, guild_id, guild_id, avoid_recent),
+        )
+        .await
+    {
+        Ok(track) => track,
+        Err(err) => {
+            println!("Error with pick_radio_track query: {}", err);
+            None
+        }
+    }
+}
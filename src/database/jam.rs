@@ -0,0 +1,290 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JamTrack {
+    pub id: u64,
+    pub audio_name: String,
+    pub ext: String,
+    /// `None` for tracks downloaded before the media store
+    pub media_id: Option<u64>,
+    pub added_by: Option<u64>,
+    /// Unix timestamp (seconds)
+    pub added_at: Option<i64>,
+}
+
+type JamRow = (u64, String, String, Option<u64>, Option<u64>, Option<i64>);
+
+fn jam_from_row((id, audio_name, ext, media_id, added_by, added_at): JamRow) -> JamTrack {
+    JamTrack {
+        id,
+        audio_name,
+        ext,
+        media_id,
+        added_by,
+        added_at,
+    }
+}
+
+/// Picks a random track. Each user rating doubles or halves the odds (up to 3 times), tracks played
+/// in the last day are less likely and the last `avoid_recent` played are left out
+pub async fn get_weighted_jam_track(
+    pool: &Pool,
+    guild_id: u64,
+    avoid_recent: u64,
+) -> Option<JamTrack> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    // Smallest -ln(U) / weight wins, which picks each row proportionally to its weight
+    let result = conn
+        .exec_first(
+            "SELECT j.id, j.audio_name, j.ext, j.media_id, j.added_by, UNIX_TIMESTAMP(j.added_at)
+            FROM jam_it j
+            LEFT JOIN (SELECT jam_id, SUM(rating) AS score FROM jam_ratings GROUP BY jam_id) r ON r.jam_id = j.id
+            WHERE j.guild_id = ? AND j.id NOT IN (
+                SELECT id FROM (
+                    SELECT id FROM jam_it WHERE guild_id = ? AND last_played_at IS NOT NULL ORDER BY last_played_at DESC LIMIT ?
+                ) recent
+            )
+            ORDER BY -LOG(1 - RAND()) / (
+                POW(2, GREATEST(-3, LEAST(3, COALESCE(r.score, 0))))
+                * GREATEST(0.1, LEAST(1, COALESCE(TIMESTAMPDIFF(HOUR, j.last_played_at, NOW()), 24) / 24))
+            )
+            LIMIT 1",
+            (guild_id, guild_id, avoid_recent),
+        )
+        .await;
+
+    match result {
+        Ok(track) => track.map(jam_from_row),
+        Err(err) => {
+            println!("Error with get_weighted_jam_track query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn mark_jam_played(pool: &Pool, id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE jam_it SET last_played_at = NOW() WHERE id = ?",
+            (id,),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with mark_jam_played query: {}", err)
+        }
+    }
+}
+
+/// Records the rating of a user, replacing their previous one. Returns false if the track is not
+/// in the guild's library
+pub async fn rate_jam_track(pool: &Pool, guild_id: u64, id: u64, user_id: u64, rating: i8) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let exists: Option<u64> = match conn
+        .exec_first(
+            "SELECT id FROM jam_it WHERE id = ? AND guild_id = ?",
+            (id, guild_id),
+        )
+        .await
+    {
+        Ok(exists) => exists,
+        Err(err) => {
+            println!("Error with rate_jam_track query: {}", err);
+            return false;
+        }
+    };
+    if exists.is_none() {
+        return false;
+    }
+
+    match conn
+        .exec_drop(
+            "INSERT INTO jam_ratings (jam_id, user_id, rating) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE rating = VALUES(rating)",
+            (id, user_id, rating),
+        )
+        .await
+    {
+        Ok(_) => true,
+        Err(err) => {
+            println!("Error with rate_jam_track query: {}", err);
+            false
+        }
+    }
+}
+
+/// Returns (title, score, votes) of the best rated tracks of a guild
+pub async fn get_top_rated_jam_tracks(
+    pool: &Pool,
+    guild_id: u64,
+    limit: u64,
+) -> Vec<(String, i64, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec(
+            "SELECT j.audio_name, CAST(SUM(r.rating) AS SIGNED) AS score, COUNT(*) AS votes FROM jam_it j JOIN jam_ratings r ON r.jam_id = j.id WHERE j.guild_id = ? GROUP BY j.id, j.audio_name HAVING score > 0 ORDER BY score DESC, votes DESC LIMIT ?",
+            (guild_id, limit),
+        )
+        .await;
+
+    match result {
+        Ok(tracks) => tracks,
+        Err(err) => {
+            println!("Error with get_top_rated_jam_tracks query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn get_jam_track(pool: &Pool, guild_id: u64, audio_name: &str) -> Option<JamTrack> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, audio_name, ext, media_id, added_by, UNIX_TIMESTAMP(added_at) FROM jam_it WHERE guild_id = ? AND audio_name = ?",
+            (guild_id, audio_name),
+        )
+        .await;
+
+    match result {
+        Ok(track) => track.map(jam_from_row),
+        Err(err) => {
+            println!("Error with get_jam_track query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn get_jam_track_by_id(pool: &Pool, guild_id: u64, id: u64) -> Option<JamTrack> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, audio_name, ext, media_id, added_by, UNIX_TIMESTAMP(added_at) FROM jam_it WHERE guild_id = ? AND id = ?",
+            (guild_id, id),
+        )
+        .await;
+
+    match result {
+        Ok(track) => track.map(jam_from_row),
+        Err(err) => {
+            println!("Error with get_jam_track_by_id query: {}", err);
+            None
+        }
+    }
+}
+
+/// Returns the tracks of a guild whose title contains `search`, sorted by title
+pub async fn get_jam_tracks(
+    pool: &Pool,
+    guild_id: u64,
+    search: &str,
+    offset: u64,
+    limit: u64,
+) -> Vec<JamTrack> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_map(
+            "SELECT id, audio_name, ext, media_id, added_by, UNIX_TIMESTAMP(added_at) FROM jam_it WHERE guild_id = ? AND audio_name LIKE CONCAT('%', ?, '%') ORDER BY audio_name LIMIT ? OFFSET ?",
+            (guild_id, search, limit, offset),
+            jam_from_row,
+        )
+        .await;
+
+    match result {
+        Ok(tracks) => tracks,
+        Err(err) => {
+            println!("Error with get_jam_tracks query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn count_jam_tracks(pool: &Pool, guild_id: u64, search: &str) -> u64 {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT COUNT(*) FROM jam_it WHERE guild_id = ? AND audio_name LIKE CONCAT('%', ?, '%')",
+            (guild_id, search),
+        )
+        .await
+    {
+        Ok(count) => count.unwrap_or(0),
+        Err(err) => {
+            println!("Error with count_jam_tracks query: {}", err);
+            0
+        }
+    }
+}
+
+pub async fn search_jam_titles(
+    pool: &Pool,
+    guild_id: u64,
+    partial: &str,
+    limit: u64,
+) -> Vec<String> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec(
+            "SELECT audio_name FROM jam_it WHERE guild_id = ? AND audio_name LIKE CONCAT('%', ?, '%') ORDER BY audio_name LIMIT ?",
+            (guild_id, partial, limit),
+        )
+        .await;
+
+    match result {
+        Ok(titles) => titles,
+        Err(err) => {
+            println!("Error with search_jam_titles query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns true if a track was removed
+pub async fn remove_jam_track(pool: &Pool, guild_id: u64, audio_name: &str) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "DELETE j, r FROM jam_it j LEFT JOIN jam_ratings r ON r.jam_id = j.id WHERE j.guild_id = ? AND j.audio_name = ?",
+            (guild_id, audio_name),
+        )
+        .await
+    {
+        Ok(_) => conn.affected_rows() > 0,
+        Err(err) => {
+            println!("Error with remove_jam_track query: {}", err);
+            false
+        }
+    }
+}
+
+/// Returns true if the track was removed
+pub async fn remove_jam_track_by_id(pool: &Pool, guild_id: u64, id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "DELETE j, r FROM jam_it j LEFT JOIN jam_ratings r ON r.jam_id = j.id WHERE j.guild_id = ? AND j.id = ?",
+            (guild_id, id),
+        )
+        .await
+    {
+        Ok(_) => conn.affected_rows() > 0,
+        Err(err) => {
+            println!("Error with remove_jam_track_by_id query: {}", err);
+            false
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MediaFile {
+    pub id: u64,
+    pub source_id: String,
+    pub source_url: String,
+    pub title: String,
+    pub duration_ms: u64,
+    pub codec: String,
+    pub ext: String,
+    pub size_bytes: u64,
+    pub checksum: String,
+}
+
+type MediaRow = (
+    u64,
+    String,
+    String,
+    String,
+    u64,
+    String,
+    String,
+    u64,
+    String,
+);
+
+fn media_from_row(
+    (id, source_id, source_url, title, duration_ms, codec, ext, size_bytes, checksum): MediaRow,
+) -> MediaFile {
+    MediaFile {
+        id,
+        source_id,
+        source_url,
+        title,
+        duration_ms,
+        codec,
+        ext,
+        size_bytes,
+        checksum,
+    }
+}
+
+/// Records a file of the media store. Returns the id of the row, the existing one when a file
+/// with the same checksum is already known
+pub async fn add_media_file(pool: &Pool, media: &MediaFile) -> Option<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO media_files (source_id, source_url, title, duration_ms, codec, ext, size_bytes, checksum) VALUES (?, ?, ?, ?, ?, ?, ?, ?) ON DUPLICATE KEY UPDATE id = LAST_INSERT_ID(id), last_used_at = NOW()",
+            (
+                &media.source_id,
+                &media.source_url,
+                &media.title,
+                media.duration_ms,
+                &media.codec,
+                &media.ext,
+                media.size_bytes,
+                &media.checksum,
+            ),
+        )
+        .await
+    {
+        Ok(_) => conn.last_insert_id(),
+        Err(err) => {
+            println!("Error with add_media_file query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn get_media_file(pool: &Pool, id: u64) -> Option<MediaFile> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_first(
+            "SELECT id, source_id, source_url, title, duration_ms, codec, ext, size_bytes, checksum FROM media_files WHERE id = ?",
+            (id,),
+        )
+        .await;
+
+    match result {
+        Ok(media) => media.map(media_from_row),
+        Err(err) => {
+            println!("Error with get_media_file query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn touch_media_file(pool: &Pool, id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE media_files SET last_used_at = NOW() WHERE id = ?",
+            (id,),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with touch_media_file query: {}", err)
+        }
+    }
+}
+
+/// Every file of the store, used by the garbage collector
+pub async fn get_all_media_files(pool: &Pool) -> Vec<MediaFile> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .query_map(
+            "SELECT id, source_id, source_url, title, duration_ms, codec, ext, size_bytes, checksum FROM media_files",
+            media_from_row,
+        )
+        .await;
+
+    match result {
+        Ok(media) => media,
+        Err(err) => {
+            println!("Error with get_all_media_files query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns (files, bytes) of the whole store, or of the files a guild downloaded or keeps in its
+/// jam library
+pub async fn get_media_usage(pool: &Pool, guild_id: Option<u64>) -> (u64, u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = match guild_id {
+        Some(guild_id) => {
+            conn.exec_first(
+                "SELECT COUNT(*), CAST(COALESCE(SUM(size_bytes), 0) AS UNSIGNED) FROM media_files WHERE id IN (SELECT media_id FROM download_jobs WHERE guild_id = ? UNION SELECT media_id FROM jam_it WHERE guild_id = ?)",
+                (guild_id, guild_id),
+            )
+            .await
+        }
+        None => {
+            conn.query_first(
+                "SELECT COUNT(*), CAST(COALESCE(SUM(size_bytes), 0) AS UNSIGNED) FROM media_files",
+            )
+            .await
+        }
+    };
+
+    match result {
+        Ok(usage) => usage.unwrap_or((0, 0)),
+        Err(err) => {
+            println!("Error with get_media_usage query: {}", err);
+            (0, 0)
+        }
+    }
+}
+
+/// Files no jam library uses, least recently used first. With a guild, only the ones it downloaded
+pub async fn get_evictable_media(pool: &Pool, guild_id: Option<u64>, limit: u64) -> Vec<MediaFile> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    // There are no stored playlists, the jam library is the only thing keeping a file
+    let result = match guild_id {
+        Some(guild_id) => {
+            conn.exec_map(
+                "SELECT id, source_id, source_url, title, duration_ms, codec, ext, size_bytes, checksum FROM media_files WHERE id IN (SELECT media_id FROM download_jobs WHERE guild_id = ?) AND id NOT IN (SELECT media_id FROM jam_it WHERE media_id IS NOT NULL) ORDER BY last_used_at LIMIT ?",
+                (guild_id, limit),
+                media_from_row,
+            )
+            .await
+        }
+        None => {
+            conn.exec_map(
+                "SELECT id, source_id, source_url, title, duration_ms, codec, ext, size_bytes, checksum FROM media_files WHERE id NOT IN (SELECT media_id FROM jam_it WHERE media_id IS NOT NULL) ORDER BY last_used_at LIMIT ?",
+                (limit,),
+                media_from_row,
+            )
+            .await
+        }
+    };
+
+    match result {
+        Ok(media) => media,
+        Err(err) => {
+            println!("Error with get_evictable_media query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Forgets a file. Jam tracks using it go too, download jobs for it are dropped so the same
+/// query can be downloaded again
+/// Returns false if the rows could not all be deleted
+pub async fn delete_media_file(pool: &Pool, id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    for query in [
+        "DELETE j, r FROM jam_it j LEFT JOIN jam_ratings r ON r.jam_id = j.id WHERE j.media_id = ?",
+        "DELETE FROM download_jobs WHERE media_id = ?",
+        "DELETE FROM media_files WHERE id = ?",
+    ] {
+        match conn.exec_drop(query, (id,)).await {
+            Ok(_) => {}
+            Err(err) => {
+                println!("Error with delete_media_file query: {}", err);
+                return false;
+            }
+        }
+    }
+
+    true
+}
@@ -22,12 +22,19 @@ pub async fn get_conn_from_ctx(ctx: &Context) -> Conn {
     conn
 }
 
+pub mod boss_music;
 pub mod channels;
+pub mod downloads;
 pub mod emojis;
 pub mod guilds;
+pub mod history;
 pub mod invites;
+pub mod jam;
+pub mod media;
 pub mod messages;
 pub mod roles;
+pub mod settings;
+pub mod soundboard;
 pub mod text_channel;
 pub mod users;
 pub mod voice;
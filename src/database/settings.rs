@@ -0,0 +1,264 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
+
+pub async fn set_stats_channel(pool: &Pool, guild_id: u64, channel_id: Option<u64>) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, stats_channel_id) VALUES (?, ?) ON DUPLICATE KEY UPDATE stats_channel_id = VALUES(stats_channel_id)",
+            (guild_id, channel_id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_stats_channel query: {}", err)
+        }
+    }
+}
+
+/// Returns (guild_id, channel_id) of the guilds whose weekly summary has not been sent in the last 7 days
+pub async fn get_due_stats_summaries(pool: &Pool) -> Vec<(u64, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .query("SELECT guild_id, stats_channel_id FROM guild_settings WHERE stats_channel_id IS NOT NULL AND (stats_summary_sent_at IS NULL OR stats_summary_sent_at <= NOW() - INTERVAL 7 DAY)")
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with get_due_stats_summaries query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn mark_stats_summary_sent(pool: &Pool, guild_id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "UPDATE guild_settings SET stats_summary_sent_at = NOW() WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with mark_stats_summary_sent query: {}", err)
+        }
+    }
+}
+
+pub async fn get_autoplay(pool: &Pool, guild_id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT autoplay FROM guild_settings WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(autoplay) => autoplay.unwrap_or(false),
+        Err(err) => {
+            println!("Error with get_autoplay query: {}", err);
+            false
+        }
+    }
+}
+
+pub async fn set_autoplay(pool: &Pool, guild_id: u64, autoplay: bool) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, autoplay) VALUES (?, ?) ON DUPLICATE KEY UPDATE autoplay = VALUES(autoplay)",
+            (guild_id, autoplay),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_autoplay query: {}", err)
+        }
+    }
+}
+
+pub async fn get_dj_role(pool: &Pool, guild_id: u64) -> Option<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first::<Option<u64>, _, _>(
+            "SELECT dj_role_id FROM guild_settings WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(role_id) => role_id.flatten(),
+        Err(err) => {
+            println!("Error with get_dj_role query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn set_dj_role(pool: &Pool, guild_id: u64, role_id: Option<u64>) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, dj_role_id) VALUES (?, ?) ON DUPLICATE KEY UPDATE dj_role_id = VALUES(dj_role_id)",
+            (guild_id, role_id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_dj_role query: {}", err)
+        }
+    }
+}
+
+pub async fn get_boss_music_enabled(pool: &Pool, guild_id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT boss_music_enabled FROM guild_settings WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(enabled) => enabled.unwrap_or(true),
+        Err(err) => {
+            println!("Error with get_boss_music_enabled query: {}", err);
+            false
+        }
+    }
+}
+
+pub async fn set_boss_music_enabled(pool: &Pool, guild_id: u64, enabled: bool) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, boss_music_enabled) VALUES (?, ?) ON DUPLICATE KEY UPDATE boss_music_enabled = VALUES(boss_music_enabled)",
+            (guild_id, enabled),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_boss_music_enabled query: {}", err)
+        }
+    }
+}
+
+pub async fn get_boss_music_approval(pool: &Pool, guild_id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT boss_music_approval FROM guild_settings WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(approval) => approval.unwrap_or(false),
+        Err(err) => {
+            println!("Error with get_boss_music_approval query: {}", err);
+            false
+        }
+    }
+}
+
+pub async fn set_boss_music_approval(pool: &Pool, guild_id: u64, approval: bool) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, boss_music_approval) VALUES (?, ?) ON DUPLICATE KEY UPDATE boss_music_approval = VALUES(boss_music_approval)",
+            (guild_id, approval),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_boss_music_approval query: {}", err)
+        }
+    }
+}
+
+pub async fn get_clip_buffer_enabled(pool: &Pool, guild_id: u64) -> bool {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT clip_buffer_enabled FROM guild_settings WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(enabled) => enabled.unwrap_or(false),
+        Err(err) => {
+            println!("Error with get_clip_buffer_enabled query: {}", err);
+            false
+        }
+    }
+}
+
+pub async fn set_clip_buffer_enabled(pool: &Pool, guild_id: u64, enabled: bool) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, clip_buffer_enabled) VALUES (?, ?) ON DUPLICATE KEY UPDATE clip_buffer_enabled = VALUES(clip_buffer_enabled)",
+            (guild_id, enabled),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_clip_buffer_enabled query: {}", err)
+        }
+    }
+}
+
+pub async fn get_voice_log_channel(pool: &Pool, guild_id: u64) -> Option<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first::<Option<u64>, _, _>(
+            "SELECT voice_log_channel_id FROM guild_settings WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(channel_id) => channel_id.flatten(),
+        Err(err) => {
+            println!("Error with get_voice_log_channel query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn set_voice_log_channel(pool: &Pool, guild_id: u64, channel_id: Option<u64>) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO guild_settings (guild_id, voice_log_channel_id) VALUES (?, ?) ON DUPLICATE KEY UPDATE voice_log_channel_id = VALUES(voice_log_channel_id)",
+            (guild_id, channel_id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_voice_log_channel query: {}", err)
+        }
+    }
+}
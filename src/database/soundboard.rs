@@ -0,0 +1,144 @@
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SoundboardClip {
+    pub id: u64,
+    pub name: String,
+    pub file_name: String,
+}
+
+type SoundboardClipRow = (u64, String, String);
+
+fn clip_from_row((id, name, file_name): SoundboardClipRow) -> SoundboardClip {
+    SoundboardClip {
+        id,
+        name,
+        file_name,
+    }
+}
+
+/// Adds a clip or replaces the file of the clip with the same name. Returns the file it replaced
+pub async fn set_soundboard_clip(
+    pool: &Pool,
+    guild_id: u64,
+    name: &str,
+    file_name: &str,
+    added_by: u64,
+) -> Option<String> {
+    let previous = get_soundboard_clip_by_name(pool, guild_id, name)
+        .await
+        .map(|clip| clip.file_name);
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO soundboard_clips (guild_id, name, file_name, added_by) VALUES (?, ?, ?, ?) ON DUPLICATE KEY UPDATE file_name = VALUES(file_name), added_by = VALUES(added_by), added_at = NOW()",
+            (guild_id, name, file_name, added_by),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with set_soundboard_clip query: {}", err)
+        }
+    }
+
+    previous
+}
+
+pub async fn get_soundboard_clip(pool: &Pool, guild_id: u64, id: u64) -> Option<SoundboardClip> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT id, name, file_name FROM soundboard_clips WHERE guild_id = ? AND id = ?",
+            (guild_id, id),
+        )
+        .await
+    {
+        Ok(row) => row.map(clip_from_row),
+        Err(err) => {
+            println!("Error with get_soundboard_clip query: {}", err);
+            None
+        }
+    }
+}
+
+pub async fn get_soundboard_clip_by_name(
+    pool: &Pool,
+    guild_id: u64,
+    name: &str,
+) -> Option<SoundboardClip> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT id, name, file_name FROM soundboard_clips WHERE guild_id = ? AND name = ?",
+            (guild_id, name),
+        )
+        .await
+    {
+        Ok(row) => row.map(clip_from_row),
+        Err(err) => {
+            println!("Error with get_soundboard_clip_by_name query: {}", err);
+            None
+        }
+    }
+}
+
+/// The clips of a guild in the order they were added
+pub async fn get_soundboard_clips(pool: &Pool, guild_id: u64, limit: u64) -> Vec<SoundboardClip> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT id, name, file_name FROM soundboard_clips WHERE guild_id = ? ORDER BY id LIMIT ?",
+            (guild_id, limit),
+        )
+        .await
+    {
+        Ok(rows) => rows.into_iter().map(clip_from_row).collect(),
+        Err(err) => {
+            println!("Error with get_soundboard_clips query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+pub async fn count_soundboard_clips(pool: &Pool, guild_id: u64) -> u64 {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT CAST(COUNT(*) AS UNSIGNED) FROM soundboard_clips WHERE guild_id = ?",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(count) => count.unwrap_or(0),
+        Err(err) => {
+            println!("Error with count_soundboard_clips query: {}", err);
+            0
+        }
+    }
+}
+
+pub async fn remove_soundboard_clip(pool: &Pool, guild_id: u64, id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "DELETE FROM soundboard_clips WHERE guild_id = ? AND id = ?",
+            (guild_id, id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with remove_soundboard_clip query: {}", err)
+        }
+    }
+}
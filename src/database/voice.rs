@@ -1,42 +1,262 @@
-use crate::database::get_conn_from_ctx;
-use mysql_async::prelude::Queryable;
-use serenity::client::Context;
+use mysql_async::prelude::*;
+use mysql_async::Pool;
+
+use crate::database::get_conn_from_pool;
 
 pub async fn update_voice_channel_user_limit() {}
 
 pub async fn update_voice_channel_user_bitrate() {}
 
-pub async fn add_voice_state() {}
 #[derive(Debug, PartialEq, Eq, Clone)]
-pub struct UserBossMusic {
-    song_name: Option<String>,
+pub struct VoiceEvent {
+    pub kind: String,
+    pub channel_id: Option<u64>,
+    pub old_channel_id: Option<u64>,
+    pub enabled: Option<bool>,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
 }
 
-pub async fn get_user_boss_music(ctx: &Context, user_id: u64) -> Option<String> {
-    let mut conn = get_conn_from_ctx(ctx).await;
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VoiceSession {
+    pub channel_id: u64,
+    /// Unix timestamps (seconds), `left_at` is `None` while the member is still there
+    pub joined_at: i64,
+    pub left_at: Option<i64>,
+}
 
-    let sql = format!(
-        "SELECT song_name FROM guild_user_boss_music WHERE user_id = '{}'",
-        user_id
-    );
+/// Records one change of the voice state of a member
+pub async fn add_voice_state(
+    pool: &Pool,
+    guild_id: u64,
+    user_id: u64,
+    kind: &str,
+    channel_id: Option<u64>,
+    old_channel_id: Option<u64>,
+    enabled: Option<bool>,
+) {
+    let mut conn = get_conn_from_pool(pool).await;
 
-    let result: Option<String> = match conn.query_first(sql).await {
-        Ok(result) => result,
-        Err(_) => None,
-    };
+    match conn
+        .exec_drop(
+            "INSERT INTO voice_events (guild_id, user_id, kind, channel_id, old_channel_id, enabled) VALUES (?, ?, ?, ?, ?, ?)",
+            (guild_id, user_id, kind, channel_id, old_channel_id, enabled),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with add_voice_state query: {}", err)
+        }
+    }
+}
+
+/// Returns the latest voice events of a member, newest first
+pub async fn get_voice_events(
+    pool: &Pool,
+    guild_id: u64,
+    user_id: u64,
+    limit: u64,
+) -> Vec<VoiceEvent> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_map(
+            "SELECT kind, channel_id, old_channel_id, enabled, UNIX_TIMESTAMP(created_at) FROM voice_events WHERE guild_id = ? AND user_id = ? ORDER BY id DESC LIMIT ?",
+            (guild_id, user_id, limit),
+            |(kind, channel_id, old_channel_id, enabled, created_at)| VoiceEvent {
+                kind,
+                channel_id,
+                old_channel_id,
+                enabled,
+                created_at,
+            },
+        )
+        .await;
 
-    result
+    match result {
+        Ok(events) => events,
+        Err(err) => {
+            println!("Error with get_voice_events query: {}", err);
+            Vec::new()
+        }
+    }
 }
 
-pub async fn add_user_boss_music(ctx: &Context, user_id: &u64, file_name: &str) {
-    let mut conn = get_conn_from_ctx(ctx).await;
+/// Starts a session, ending the one the member could still have open
+pub async fn open_voice_session(pool: &Pool, guild_id: u64, user_id: u64, channel_id: u64) {
+    close_voice_session(pool, guild_id, user_id).await;
+
+    let mut conn = get_conn_from_pool(pool).await;
+    match conn
+        .exec_drop(
+            "INSERT INTO voice_sessions (guild_id, user_id, channel_id) VALUES (?, ?, ?)",
+            (guild_id, user_id, channel_id),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with open_voice_session query: {}", err)
+        }
+    }
+}
 
-    let sql = format!("INSERT IGNORE INTO guild_user_boss_music (user_id, song_name) VALUES ('{}', '{}.ogg') ON DUPLICATE KEY UPDATE song_name = '{}.ogg'", user_id, file_name, file_name);
+pub async fn close_voice_session(pool: &Pool, guild_id: u64, user_id: u64) {
+    let mut conn = get_conn_from_pool(pool).await;
 
-    match conn.query_drop(sql).await {
+    match conn
+        .exec_drop(
+            "UPDATE voice_sessions SET left_at = NOW() WHERE guild_id = ? AND user_id = ? AND left_at IS NULL",
+            (guild_id, user_id),
+        )
+        .await
+    {
         Ok(_) => {}
-        Err(why) => {
-            println!("Error with add_user_boss_music query: {}", why)
+        Err(err) => {
+            println!("Error with close_voice_session query: {}", err)
+        }
+    }
+}
+
+/// Returns the channel of the session the member has open, if any
+pub async fn get_open_voice_session(pool: &Pool, guild_id: u64, user_id: u64) -> Option<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_first(
+            "SELECT channel_id FROM voice_sessions WHERE guild_id = ? AND user_id = ? AND left_at IS NULL ORDER BY id DESC",
+            (guild_id, user_id),
+        )
+        .await
+    {
+        Ok(channel_id) => channel_id,
+        Err(err) => {
+            println!("Error with get_open_voice_session query: {}", err);
+            None
+        }
+    }
+}
+
+/// Returns the members of the guild with a session still open
+pub async fn get_open_voice_session_users(pool: &Pool, guild_id: u64) -> Vec<u64> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT DISTINCT user_id FROM voice_sessions WHERE guild_id = ? AND left_at IS NULL",
+            (guild_id,),
+        )
+        .await
+    {
+        Ok(user_ids) => user_ids,
+        Err(err) => {
+            println!("Error with get_open_voice_session_users query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the latest sessions of a member, newest first
+pub async fn get_voice_sessions(
+    pool: &Pool,
+    guild_id: u64,
+    user_id: u64,
+    limit: u64,
+) -> Vec<VoiceSession> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    let result = conn
+        .exec_map(
+            "SELECT channel_id, UNIX_TIMESTAMP(joined_at), UNIX_TIMESTAMP(left_at) FROM voice_sessions WHERE guild_id = ? AND user_id = ? ORDER BY id DESC LIMIT ?",
+            (guild_id, user_id, limit),
+            |(channel_id, joined_at, left_at)| VoiceSession {
+                channel_id,
+                joined_at,
+                left_at,
+            },
+        )
+        .await;
+
+    match result {
+        Ok(sessions) => sessions,
+        Err(err) => {
+            println!("Error with get_voice_sessions query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Records the time a member spent in a voice channel the bot listened to. Times are unix timestamps
+pub async fn add_speaking_session(
+    pool: &Pool,
+    guild_id: u64,
+    channel_id: u64,
+    user_id: u64,
+    joined_at: i64,
+    left_at: i64,
+    speaking_ms: u64,
+) {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec_drop(
+            "INSERT INTO voice_speaking_sessions (guild_id, channel_id, user_id, joined_at, left_at, speaking_ms) VALUES (?, ?, ?, FROM_UNIXTIME(?), FROM_UNIXTIME(?), ?)",
+            (guild_id, channel_id, user_id, joined_at, left_at, speaking_ms),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Error with add_speaking_session query: {}", err)
+        }
+    }
+}
+
+/// Members who spoke the most since `since`, with their speaking time in milliseconds
+pub async fn get_top_talkers(
+    pool: &Pool,
+    guild_id: u64,
+    since: i64,
+    limit: u64,
+) -> Vec<(u64, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT user_id, CAST(SUM(speaking_ms) AS UNSIGNED) AS spoken FROM voice_speaking_sessions WHERE guild_id = ? AND joined_at >= FROM_UNIXTIME(?) GROUP BY user_id HAVING spoken > 0 ORDER BY spoken DESC LIMIT ?",
+            (guild_id, since, limit),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with get_top_talkers query: {}", err);
+            Vec::new()
+        }
+    }
+}
+
+/// Members who spent the most time in voice since `since`, in seconds
+pub async fn get_time_in_channel(
+    pool: &Pool,
+    guild_id: u64,
+    since: i64,
+    limit: u64,
+) -> Vec<(u64, u64)> {
+    let mut conn = get_conn_from_pool(pool).await;
+
+    match conn
+        .exec(
+            "SELECT user_id, CAST(SUM(TIMESTAMPDIFF(SECOND, joined_at, left_at)) AS UNSIGNED) AS present FROM voice_speaking_sessions WHERE guild_id = ? AND joined_at >= FROM_UNIXTIME(?) GROUP BY user_id ORDER BY present DESC LIMIT ?",
+            (guild_id, since, limit),
+        )
+        .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            println!("Error with get_time_in_channel query: {}", err);
+            Vec::new()
         }
     }
 }
@@ -1,5 +1,7 @@
 use serenity::client::Context;
 
+use crate::features;
+
 pub async fn guild_ban_addition(
     _ctx: Context,
     _guild_id: serenity::model::id::GuildId,
@@ -16,7 +18,8 @@ pub async fn guild_ban_removal(
     todo!()
 }
 
-pub async fn guild_create(_ctx: Context, _guild: serenity::model::guild::Guild, _is_new: bool) {
+pub async fn guild_create(ctx: Context, guild: serenity::model::guild::Guild, _is_new: bool) {
+    features::voice_log::close_stale_sessions(&ctx, &guild).await;
     // println!("guild data : {:?}", is_new);
     // database::guilds::sync_guilds(guild, is_new).await;
 }
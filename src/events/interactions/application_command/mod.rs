@@ -1,21 +1,29 @@
+use lavalink_rs::{
+    model::{Track, TrackQueue},
+    LavalinkClient,
+};
 use serenity::{
     client::Context,
     model::{
         channel::ReactionType,
-        id::{EmojiId, GuildId},
+        id::{EmojiId, GuildId, UserId},
         interactions::{
             application_command::{
-                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+                ApplicationCommandInteractionDataOptionValue, ApplicationCommandOptionType,
             },
             message_component::ButtonStyle,
             InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
         },
     },
 };
-use tracing::info;
+use tracing::{info, warn};
 
 use crate::{
+    database::settings::set_dj_role,
     events::interactions::{get_songbird_manager, interactions::download_track_async},
+    features::{jam::jam_rating_buttons, sleep::sleep_timer_status},
+    helpers::db_helper::get_pool_from_ctx,
     GuildTrackMap,
 };
 
@@ -24,9 +32,11 @@ use std::fmt::Write;
 use super::{
     helpers::{
         add_events_to_handle, get_guild_channel_id_from_interaction_application,
-        join_or_get_voice_channel, misc_handle, not_in_a_voice_channel_application,
+        join_or_get_voice_channel, member_can_manage_guild, member_is_dj, misc_handle,
+        not_in_a_voice_channel_application, CANNOT_JOIN_VOICE,
     },
-    lavalink::get_lavalink_client,
+    lavalink::{get_lavalink_client, nodes::MUSIC_BACKEND_UNAVAILABLE},
+    message_component::DELETE_AND_SKIP,
 };
 
 const MAX_DISPLAY_QUEUED_TRACKS: usize = 19;
@@ -45,7 +55,13 @@ pub async fn handle_j(ctx: &Context, command: &ApplicationCommandInteraction) {
         None => return,
     };
 
-    let _ = join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id).await;
+    if join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id)
+        .await
+        .is_err()
+    {
+        edit_original_response_simple_content(command, ctx, CANNOT_JOIN_VOICE).await;
+        return;
+    }
 
     fun_name(command, ctx, guild_id).await;
 }
@@ -84,7 +100,13 @@ pub async fn display_current_queue(ctx: &Context, command: &ApplicationCommandIn
     let (guild_id, _channel_id) =
         get_guild_channel_id_from_interaction_application(command, ctx).await;
     let manager = get_songbird_manager(ctx).await;
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
 
     match manager.get(guild_id) {
         Some(_handle_lock) => {
@@ -183,6 +205,29 @@ pub async fn send_interaction_message_basic(
     };
 }
 
+pub async fn send_interaction_message_ephemeral(
+    command: &ApplicationCommandInteraction,
+    ctx: &Context,
+    content: &str,
+) {
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
 // This command will try to set both the current and global volume
 pub async fn handle_vol(ctx: &Context, command: &ApplicationCommandInteraction) {
     let (guild_id, channel_id) =
@@ -205,7 +250,14 @@ pub async fn handle_vol(ctx: &Context, command: &ApplicationCommandInteraction)
                 }
 
                 let manager = get_songbird_manager(ctx).await;
-                let lavalink = get_lavalink_client(ctx).await;
+                let lavalink = match get_lavalink_client(ctx, guild_id).await {
+                    Some(lavalink) => lavalink,
+                    None => {
+                        send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE)
+                            .await;
+                        return;
+                    }
+                };
                 match manager.get(guild_id) {
                     Some(_) => {
                         match lavalink.volume(guild_id.0, *value as u16).await {
@@ -290,9 +342,20 @@ pub async fn handle_playlist(ctx: &Context, command: &ApplicationCommandInteract
         None => return,
     };
 
-    let lavalink = get_lavalink_client(ctx).await;
-    let _handle_lock =
-        join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            edit_original_response_simple_content(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
+    if join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id)
+        .await
+        .is_err()
+    {
+        edit_original_response_simple_content(command, ctx, CANNOT_JOIN_VOICE).await;
+        return;
+    }
 
     let option = get_option_at_index_application_command(command, 0).await;
     if let ApplicationCommandInteractionDataOptionValue::String(option) = option {
@@ -318,6 +381,7 @@ pub async fn handle_playlist(ctx: &Context, command: &ApplicationCommandInteract
             for ele in &query_information.tracks {
                 if let Err(why) = &lavalink
                     .play(guild_id.0, ele.clone())
+                    .requester(command.user.id)
                     // Change this to play() if you want your own custom queue or no queue at all.
                     .queue()
                     .await
@@ -392,7 +456,13 @@ pub async fn handle_youtube_link(
     ctx: &Context,
     guild_id: GuildId,
 ) {
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            edit_original_response_simple_content(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
     let tracks = lavalink
         .auto_search_tracks(option)
         .await
@@ -407,6 +477,7 @@ pub async fn handle_youtube_link(
 
     match lavalink
         .play(guild_id.0, tracks.tracks[0].clone())
+        .requester(command.user.id)
         .queue()
         .await
     {
@@ -417,7 +488,7 @@ pub async fn handle_youtube_link(
         }
     };
 
-    download_track_async(ctx, option, guild_id).await;
+    download_track_async(ctx, option, guild_id, command.user.id).await;
     play_audio_from_string(
         command,
         ctx,
@@ -431,13 +502,17 @@ pub async fn handle_youtube_link(
     .await;
 }
 
-async fn edit_original_response_simple_content(
+pub(crate) async fn edit_original_response_simple_content(
     command: &ApplicationCommandInteraction,
     ctx: &Context,
     content: &str,
 ) {
     match command
-        .edit_original_interaction_response(ctx, |response| response.content(content))
+        .edit_original_interaction_response(ctx, |response| {
+            response
+                .content(content)
+                .allowed_mentions(|mentions| mentions.empty_parse())
+        })
         .await
     {
         Ok(_) => {}
@@ -452,6 +527,30 @@ pub async fn play_audio_from_string(
     ctx: &Context,
     title: &str,
 ) {
+    edit_now_playing(command, ctx, title, None).await;
+}
+
+/// Like `play_audio_from_string`, with the buttons to rate the jam track or take it out of the
+/// library
+pub async fn play_jam_audio_from_string(
+    command: &ApplicationCommandInteraction,
+    ctx: &Context,
+    title: &str,
+    jam_id: u64,
+) {
+    edit_now_playing(command, ctx, title, Some(jam_id)).await;
+}
+
+async fn edit_now_playing(
+    command: &ApplicationCommandInteraction,
+    ctx: &Context,
+    title: &str,
+    jam_id: Option<u64>,
+) {
+    let sleep_status = match command.guild_id {
+        Some(guild_id) => sleep_timer_status(ctx, guild_id.0).await,
+        None => String::new(),
+    };
     let a = command
         .edit_original_interaction_response(&ctx.http, |response| {
             response
@@ -491,9 +590,20 @@ pub async fn play_audio_from_string(
                                 .label("")
                                 .style(ButtonStyle::Secondary)
                         })
-                    })
+                    });
+                    if let Some(jam_id) = jam_id {
+                        comp.create_action_row(|row| {
+                            row.create_button(|btn| {
+                                btn.custom_id(format!("{}{}", DELETE_AND_SKIP, jam_id))
+                                    .emoji(ReactionType::Unicode("❌".to_string()))
+                                    .style(ButtonStyle::Secondary)
+                            });
+                            jam_rating_buttons(row, jam_id)
+                        });
+                    }
+                    comp
                 })
-                .content(format!("Playing a jammer: {}", title))
+                .content(format!("Playing a jammer: {}{}", title, sleep_status))
         })
         .await;
 
@@ -520,6 +630,41 @@ pub async fn get_option_at_index_application_command(
     option
 }
 
+/// Returns the name and the options of the subcommand that was used
+pub fn get_subcommand(
+    command: &ApplicationCommandInteraction,
+) -> Option<(&str, &[ApplicationCommandInteractionDataOption])> {
+    command
+        .data
+        .options
+        .get(0)
+        .filter(|option| option.kind == ApplicationCommandOptionType::SubCommand)
+        .map(|option| (option.name.as_str(), option.options.as_slice()))
+}
+
+/// Finds an optional option by its name
+pub fn get_option_by_name<'a>(
+    options: &'a [ApplicationCommandInteractionDataOption],
+    name: &str,
+) -> Option<&'a ApplicationCommandInteractionDataOptionValue> {
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.resolved.as_ref())
+}
+
+/// Reads an option given in seconds, which may be a whole number
+pub fn get_seconds_option(
+    options: &[ApplicationCommandInteractionDataOption],
+    name: &str,
+) -> Option<f64> {
+    match get_option_by_name(options, name) {
+        Some(ApplicationCommandInteractionDataOptionValue::Number(secs)) => Some(*secs),
+        Some(ApplicationCommandInteractionDataOptionValue::Integer(secs)) => Some(*secs as f64),
+        _ => None,
+    }
+}
+
 pub async fn handle_patryk_application_command(
     command: &ApplicationCommandInteraction,
     ctx: &Context,
@@ -542,7 +687,13 @@ pub async fn handle_search_string_application_command(
     command: &ApplicationCommandInteraction,
 ) {
     // We search youtube for the string
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            edit_original_response_simple_content(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
     let manager = songbird::get(ctx).await.unwrap().clone();
 
     if let Some(_handler) = manager.get(guild_id) {
@@ -558,6 +709,7 @@ pub async fn handle_search_string_application_command(
 
         match lavalink
             .play(guild_id.0, tracks.tracks[0].clone())
+            .requester(command.user.id)
             .queue()
             .await
         {
@@ -568,7 +720,7 @@ pub async fn handle_search_string_application_command(
             }
         };
 
-        download_track_async(ctx, option, guild_id).await;
+        download_track_async(ctx, option, guild_id, command.user.id).await;
         play_audio_from_string(
             command,
             ctx,
@@ -585,6 +737,217 @@ pub async fn handle_search_string_application_command(
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueuePriority {
+    /// Right after the current track
+    Next,
+    /// Interrupt the current track, it is played again afterwards
+    Now,
+}
+
+pub async fn handle_playnext(ctx: &Context, command: &ApplicationCommandInteraction) {
+    handle_priority_play(ctx, command, QueuePriority::Next).await;
+}
+
+pub async fn handle_playnow(ctx: &Context, command: &ApplicationCommandInteraction) {
+    handle_priority_play(ctx, command, QueuePriority::Now).await;
+}
+
+async fn handle_priority_play(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    priority: QueuePriority,
+) {
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    let (guild_id, channel_id) =
+        get_guild_channel_id_from_interaction_application(command, ctx).await;
+
+    if !member_is_dj(ctx, guild_id, command.member.as_ref()).await {
+        edit_original_response_simple_content(
+            command,
+            ctx,
+            "Only DJs can change the order of the queue",
+        )
+        .await;
+        return;
+    }
+
+    let connect_to = match not_in_a_voice_channel_application(channel_id, command, ctx).await {
+        Some(value) => value,
+        None => return,
+    };
+
+    if join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id)
+        .await
+        .is_err()
+    {
+        edit_original_response_simple_content(command, ctx, CANNOT_JOIN_VOICE).await;
+        return;
+    }
+
+    let option = get_option_at_index_application_command(command, 0).await;
+    let query = match option {
+        ApplicationCommandInteractionDataOptionValue::String(query) => query,
+        _ => {
+            edit_original_response_simple_content(command, ctx, "Provide a string").await;
+            return;
+        }
+    };
+
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            edit_original_response_simple_content(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
+    let tracks = match lavalink.auto_search_tracks(query).await {
+        Ok(tracks) => tracks,
+        Err(err) => {
+            warn!("Cannot search tracks: {}", err);
+            edit_original_response_simple_content(command, ctx, "Search failed").await;
+            return;
+        }
+    };
+
+    if tracks.tracks.is_empty() {
+        edit_original_response_simple_content(command, ctx, "Search returned no results").await;
+        return;
+    }
+
+    let track = tracks.tracks[0].clone();
+    if !insert_with_priority(ctx, &lavalink, guild_id, &track, command.user.id, priority).await {
+        // Nothing is playing, a normal queue starts it right away
+        match lavalink
+            .play(guild_id.0, track.clone())
+            .requester(command.user.id)
+            .queue()
+            .await
+        {
+            Ok(_) => {}
+            Err(err) => {
+                warn!("Cannot play track: {}", err);
+                edit_original_response_simple_content(command, ctx, "Cannot play track").await;
+                return;
+            }
+        };
+    }
+
+    download_track_async(ctx, query, guild_id, command.user.id).await;
+    play_audio_from_string(
+        command,
+        ctx,
+        track
+            .info
+            .as_ref()
+            .map_or("Unkown title", |info| info.title.as_str()),
+    )
+    .await;
+}
+
+/// Puts a track right behind the one currently playing (which is at the front of the lavalink queue).
+/// With `QueuePriority::Now` the current track is put back behind the new one, from where it was, and skipped.
+/// Returns false when nothing is playing
+pub(crate) async fn insert_with_priority(
+    ctx: &Context,
+    lavalink: &LavalinkClient,
+    guild_id: GuildId,
+    track: &Track,
+    requester: UserId,
+    priority: QueuePriority,
+) -> bool {
+    let current_position = {
+        let guild_track = ctx
+            .data
+            .read()
+            .await
+            .get::<GuildTrackMap>()
+            .expect("cannot get GuildTrackMap")
+            .clone();
+        let mutex_guard = guild_track.lock().await;
+        match mutex_guard.get(&guild_id.0) {
+            Some(guild_track) => {
+                guild_track.position.max(0) as u64
+                    + guild_track.how_long.elapsed().as_millis() as u64
+            }
+            None => 0,
+        }
+    };
+
+    {
+        let nodes = lavalink.nodes().await;
+        let mut node = match nodes.get_mut(&guild_id.0) {
+            Some(node) => node,
+            None => return false,
+        };
+
+        let current = match &node.now_playing {
+            Some(current) if !node.queue.is_empty() => current.clone(),
+            _ => return false,
+        };
+
+        node.queue.insert(
+            1,
+            TrackQueue {
+                track: track.clone(),
+                start_time: 0,
+                end_time: None,
+                requester: Some(requester),
+            },
+        );
+
+        if priority == QueuePriority::Now {
+            let mut resumed = current;
+            resumed.start_time = current_position;
+            node.queue.insert(2, resumed);
+        }
+    }
+
+    if priority == QueuePriority::Now {
+        let _ = lavalink.skip(guild_id.0).await;
+    }
+
+    true
+}
+
+/// `/djrole [role]`. Without a role everyone can reorder the queue again
+pub async fn handle_dj_role(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    match command
+        .data
+        .options
+        .get(0)
+        .and_then(|option| option.resolved.as_ref())
+    {
+        Some(ApplicationCommandInteractionDataOptionValue::Role(role)) => {
+            set_dj_role(&pool, guild_id.0, Some(role.id.0)).await;
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                format!("Only members with {} can reorder the queue", role.name).as_str(),
+            )
+            .await;
+        }
+        _ => {
+            set_dj_role(&pool, guild_id.0, None).await;
+            send_interaction_message_ephemeral(command, ctx, "Everyone can reorder the queue")
+                .await;
+        }
+    }
+}
+
 pub async fn hanle_fast_forward_audio_application_command(
     ctx: &Context,
     command: &ApplicationCommandInteraction,
@@ -597,7 +960,14 @@ pub async fn hanle_fast_forward_audio_application_command(
     match manager.get(guild_id) {
         Some(_handle_lock) => {
             // handle_handle(handle_lock, command, ctx).await;
-            let lavalink = get_lavalink_client(ctx).await;
+            let lavalink = match get_lavalink_client(ctx, guild_id).await {
+                Some(lavalink) => lavalink,
+                None => {
+                    send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE)
+                        .await;
+                    return;
+                }
+            };
             let is_playing = match lavalink.nodes().await.get(&guild_id.0) {
                 Some(ok) => {
                     ok.now_playing.is_some()
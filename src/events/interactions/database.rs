@@ -1,23 +1,18 @@
-use crate::database::get_conn_from_ctx;
+use crate::database::{get_conn_from_ctx, media::MediaFile};
 use mysql_async::prelude::Queryable;
 use serenity::client::Context;
 
 pub async fn add_track_to_db(
     ctx: Context,
     guild_id: serenity::model::id::GuildId,
-    title: String,
-    ext: String,
-) {
+    media: &MediaFile,
+    added_by: Option<u64>,
+) -> Result<(), mysql_async::Error> {
     let mut conn = get_conn_from_ctx(&ctx).await;
-    let query = format!(
-        "INSERT IGNORE INTO jam_it (id, guild_id, audio_name, ext) VALUES (NULL, '{}', '{}', '{}')",
-        guild_id, title, ext
-    );
 
-    match conn.query_drop(query).await {
-        Ok(_) => {}
-        Err(err) => {
-            panic!("error when trying to insert: {}", err)
-        }
-    }
+    conn.exec_drop(
+        "INSERT IGNORE INTO jam_it (id, guild_id, audio_name, ext, media_id, added_by) VALUES (NULL, ?, ?, ?, ?, ?)",
+        (guild_id.0, &media.title, &media.ext, media.id, added_by),
+    )
+    .await
 }
@@ -1,11 +1,15 @@
-use std::{process::Command, sync::Arc};
+use std::sync::Arc;
 
 use super::{get_songbird_manager, interactions::TrackEndNotifier};
-use crate::{GuildTrack, GuildTrackMap, Lavalink};
+use crate::{
+    database::settings::get_dj_role, helpers::db_helper::get_pool_from_ctx, GuildTrack,
+    GuildTrackMap, Lavalink,
+};
 use serenity::{
     client::Context,
     model::{
-        id::ChannelId,
+        guild::Member,
+        id::{ChannelId, GuildId},
         interactions::{
             application_command::ApplicationCommandInteraction,
             message_component::MessageComponentInteraction,
@@ -16,6 +20,7 @@ use serenity::{
 use songbird::error::JoinError;
 use songbird::Call;
 use songbird::{Event, TrackEvent};
+use tracing::warn;
 
 pub async fn get_guild_channel_id_from_interaction_application(
     command: &ApplicationCommandInteraction,
@@ -53,35 +58,26 @@ pub async fn get_guild_channel_id_from_interaction_message(
     (guild_id, channel_id)
 }
 
-pub async fn ytdl_input_from_string(option: &str) -> (String, String) {
-    let stra = format!("ytsearch:{}", option);
-    let command = Command::new("yt-dlp")
-        .args([
-            "-j",
-            "--embed-metadata",
-            "-f",
-            "webm[abr>0]/bestaudio/best",
-            "-R",
-            "infinite",
-            "--no-playlist",
-            "--ignore-config",
-            "--no-warnings",
-            stra.as_str(),
-            "-o",
-            "/home/ubuntu/projects/sakiot_rouvas/t/%(title)s.%(ext)s",
-        ])
-        .output();
-
-    let child = command.expect("cannot start yt-dlp");
-    // Convert the output to a string
-    let output = std::str::from_utf8(&child.stdout).expect("expected output in stdout");
-    // Convert the string to a dynamic json (--print-json)
-    let json: serde_json::Value = serde_json::from_str(output).expect("cannot serialize json");
-    // Find the title in the json
-    let title = json["title"].as_str().expect("title not found").to_owned();
-    let ext = json["ext"].as_str().expect("ext not found").to_owned();
-
-    (title, ext)
+/// Whether the member that used an interaction can manage the guild. Used to gate admin commands
+pub fn member_can_manage_guild(member: Option<&Member>) -> bool {
+    member
+        .and_then(|member| member.permissions)
+        .map_or(false, |permissions| permissions.manage_guild())
+}
+
+/// DJs can reorder the queue. When the guild has no DJ role configured everyone is a DJ
+pub async fn member_is_dj(ctx: &Context, guild_id: GuildId, member: Option<&Member>) -> bool {
+    if member_can_manage_guild(member) {
+        return true;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    match get_dj_role(&pool, guild_id.0).await {
+        Some(role_id) => member.map_or(false, |member| {
+            member.roles.iter().any(|role| role.0 == role_id)
+        }),
+        None => true,
+    }
 }
 
 pub async fn not_in_a_voice_channel_application(
@@ -123,19 +119,23 @@ pub async fn join_voice_channel(
     }
 }
 
+pub const CANNOT_JOIN_VOICE: &str = "Cannot join your voice channel";
+
+/// The call of the guild, joining `connect_to` when the bot is not in voice yet. Callers answer
+/// with `CANNOT_JOIN_VOICE` when the join fails
 pub async fn join_or_get_voice_channel(
     ctx: &Context,
     guild_id: serenity::model::id::GuildId,
     connect_to: ChannelId,
     text_channel_id: ChannelId,
-) -> Arc<Mutex<Call>> {
+) -> Result<Arc<Mutex<Call>>, JoinError> {
     let manager = get_songbird_manager(ctx).await;
 
     match manager.get(guild_id) {
         Some(handle_lock) => {
             // we have a handle. Re-use it
             // TODO: re-connect to a different channel if user is in a different channel
-            handle_lock
+            Ok(handle_lock)
         }
         None => {
             // No handle get a new one
@@ -145,11 +145,13 @@ pub async fn join_or_get_voice_channel(
                     misc_handle(ctx, connection_info, guild_id).await;
                     add_events_to_handle(&handle_lock, ctx, text_channel_id, guild_id).await;
 
-                    handle_lock
+                    Ok(handle_lock)
                 }
-                Err(_why) => {
-                    // TODO: send response
-                    panic!("cannot join voice channel");
+                Err(why) => {
+                    warn!("Cannot join voice channel {}: {}", connect_to, why);
+                    // Do not keep a call that never connected
+                    let _ = manager.remove(guild_id).await;
+                    Err(why)
                 }
             }
         }
@@ -162,12 +164,35 @@ pub async fn misc_handle(
     guild_id: serenity::model::id::GuildId,
 ) {
     let data = ctx.data.read().await;
-    let lavalink = data.get::<Lavalink>().unwrap().clone();
-    lavalink
-        .create_session_with_songbird(&connection_info)
-        .await
-        .expect("cannot create lavalink session");
-    let _ = lavalink.volume(guild_id.0, 100).await;
+    let nodes = data.get::<Lavalink>().unwrap().clone();
+    let lavalink = {
+        let mut nodes = nodes.write().await;
+        nodes.set_connection_info(guild_id.0, connection_info.clone());
+        let lavalink = nodes.client_for_guild(guild_id.0);
+        if lavalink.is_none() {
+            // The session is created when a node connects
+            nodes.add_pending_guild(guild_id.0, None, 0);
+        }
+        lavalink
+    };
+    if let Some(lavalink) = lavalink {
+        match lavalink
+            .create_session_with_songbird(&connection_info)
+            .await
+        {
+            Ok(_) => {
+                let _ = lavalink.volume(guild_id.0, 100).await;
+            }
+            Err(err) => {
+                // The node went down in the meantime, the session is created once one is up
+                warn!(
+                    "Cannot create lavalink session for guild {}: {}",
+                    guild_id, err
+                );
+                nodes.write().await.add_pending_guild(guild_id.0, None, 0);
+            }
+        }
+    }
     let guild_track = data
         .get::<GuildTrackMap>()
         .expect("cannot get GuildTrackMap")
@@ -179,6 +204,9 @@ pub async fn misc_handle(
             volume: 100,
             position: 0,
             how_long: std::time::Instant::now(),
+            history_id: None,
+            sleep_timer: None,
+            boss_music_visit: false,
         },
     );
 }
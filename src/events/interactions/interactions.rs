@@ -4,7 +4,7 @@ use serenity::{
     async_trait,
     http::Http,
     model::{
-        id::{ChannelId, GuildId},
+        id::{ChannelId, GuildId, UserId},
         interactions::{
             application_command::{
                 ApplicationCommand, ApplicationCommandInteractionDataOptionValue,
@@ -14,16 +14,70 @@ use serenity::{
     },
     prelude::*,
 };
-use songbird::{input::Restartable, Event, EventContext, EventHandler as VoiceEventHandler};
+use songbird::{Event, EventContext, EventHandler as VoiceEventHandler};
 use tracing::info;
 
 use crate::events::interactions::message_component::{
     handle_delete_and_skip_from_jam, handle_jam_it, handle_next_audio_in_queue,
-    handle_play_pause_audio, handle_stop_audio, hanle_fast_forward_audio,
+    handle_play_pause_audio, handle_stop_audio, hanle_fast_forward_audio, DELETE_AND_SKIP,
 };
 use crate::events::interactions::{
-    application_command::*, database::add_track_to_db, helpers::ytdl_input_from_string,
+    application_command::*,
+    lavalink::nodes::{music_backend_available, MUSIC_BACKEND_UNAVAILABLE},
 };
+use crate::features::autoplay::handle_autoplay;
+use crate::features::boss_music::{
+    handle_boss_music, handle_boss_music_review, BOSS_MUSIC_APPROVE, BOSS_MUSIC_REJECT,
+};
+use crate::features::clip::{handle_clip, handle_clip_buffer, is_listening, CLIP_BUFFER_LISTENING};
+use crate::features::downloads::{handle_downloads, queue_download};
+use crate::features::history::{
+    handle_history, handle_history_autocomplete, handle_history_page, handle_history_replay,
+    HISTORY_PAGE, HISTORY_REPLAY,
+};
+use crate::features::jam::{
+    handle_jam, handle_jam_autocomplete, handle_jam_page, handle_jam_rate, JAM_PAGE, JAM_RATE_DOWN,
+    JAM_RATE_UP,
+};
+use crate::features::recording::{handle_record, is_recording, RECORDING_IN_PROGRESS};
+use crate::features::sleep::handle_sleep;
+use crate::features::soundboard::{
+    handle_soundboard, handle_soundboard_play, is_mixing, SOUNDBOARD_MIXING, SOUNDBOARD_PLAY,
+};
+use crate::features::stats::handle_stats;
+use crate::features::storage::handle_storage;
+use crate::features::voice_log::handle_voice_log;
+use crate::features::voice_stats::handle_voice_stats;
+
+// Commands and buttons that need lavalink
+const MUSIC_COMMANDS: &[&str] = &[
+    "j", "que", "vol", "playlist", "join", "ff", "playnext", "playnow",
+];
+const MUSIC_COMPONENTS: &[&str] = &["play", "next", "stop", "ff", "jam_it"];
+
+fn is_music_component(custom_id: &str) -> bool {
+    MUSIC_COMPONENTS.contains(&custom_id)
+        || custom_id.starts_with(HISTORY_REPLAY)
+        || custom_id.starts_with(DELETE_AND_SKIP)
+}
+
+/// Songbird owns the voice connection while the bot records, listens for clips or mixes soundboard
+/// clips, lavalink cannot play then
+pub(crate) async fn voice_connection_taken(
+    ctx: &Context,
+    guild_id: Option<GuildId>,
+) -> Option<&'static str> {
+    let guild_id = guild_id?;
+    if is_recording(&ctx.data, guild_id).await {
+        Some(RECORDING_IN_PROGRESS)
+    } else if is_listening(&ctx.data, guild_id).await {
+        Some(CLIP_BUFFER_LISTENING)
+    } else if is_mixing(&ctx.data, guild_id).await {
+        Some(SOUNDBOARD_MIXING)
+    } else {
+        None
+    }
+}
 
 pub struct TrackEndNotifier {
     pub chann_id: ChannelId,
@@ -75,6 +129,18 @@ pub async fn interaction_create(ctx: Context, interaction: Interaction) {
     let now = Instant::now();
     if let Interaction::ApplicationCommand(command) = interaction {
         println!("interaction name: {}", command.data.name);
+        if MUSIC_COMMANDS.contains(&command.data.name.as_str())
+            && !music_backend_available(&ctx.data).await
+        {
+            send_interaction_message_ephemeral(&command, &ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+        if MUSIC_COMMANDS.contains(&command.data.name.as_str()) {
+            if let Some(taken) = voice_connection_taken(&ctx, command.guild_id).await {
+                send_interaction_message_ephemeral(&command, &ctx, taken).await;
+                return;
+            }
+        }
         match command.data.name.as_str() {
             "j" => handle_j(&ctx, &command).await,
             "que" => display_current_queue(&ctx, &command).await,
@@ -83,6 +149,23 @@ pub async fn interaction_create(ctx: Context, interaction: Interaction) {
             "playlist" => handle_playlist(&ctx, &command).await,
             "join" => handle_join(&ctx, &command).await,
             "ff" => handle_ff(&ctx, &command).await,
+            "history" => handle_history(&ctx, &command).await,
+            "stats" => handle_stats(&ctx, &command).await,
+            "autoplay" => handle_autoplay(&ctx, &command).await,
+            "playnext" => handle_playnext(&ctx, &command).await,
+            "playnow" => handle_playnow(&ctx, &command).await,
+            "djrole" => handle_dj_role(&ctx, &command).await,
+            "sleep" => handle_sleep(&ctx, &command).await,
+            "downloads" => handle_downloads(&ctx, &command).await,
+            "storage" => handle_storage(&ctx, &command).await,
+            "jam" => handle_jam(&ctx, &command).await,
+            "bossmusic" => handle_boss_music(&ctx, &command).await,
+            "record" => handle_record(&ctx, &command).await,
+            "clip" => handle_clip(&ctx, &command).await,
+            "clipbuffer" => handle_clip_buffer(&ctx, &command).await,
+            "voicestats" => handle_voice_stats(&ctx, &command).await,
+            "voicelog" => handle_voice_log(&ctx, &command).await,
+            "soundboard" => handle_soundboard(&ctx, &command).await,
             _ => {
                 send_interaction_message_basic(
                     &command,
@@ -98,6 +181,29 @@ pub async fn interaction_create(ctx: Context, interaction: Interaction) {
         };
     } else if let Interaction::MessageComponent(command) = interaction {
         println!("Message component command: {}", command.data.custom_id);
+        let unavailable = if !is_music_component(&command.data.custom_id) {
+            None
+        } else if !music_backend_available(&ctx.data).await {
+            Some(MUSIC_BACKEND_UNAVAILABLE)
+        } else {
+            voice_connection_taken(&ctx, command.guild_id).await
+        };
+        if let Some(unavailable) = unavailable {
+            if let Err(why) = command
+                .create_interaction_response(&ctx, |f| {
+                    f.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|message| {
+                            message
+                                .content(unavailable)
+                                .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                        })
+                })
+                .await
+            {
+                println!("Cannot respond to message component: {}", why);
+            }
+            return;
+        }
         match command.data.custom_id.as_str() {
             "play" => {
                 handle_play_pause_audio(&ctx, &command).await;
@@ -119,9 +225,32 @@ pub async fn interaction_create(ctx: Context, interaction: Interaction) {
             "jam_it" => {
                 handle_jam_it(&ctx, &command).await;
             }
-            "delete_and_skip" => {
+            custom_id if custom_id.starts_with(DELETE_AND_SKIP) => {
                 handle_delete_and_skip_from_jam(&ctx, &command).await;
             }
+            custom_id if custom_id.starts_with(HISTORY_REPLAY) => {
+                handle_history_replay(&ctx, &command).await;
+            }
+            custom_id if custom_id.starts_with(HISTORY_PAGE) => {
+                handle_history_page(&ctx, &command).await;
+            }
+            custom_id if custom_id.starts_with(JAM_PAGE) => {
+                handle_jam_page(&ctx, &command).await;
+            }
+            custom_id
+                if custom_id.starts_with(JAM_RATE_UP) || custom_id.starts_with(JAM_RATE_DOWN) =>
+            {
+                handle_jam_rate(&ctx, &command).await;
+            }
+            custom_id
+                if custom_id.starts_with(BOSS_MUSIC_APPROVE)
+                    || custom_id.starts_with(BOSS_MUSIC_REJECT) =>
+            {
+                handle_boss_music_review(&ctx, &command).await;
+            }
+            custom_id if custom_id.starts_with(SOUNDBOARD_PLAY) => {
+                handle_soundboard_play(&ctx, &command).await;
+            }
             _ => {
                 if let Err(why) = command
 				.create_interaction_response(&ctx, |f| {
@@ -135,6 +264,14 @@ pub async fn interaction_create(ctx: Context, interaction: Interaction) {
                 panic!("Unkown custom id")
             }
         }
+    } else if let Interaction::Autocomplete(autocomplete) = interaction {
+        match autocomplete.data.name.as_str() {
+            "j" => handle_history_autocomplete(&ctx, &autocomplete).await,
+            "jam" => handle_jam_autocomplete(&ctx, &autocomplete).await,
+            _ => {
+                println!("No autocomplete for {}", autocomplete.data.name);
+            }
+        }
     } else if let Interaction::Ping(_command) = interaction {
         println!("ping");
     }
@@ -197,14 +334,13 @@ async fn handle_ff(
 //     command.stdout
 // }
 
-pub async fn download_track_async(ctx: &Context, option: &str, guild_id: GuildId) {
-    let ctx1 = ctx.clone();
-    let stra = Arc::new(option.to_owned());
-    tokio::spawn(async move {
-        let (title, ext) = ytdl_input_from_string(&stra).await;
-        info!("Downloaded track: {}.{}", title, ext);
-        add_track_to_db(ctx1, guild_id, title, ext).await;
-    });
+pub async fn download_track_async(
+    ctx: &Context,
+    option: &str,
+    guild_id: GuildId,
+    requester: UserId,
+) {
+    queue_download(ctx, option, guild_id, requester).await;
 }
 
 // async fn handle_handle(
@@ -282,20 +418,6 @@ pub async fn download_track_async(ctx: &Context, option: &str, guild_id: GuildId
 //     }
 // }
 
-pub async fn ffmpeg_input_from_string(title: &str, ext: &str) -> songbird::input::Restartable {
-    let ffmpeg_input = Restartable::ffmpeg(
-        format!(
-            "/home/ubuntu/projects/sakiot_rouvas/temp_video/{}.{}",
-            &title, ext
-        ),
-        true,
-    )
-    .await
-    .expect("error with ffmpeg songibrd");
-
-    ffmpeg_input
-}
-
 pub async fn application_command_create(_ctx: Context, _application_command: ApplicationCommand) {
     todo!()
 }
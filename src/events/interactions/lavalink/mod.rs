@@ -1,10 +1,14 @@
-use serenity::client::Context;
+use serenity::{client::Context, model::id::GuildId};
 
-use crate::Lavalink;
+use self::nodes::lavalink_client_for_guild;
 
-pub(crate) async fn get_lavalink_client(ctx: &Context) -> lavalink_rs::LavalinkClient {
-    let data = ctx.data.read().await;
-    let lavalink = data.get::<Lavalink>().unwrap().clone();
+pub mod nodes;
 
-    lavalink
+/// The client of the lavalink node the guild is assigned to. `None` while every node is down, the
+/// caller answers with `MUSIC_BACKEND_UNAVAILABLE` then
+pub(crate) async fn get_lavalink_client(
+    ctx: &Context,
+    guild_id: GuildId,
+) -> Option<lavalink_rs::LavalinkClient> {
+    lavalink_client_for_guild(&ctx.data, guild_id.0).await
 }
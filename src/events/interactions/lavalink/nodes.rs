@@ -0,0 +1,465 @@
+use std::{
+    collections::HashMap,
+    env,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use lavalink_rs::{model::Stats, LavalinkClient};
+use serenity::prelude::{Mutex, RwLock, TypeMap};
+use songbird::ConnectionInfo;
+use tracing::{info, warn};
+
+use crate::{connect_lavalink_node, GuildTrack, GuildTrackMap, Lavalink};
+
+// Lavalink sends stats every minute. A node that has been silent for longer is considered down
+const STATS_TIMEOUT: Duration = Duration::from_secs(150);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(120);
+// Lavalink reports the position of a playing track this often
+const PLAYER_UPDATE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub const MUSIC_BACKEND_UNAVAILABLE: &str = "Music backend unavailable, try again in a bit";
+
+pub struct NodeStats {
+    pub playing_players: u64,
+    pub system_load: f64,
+    pub received_at: Instant,
+}
+
+pub struct LavalinkNode {
+    pub host: String,
+    pub port: u16,
+    /// `None` until the first connection succeeds
+    pub client: Option<LavalinkClient>,
+    pub stats: Option<NodeStats>,
+    pub healthy: bool,
+    connected_at: Instant,
+}
+
+impl LavalinkNode {
+    /// Lower is better. Stats are only sent every minute so the guilds we assigned since are
+    /// counted as well
+    fn penalty(&self, assigned_guilds: usize) -> f64 {
+        match &self.stats {
+            Some(stats) => {
+                (stats.playing_players as f64).max(assigned_guilds as f64)
+                    + stats.system_load * 10.0
+            }
+            None => assigned_guilds as f64,
+        }
+    }
+
+    fn last_seen(&self) -> Instant {
+        match &self.stats {
+            Some(stats) => stats.received_at,
+            None => self.connected_at,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LavalinkNodes {
+    pub nodes: Vec<LavalinkNode>,
+    /// guild id -> index in `nodes`
+    guilds: HashMap<u64, usize>,
+    /// The voice connection of each guild, needed to create the session again on another node
+    connections: HashMap<u64, ConnectionInfo>,
+    /// Guilds that lost their node while no other one was up. Holds the client of the lost node
+    /// since it still has the queue of the guild, and where the current track was at that moment
+    pending: HashMap<u64, (Option<LavalinkClient>, u64)>,
+}
+
+impl LavalinkNodes {
+    /// Registers a node. It gets connected by the task started with `start_node_connection`
+    pub fn add_node(&mut self, host: String, port: u16) -> usize {
+        self.nodes.push(LavalinkNode {
+            host,
+            port,
+            client: None,
+            stats: None,
+            healthy: false,
+            connected_at: Instant::now(),
+        });
+
+        self.nodes.len() - 1
+    }
+
+    pub fn set_client(&mut self, index: usize, client: LavalinkClient) {
+        let node = &mut self.nodes[index];
+        info!("Connected to lavalink node {}:{}", node.host, node.port);
+        node.client = Some(client);
+        node.stats = None;
+        node.healthy = true;
+        node.connected_at = Instant::now();
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.nodes.iter().any(|node| node.healthy)
+    }
+
+    /// The client of the node the guild is on. Guilds without a node are assigned to the least
+    /// loaded healthy one
+    pub fn client_for_guild(&mut self, guild_id: u64) -> Option<LavalinkClient> {
+        let index = match self.guilds.get(&guild_id) {
+            Some(index) if self.nodes[*index].healthy => *index,
+            _ => {
+                let index = self.least_loaded()?;
+                self.guilds.insert(guild_id, index);
+                index
+            }
+        };
+
+        self.nodes[index].client.clone()
+    }
+
+    pub fn set_connection_info(&mut self, guild_id: u64, connection_info: ConnectionInfo) {
+        self.connections.insert(guild_id, connection_info);
+    }
+
+    /// Keeps the guild around so that its player is created once a node is up again
+    pub fn add_pending_guild(
+        &mut self,
+        guild_id: u64,
+        old_client: Option<LavalinkClient>,
+        position: u64,
+    ) {
+        self.guilds.remove(&guild_id);
+        self.pending.insert(guild_id, (old_client, position));
+    }
+
+    /// Called when the bot leaves the voice channel of a guild
+    pub fn release_guild(&mut self, guild_id: u64) {
+        self.guilds.remove(&guild_id);
+        self.connections.remove(&guild_id);
+        self.pending.remove(&guild_id);
+    }
+
+    fn least_loaded(&self) -> Option<usize> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.healthy)
+            .map(|(index, node)| {
+                let assigned = self
+                    .guilds
+                    .values()
+                    .filter(|assigned_node| **assigned_node == index)
+                    .count();
+                (index, node.penalty(assigned))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(index, _)| index)
+    }
+}
+
+/// Reads `LAVALINK_NODES` ("host:port,host:port"). Defaults to a single local node
+pub fn node_addresses_from_env() -> Vec<(String, u16)> {
+    let nodes = env::var("LAVALINK_NODES").unwrap_or_else(|_| "127.0.0.1:2333".to_string());
+
+    nodes
+        .split(',')
+        .map(|address| address.trim())
+        .filter(|address| !address.is_empty())
+        .map(|address| match address.rsplit_once(':') {
+            Some((host, port)) => (host.to_string(), port.parse().unwrap_or(2333)),
+            None => (address.to_string(), 2333),
+        })
+        .collect()
+}
+
+/// Whether at least one node is connected. Music interactions are refused otherwise
+pub async fn music_backend_available(data: &Arc<RwLock<TypeMap>>) -> bool {
+    let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+    let nodes = nodes.read().await;
+
+    nodes.is_available()
+}
+
+pub async fn lavalink_client_for_guild(
+    data: &Arc<RwLock<TypeMap>>,
+    guild_id: u64,
+) -> Option<LavalinkClient> {
+    let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+    let mut nodes = nodes.write().await;
+
+    nodes.client_for_guild(guild_id)
+}
+
+/// Called from `LavalinkHandler::stats`
+pub async fn update_node_stats(data: &Arc<RwLock<TypeMap>>, node: usize, stats: &Stats) {
+    let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+    let mut nodes = nodes.write().await;
+
+    if let Some(node) = nodes.nodes.get_mut(node) {
+        if node.client.is_none() {
+            return;
+        }
+        if !node.healthy {
+            info!("Lavalink node {}:{} is back", node.host, node.port);
+        }
+        node.healthy = true;
+        node.stats = Some(NodeStats {
+            playing_players: stats.playing_players as u64,
+            system_load: stats.cpu.system_load,
+            received_at: Instant::now(),
+        });
+    }
+}
+
+/// Keeps the node connected. Retries with an exponential backoff while it is down
+pub fn start_node_connection(data: Arc<RwLock<TypeMap>>, index: usize, host: String, port: u16) {
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let healthy = {
+                let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+                let nodes = nodes.read().await;
+                nodes.nodes[index].healthy
+            };
+            if healthy {
+                backoff = INITIAL_BACKOFF;
+                tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+                continue;
+            }
+
+            match connect_lavalink_node(&host, port, data.clone(), index).await {
+                Ok(client) => {
+                    {
+                        let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+                        nodes.write().await.set_client(index, client);
+                    }
+                    restore_pending_guilds(&data).await;
+                }
+                Err(err) => {
+                    info!(
+                        "Cannot connect to lavalink {}:{}, retrying in {}s: {}",
+                        host,
+                        port,
+                        backoff.as_secs(),
+                        err
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
+
+/// Periodically checks that every node still sends stats and moves the guilds of the nodes that
+/// stopped to the other ones
+pub fn start_health_check(data: Arc<RwLock<TypeMap>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let down = {
+                let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+                let mut nodes = nodes.write().await;
+                let mut down = Vec::new();
+                for (index, node) in nodes.nodes.iter_mut().enumerate() {
+                    if node.healthy && node.last_seen().elapsed() > STATS_TIMEOUT {
+                        warn!(
+                            "Lavalink node {}:{} stopped responding",
+                            node.host, node.port
+                        );
+                        node.healthy = false;
+                        down.push(index);
+                    }
+                }
+                down
+            };
+
+            for index in down {
+                migrate_guilds(&data, index).await;
+            }
+        }
+    });
+}
+
+/// Moves the player and queue of every guild on the node to the least loaded healthy node. Guilds
+/// are kept pending when there is none left
+pub async fn migrate_guilds(data: &Arc<RwLock<TypeMap>>, from: usize) {
+    let (nodes, guild_track) = {
+        let data = data.read().await;
+        (
+            data.get::<Lavalink>().cloned().unwrap(),
+            data.get::<GuildTrackMap>().cloned().unwrap(),
+        )
+    };
+
+    // Taken now, the node is gone and the tracks do not advance anymore
+    let positions = positions_at_drop(&guild_track).await;
+    let (old_client, moves) = {
+        let mut nodes = nodes.write().await;
+        let old_client = nodes.nodes[from].client.clone();
+        let guilds: Vec<u64> = nodes
+            .guilds
+            .iter()
+            .filter(|(_, node)| **node == from)
+            .map(|(guild_id, _)| *guild_id)
+            .collect();
+
+        let mut moves = Vec::new();
+        for guild_id in guilds {
+            nodes.guilds.remove(&guild_id);
+            let position = positions.get(&guild_id).copied().unwrap_or(0);
+            match (
+                nodes.client_for_guild(guild_id),
+                nodes.connections.get(&guild_id).cloned(),
+            ) {
+                (Some(client), Some(connection_info)) => {
+                    moves.push((guild_id, client, connection_info, position))
+                }
+                (None, _) => {
+                    warn!(
+                        "No lavalink node left for guild {}, waiting for one",
+                        guild_id
+                    );
+                    nodes.add_pending_guild(guild_id, old_client.clone(), position);
+                }
+                (_, None) => {
+                    warn!("No voice connection to move for guild {}", guild_id);
+                }
+            }
+        }
+
+        (old_client, moves)
+    };
+
+    for (guild_id, new_client, connection_info, position) in moves {
+        migrate_guild(
+            old_client.as_ref(),
+            &new_client,
+            guild_id,
+            &connection_info,
+            position,
+        )
+        .await;
+    }
+}
+
+/// Creates the players of the guilds that were left without a node. Called when a node connects
+async fn restore_pending_guilds(data: &Arc<RwLock<TypeMap>>) {
+    let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+
+    let restores = {
+        let mut nodes = nodes.write().await;
+        let pending: Vec<(u64, (Option<LavalinkClient>, u64))> = nodes.pending.drain().collect();
+
+        let mut restores = Vec::new();
+        for (guild_id, (old_client, position)) in pending {
+            match (
+                nodes.client_for_guild(guild_id),
+                nodes.connections.get(&guild_id).cloned(),
+            ) {
+                (Some(client), Some(connection_info)) => {
+                    restores.push((guild_id, old_client, client, connection_info, position))
+                }
+                (None, _) => nodes.add_pending_guild(guild_id, old_client, position),
+                (_, None) => {
+                    warn!("No voice connection to restore for guild {}", guild_id);
+                }
+            }
+        }
+
+        restores
+    };
+
+    for (guild_id, old_client, new_client, connection_info, position) in restores {
+        migrate_guild(
+            old_client.as_ref(),
+            &new_client,
+            guild_id,
+            &connection_info,
+            position,
+        )
+        .await;
+    }
+}
+
+/// Where the current track of every guild is when a node is lost. The node stopped reporting, so
+/// at most one update interval passed since the last position it sent
+async fn positions_at_drop(
+    guild_track: &Arc<Mutex<HashMap<u64, GuildTrack>>>,
+) -> HashMap<u64, u64> {
+    let mutex_guard = guild_track.lock().await;
+    mutex_guard
+        .iter()
+        .map(|(guild_id, guild_track)| {
+            (
+                *guild_id,
+                position_at_drop(guild_track.position, guild_track.how_long.elapsed()),
+            )
+        })
+        .collect()
+}
+
+pub(crate) fn position_at_drop(reported: i64, since_report: Duration) -> u64 {
+    reported.max(0) as u64 + since_report.min(PLAYER_UPDATE_INTERVAL).as_millis() as u64
+}
+
+/// Creates the session of the guild on `new_client` and queues again what was on `old_client`
+async fn migrate_guild(
+    old_client: Option<&LavalinkClient>,
+    new_client: &LavalinkClient,
+    guild_id: u64,
+    connection_info: &ConnectionInfo,
+    position: u64,
+) {
+    // The node state is kept locally so it is still there even if the node is gone
+    let (queue, volume) = match old_client {
+        Some(old_client) => {
+            old_client.loops().await.remove(&guild_id);
+            match old_client.nodes().await.remove(&guild_id) {
+                Some((_, node)) => (node.queue, node.volume),
+                None => (Vec::new(), 100),
+            }
+        }
+        None => (Vec::new(), 100),
+    };
+
+    match new_client
+        .create_session_with_songbird(connection_info)
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            warn!(
+                "Cannot create lavalink session for guild {}: {}",
+                guild_id, err
+            );
+            return;
+        }
+    }
+
+    for (i, queued) in queue.into_iter().enumerate() {
+        // The first track of the queue is the one that was playing
+        let start_time = if i == 0 { position } else { queued.start_time };
+        let mut play = new_client
+            .play(guild_id, queued.track)
+            .start_time(Duration::from_millis(start_time));
+        if let Some(end_time) = queued.end_time {
+            play = play.finish_time(Duration::from_millis(end_time));
+        }
+        if let Some(requester) = queued.requester {
+            play = play.requester(requester);
+        }
+
+        match play.queue().await {
+            Ok(_) => {}
+            Err(err) => {
+                warn!(
+                    "Cannot queue track while moving guild {}: {}",
+                    guild_id, err
+                );
+            }
+        }
+    }
+
+    let _ = new_client.volume(guild_id, volume).await;
+    info!("Moved guild {} to another lavalink node", guild_id);
+}
@@ -1,6 +1,3 @@
-use std::sync::Arc;
-
-use mysql_async::{prelude::Queryable, Row};
 use serenity::{
     client::Context,
     model::{
@@ -12,34 +9,40 @@ use serenity::{
         },
     },
 };
-use songbird::input::Input;
 use tracing::{info, warn};
 
 use crate::{
-    database::get_conn_from_ctx,
+    database::jam::{get_jam_track_by_id, remove_jam_track_by_id},
     events::interactions::{
-        get_songbird_manager, helpers::get_guild_channel_id_from_interaction_message,
-        interactions::ffmpeg_input_from_string,
+        get_songbird_manager,
+        helpers::{
+            get_guild_channel_id_from_interaction_message, join_or_get_voice_channel, member_is_dj,
+            CANNOT_JOIN_VOICE,
+        },
+    },
+    features::{
+        jam::{jam_rating_buttons, jam_track_uri, pick_jam_track, queue_jam_track},
+        sleep::sleep_timer_status,
     },
+    helpers::db_helper::get_pool_from_ctx,
     GuildTrackMap,
 };
 
-use super::{
-    helpers::{add_events_to_handle, misc_handle},
-    lavalink::get_lavalink_client,
-};
+use super::lavalink::{get_lavalink_client, nodes::MUSIC_BACKEND_UNAVAILABLE};
 
-struct JamIt {
-    // guild_id: u64,
-    audio_name: String,
-    ext: String,
-}
+/// Followed by the id of the jam track the message was posted for
+pub const DELETE_AND_SKIP: &str = "delete_and_skip_";
 
 async fn play_audio_from_string_from_message_component(
     command: &MessageComponentInteraction,
     ctx: &Context,
     title: &str,
+    jam_id: u64,
 ) {
+    let sleep_status = match command.guild_id {
+        Some(guild_id) => sleep_timer_status(ctx, guild_id.0).await,
+        None => String::new(),
+    };
     let a = command
         .edit_original_interaction_response(&ctx.http, |response| {
             response
@@ -81,15 +84,16 @@ async fn play_audio_from_string_from_message_component(
                     })
                     .create_action_row(|row| {
                         row.create_button(|btn| {
-                            btn.custom_id("delete_and_skip")
+                            btn.custom_id(format!("{}{}", DELETE_AND_SKIP, jam_id))
                                 .emoji(ReactionType::Unicode("❌".to_string()))
                                 .style(ButtonStyle::Secondary)
-                        })
+                        });
+                        jam_rating_buttons(row, jam_id)
                     })
                 })
                 .content(format!(
-                    "Giga Jamming: {} - TODO: special buttons for this",
-                    title
+                    "Giga Jamming: {} - TODO: special buttons for this{}",
+                    title, sleep_status
                 ))
         })
         .await;
@@ -113,23 +117,39 @@ pub async fn handle_jam_it(ctx: &Context, command: &MessageComponentInteraction)
         Some(value) => value,
         None => return,
     };
-    let mut conn = get_conn_from_ctx(ctx).await;
-    let query = format!(
-        "SELECT guild_id, audio_name, ext FROM jam_it WHERE guild_id = {} ORDER BY RAND() LIMIT 1",
-        guild_id
-    );
-    let result: Vec<Row> = match conn.query(query).await {
-        Ok(ok) => ok,
-        Err(_) => {
-            // TODO: handle error
+    let pool = get_pool_from_ctx(ctx).await;
+    let track = match pick_jam_track(&pool, guild_id.0).await {
+        Some(track) => track,
+        None => {
+            // No result
+            match command
+                .edit_original_interaction_response(ctx, |f| f.content("No tracks present to jam"))
+                .await
+            {
+                Ok(_) => {}
+                Err(err) => {
+                    panic!("cannot send response err: {}", err)
+                }
+            };
             return;
         }
     };
 
-    if result.is_empty() {
-        // No result
+    if join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id)
+        .await
+        .is_err()
+    {
+        if let Err(err) = command
+            .edit_original_interaction_response(ctx, |f| f.content(CANNOT_JOIN_VOICE))
+            .await
+        {
+            println!("Cannot respond to message component {}", err);
+        }
+        return;
+    }
+    if let Err(content) = queue_jam_track(ctx, guild_id, &track, command.user.id).await {
         match command
-            .edit_original_interaction_response(ctx, |f| f.content("No tracks present to jam"))
+            .edit_original_interaction_response(ctx, |f| f.content(content))
             .await
         {
             Ok(_) => {}
@@ -137,45 +157,13 @@ pub async fn handle_jam_it(ctx: &Context, command: &MessageComponentInteraction)
                 panic!("cannot send response err: {}", err)
             }
         };
-    } else {
-        let mut jam: Vec<JamIt> = Vec::new();
-
-        for mut data in result {
-            let j = JamIt {
-                // guild_id: data.take("guild_id").expect("cannot get guild_id"),
-                audio_name: data.take("audio_name").expect("cannot get audio name"),
-                ext: data.take("ext").expect("cannot get ext"),
-            };
-            jam.push(j);
-        }
-        // TODO: Will not work if we plan on playing more than 1 audio
-        let ffmpeg = ffmpeg_input_from_string(&jam[0].audio_name, &jam[0].ext).await;
-        let manager = get_songbird_manager(ctx).await;
-
-        let mut new_input = songbird::input::Input::from(ffmpeg);
-        new_input.metadata.title = Some(jam[0].audio_name.to_owned());
-
-        match manager.get(guild_id) {
-            Some(handle_lock) => {
-                let mut handle = handle_lock.lock().await;
-
-                handle.enqueue_source(new_input);
-                play_audio_from_string_from_message_component(command, ctx, &jam[0].audio_name)
-                    .await;
-            }
-            None => {
-                handle_no_handle_from_message_component(
-                    manager, guild_id, connect_to, ctx, command, new_input,
-                )
-                .await;
-                play_audio_from_string_from_message_component(command, ctx, &jam[0].audio_name)
-                    .await;
-            }
-        };
+        return;
     }
+
+    play_audio_from_string_from_message_component(command, ctx, &track.audio_name, track.id).await;
 }
 
-async fn not_in_a_voice_channel_message(
+pub async fn not_in_a_voice_channel_message(
     channel_id: Option<ChannelId>,
     command: &MessageComponentInteraction,
     ctx: &Context,
@@ -194,58 +182,64 @@ async fn not_in_a_voice_channel_message(
     Some(connect_to)
 }
 
+/// ❌ on a jam message. Removes the track the message was posted for, and skips it if it is the
+/// one playing
 pub async fn handle_delete_and_skip_from_jam(ctx: &Context, command: &MessageComponentInteraction) {
-    // TODO: lock is aquired in each function
-    handle_next_audio_in_queue(ctx, command).await;
-
-    delete_from_jam(command, ctx).await;
-}
-
-async fn delete_from_jam(command: &MessageComponentInteraction, ctx: &Context) {
-    let (guild_id, channel_id) = get_guild_channel_id_from_interaction_message(command, ctx).await;
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    // Same gate as /jam remove
+    if !member_is_dj(ctx, guild_id, command.member.as_ref()).await {
+        send_interaction_message_ephemeral(command, ctx, "Only DJs can remove jam tracks").await;
+        return;
+    }
 
-    let _connect_to = match not_in_a_voice_channel_message(channel_id, command, ctx).await {
-        Some(value) => value,
-        None => return,
+    let pool = get_pool_from_ctx(ctx).await;
+    let track = match command
+        .data
+        .custom_id
+        .trim_start_matches(DELETE_AND_SKIP)
+        .parse::<u64>()
+    {
+        Ok(id) => get_jam_track_by_id(&pool, guild_id.0, id).await,
+        Err(_) => None,
     };
-    let manager = get_songbird_manager(ctx).await;
-
-    match manager.get(guild_id) {
-        Some(handle_lock) => {
-            let handle = handle_lock.lock().await;
-            let queue = handle.queue().clone();
-            // Try to set the currently playing song volume
-            // TODO: Proper error handling
-            let current = queue.current().expect("expected a current track");
-            let title = current
-                .metadata()
-                .clone()
-                .title
-                .expect("expected a title from metedata");
-
-            let mut conn = get_conn_from_ctx(ctx).await;
-
-            let query = format!(
-                "DELETE FROM jam_it WHERE jam_it.audio_name = '{}' AND jam_it.guild_id = {}",
-                title, guild_id.0
-            );
-
-            let _: Vec<usize> = match conn.query(query).await {
-                Ok(ok) => ok,
-                Err(err) => {
-                    panic!("error when trying to delete: {}", err)
-                }
-            };
-        }
+    let track = match track {
+        Some(track) => track,
         None => {
-            send_interaction_message_basic_from_message(
-                ctx,
+            send_interaction_message_ephemeral(
                 command,
-                "bot is not present in the channel",
+                ctx,
+                "This track is no longer in the jam library",
             )
             .await;
+            return;
         }
     };
+
+    // Read what plays before removing the track, its file may go with it
+    let uri = jam_track_uri(&pool, &track).await;
+    let playing = match (uri, get_lavalink_client(ctx, guild_id).await) {
+        (Some(uri), Some(lavalink)) => match lavalink.nodes().await.get(&guild_id.0) {
+            Some(node) => node
+                .now_playing
+                .as_ref()
+                .and_then(|now_playing| now_playing.track.info.as_ref())
+                .map_or(false, |info| info.uri == uri),
+            None => false,
+        },
+        _ => false,
+    };
+
+    remove_jam_track_by_id(&pool, guild_id.0, track.id).await;
+    if playing {
+        handle_next_audio_in_queue(ctx, command).await;
+    } else {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            format!("Removed {} from the jam library", track.audio_name).as_str(),
+        )
+        .await;
+    }
 }
 
 pub async fn handle_next_audio_in_queue(
@@ -254,7 +248,13 @@ pub async fn handle_next_audio_in_queue(
 ) {
     let (guild_id, _channel_id) = get_guild_channel_id_from_interaction_message(command, ctx).await;
     let manager = get_songbird_manager(ctx).await;
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
 
     match manager.get(guild_id) {
         Some(_handle_lock) => {
@@ -331,49 +331,6 @@ pub async fn handle_next_audio_in_queue(
     };
 }
 
-async fn handle_no_handle_from_message_component(
-    manager: Arc<songbird::Songbird>,
-    guild_id: serenity::model::id::GuildId,
-    connect_to: ChannelId,
-    ctx: &Context,
-    command: &MessageComponentInteraction,
-    new_input: Input,
-) {
-    let (handle_lock, handler) = manager.join_gateway(guild_id, connect_to).await;
-    match handler {
-        Ok(connection_info) => {
-            let mut handle = handle_lock.lock().await;
-
-            misc_handle(ctx, connection_info, guild_id).await;
-            add_events_to_handle(&handle_lock, ctx, command.channel_id, guild_id).await;
-
-            handle.enqueue_source(new_input);
-        }
-        Err(why) => {
-            panic!("Cannot join voice channel: {}", why)
-        }
-    }
-}
-
-async fn send_interaction_message_basic_from_message(
-    ctx: &Context,
-    command: &MessageComponentInteraction,
-    content: &str,
-) {
-    match command
-        .create_interaction_response(ctx, |f| {
-            f.kind(InteractionResponseType::ChannelMessageWithSource)
-                .interaction_response_data(|message| message.content(content))
-        })
-        .await
-    {
-        Ok(_) => {}
-        Err(err) => {
-            panic!("cannot send response err: {}", err)
-        }
-    };
-}
-
 pub async fn hanle_fast_forward_audio(
     ctx: &Context,
     command: &MessageComponentInteraction,
@@ -381,7 +338,13 @@ pub async fn hanle_fast_forward_audio(
 ) {
     let (guild_id, _channel_id) = get_guild_channel_id_from_interaction_message(command, ctx).await;
     let manager = get_songbird_manager(ctx).await;
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
 
     match manager.get(guild_id) {
         Some(_handle_lock) => {
@@ -495,7 +458,13 @@ pub async fn hanle_fast_forward_audio(
 
 pub async fn handle_stop_audio(ctx: &Context, command: &MessageComponentInteraction) {
     let (guild_id, _channel_id) = get_guild_channel_id_from_interaction_message(command, ctx).await;
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
     let manager = get_songbird_manager(ctx).await;
 
     match manager.get(guild_id) {
@@ -554,7 +523,13 @@ pub async fn handle_stop_audio(ctx: &Context, command: &MessageComponentInteract
 pub async fn handle_play_pause_audio(ctx: &Context, command: &MessageComponentInteraction) {
     let (guild_id, _channel_id) = get_guild_channel_id_from_interaction_message(command, ctx).await;
     let manager = get_songbird_manager(ctx).await;
-    let lavalink = get_lavalink_client(ctx).await;
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
 
     match manager.get(guild_id) {
         Some(_handle_lock) => {
@@ -621,6 +596,46 @@ pub async fn send_defered_response(command: &MessageComponentInteraction, ctx: &
     false
 }
 
+pub(crate) async fn edit_original_response_simple_content(
+    command: &MessageComponentInteraction,
+    ctx: &Context,
+    content: &str,
+) {
+    match command
+        .edit_original_interaction_response(ctx, |response| response.content(content))
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err)
+        }
+    };
+}
+
+/// Like `send_defered_response` but only the member who clicked sees the answer
+pub async fn send_defered_response_ephemeral(
+    command: &MessageComponentInteraction,
+    ctx: &Context,
+) -> bool {
+    match command
+        .create_interaction_response(&ctx.http, |response| {
+            response
+                .kind(InteractionResponseType::DeferredChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err);
+            return true;
+        }
+    }
+    false
+}
+
 pub async fn send_interaction_message_basic(
     command: &MessageComponentInteraction,
     ctx: &Context,
@@ -639,3 +654,26 @@ pub async fn send_interaction_message_basic(
         }
     };
 }
+
+pub async fn send_interaction_message_ephemeral(
+    command: &MessageComponentInteraction,
+    ctx: &Context,
+    content: &str,
+) {
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err)
+        }
+    };
+}
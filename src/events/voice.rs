@@ -1,7 +1,7 @@
 use serenity::client::Context;
 use tracing::info;
 
-use crate::config::APPLICATION_ID;
+use crate::{config::APPLICATION_ID, features};
 
 use super::interactions::{get_songbird_manager, lavalink::get_lavalink_client};
 
@@ -14,9 +14,17 @@ pub async fn voice_server_update(
 pub async fn voice_state_update(
     ctx: Context,
     guild_id: Option<serenity::model::id::GuildId>,
-    _old_state: Option<serenity::model::prelude::VoiceState>,
+    old_state: Option<serenity::model::prelude::VoiceState>,
     new_state: serenity::model::prelude::VoiceState,
 ) {
+    if let Some(guild_id) = guild_id {
+        features::voice_log::on_voice_state_update(&ctx, guild_id, old_state.as_ref(), &new_state)
+            .await;
+        features::voice_stats::on_voice_state_update(&ctx, guild_id, &new_state).await;
+        features::boss_music::on_voice_state_update(&ctx, guild_id, old_state.as_ref(), &new_state)
+            .await;
+    }
+
     // if new_state.user_id.0 == APPLICATION_ID && new_state.channel_id.is_none() {
     // let manager = get_songbird_manager(&ctx).await;
     // let lavalink = get_lavalink_client(&ctx).await;
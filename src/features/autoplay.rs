@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use lavalink_rs::LavalinkClient;
+use serenity::{
+    client::Context,
+    model::interactions::application_command::{
+        ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+    },
+    prelude::{RwLock, TypeMap},
+};
+use tracing::{info, warn};
+
+use crate::{
+    database::{
+        history::pick_radio_track,
+        jam::get_jam_track,
+        settings::{get_autoplay, set_autoplay},
+    },
+    events::interactions::application_command::send_interaction_message_basic,
+    features::jam::play_jam_track,
+    helpers::db_helper::get_pool_from_ctx,
+    MysqlConnection,
+};
+
+// How many of the last played tracks autoplay will not pick again
+const AVOID_RECENT: u64 = 20;
+
+/// `/autoplay [enabled]`. Toggles when no value is given
+pub async fn handle_autoplay(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+
+    let enabled = match command
+        .data
+        .options
+        .get(0)
+        .and_then(|option| option.resolved.as_ref())
+    {
+        Some(ApplicationCommandInteractionDataOptionValue::Boolean(enabled)) => *enabled,
+        _ => !get_autoplay(&pool, guild_id.0).await,
+    };
+
+    set_autoplay(&pool, guild_id.0, enabled).await;
+
+    send_interaction_message_basic(
+        command,
+        ctx,
+        if enabled {
+            "Autoplay enabled. I'll keep playing from the jam library and history when the queue runs out"
+        } else {
+            "Autoplay disabled"
+        },
+    )
+    .await;
+}
+
+/// Called from `LavalinkHandler::track_finish`. Queues something new when the last track of the
+/// queue ended and autoplay is enabled for the guild
+pub async fn on_track_finish(
+    data: &Arc<RwLock<TypeMap>>,
+    lavalink: &LavalinkClient,
+    guild_id: u64,
+    track: &str,
+    reason: &str,
+) {
+    // Only when the track ended by itself. Stopping or skipping should not start the radio
+    if reason != "FINISHED" {
+        return;
+    }
+
+    let queue_is_empty = match lavalink.nodes().await.get(&guild_id) {
+        // The finished track can still be at the front of the queue
+        Some(node) => node.queue.iter().all(|queued| queued.track.track == track),
+        None => return,
+    };
+    if !queue_is_empty {
+        return;
+    }
+
+    let pool = data.read().await.get::<MysqlConnection>().cloned().unwrap();
+    if !get_autoplay(&pool, guild_id).await {
+        return;
+    }
+
+    let (title, uri) = match pick_radio_track(&pool, guild_id, AVOID_RECENT).await {
+        Some(track) => track,
+        None => {
+            info!("Nothing to autoplay in guild {}", guild_id);
+            return;
+        }
+    };
+
+    // Tracks that only exist in jam_it have no uri, they play from the media store
+    if uri.is_empty() {
+        match get_jam_track(&pool, guild_id, &title).await {
+            Some(track) => match play_jam_track(&pool, lavalink, guild_id, &track, None).await {
+                Ok(_) => info!("Autoplay queued {} in guild {}", title, guild_id),
+                Err(err) => warn!("Autoplay cannot play {}: {}", title, err),
+            },
+            None => info!("Autoplay pick {} left the jam library", title),
+        }
+        return;
+    }
+
+    match lavalink.get_tracks(&uri).await {
+        Ok(tracks) if !tracks.tracks.is_empty() => {
+            match lavalink
+                .play(guild_id, tracks.tracks[0].clone())
+                .queue()
+                .await
+            {
+                Ok(_) => {
+                    info!("Autoplay queued {} in guild {}", title, guild_id);
+                }
+                Err(err) => {
+                    warn!("Autoplay cannot play track: {}", err);
+                }
+            }
+        }
+        Ok(_) => {
+            info!("Autoplay found no results for {}", title);
+        }
+        Err(err) => {
+            warn!("Autoplay cannot load {}: {}", title, err);
+        }
+    }
+}
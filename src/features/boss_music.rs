@@ -1,5 +1,1142 @@
-pub async fn get_user_boss_music() {}
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-pub async fn add_user_boss_music() {}
+use lavalink_rs::{model::TrackQueue, LavalinkClient};
+use mysql_async::Pool;
+use serenity::{
+    client::Context,
+    model::{
+        channel::ChannelType,
+        id::{ChannelId, GuildId, UserId},
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+                ApplicationCommandInteractionDataOptionValue,
+            },
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+        },
+        prelude::VoiceState,
+    },
+    prelude::{Mutex, RwLock, TypeMap, TypeMapKey},
+};
+use tracing::{info, warn};
 
-pub async fn update_user_boss_music() {}
+use crate::{
+    database::{
+        boss_music::{
+            add_boss_music_channel, add_boss_music_submission, clear_boss_music_channels,
+            count_boss_music_submissions, get_all_boss_music_songs, get_boss_music_channels,
+            get_boss_music_submission, get_boss_music_theme, get_guild_boss_music_users,
+            get_next_boss_music_submission, get_user_boss_music_submission,
+            remove_boss_music_channel, remove_boss_music_submission, remove_boss_music_theme,
+            set_boss_music_theme,
+        },
+        settings::{
+            get_boss_music_approval, get_boss_music_enabled, set_boss_music_approval,
+            set_boss_music_enabled,
+        },
+    },
+    events::interactions::{
+        application_command::{
+            edit_original_response_simple_content, get_option_by_name, get_seconds_option,
+            get_subcommand, insert_with_priority, send_defered_response,
+            send_interaction_message_ephemeral, QueuePriority,
+        },
+        get_songbird_manager,
+        helpers::{join_or_get_voice_channel, member_can_manage_guild},
+        lavalink::{get_lavalink_client, nodes::music_backend_available},
+    },
+    features::{
+        clip::is_listening,
+        recording::is_recording,
+        sleep::stop_and_leave,
+        soundboard::{is_mixing, is_soundboard_uri},
+    },
+    helpers::{
+        db_helper::get_pool_from_ctx,
+        main::{
+            self, download_attachment, env_or, max_upload_bytes, probe_audio_duration,
+            store_opus_clip,
+        },
+        media_server::media_url,
+        media_store::{download_section_to_staging, media_root, STAGING_DIR},
+    },
+    GuildTrackMap, HasBossMusic,
+};
+
+pub const BOSS_MUSIC_APPROVE: &str = "boss_music_approve_";
+pub const BOSS_MUSIC_REJECT: &str = "boss_music_reject_";
+
+const BOSS_MUSIC_DIR: &str = "boss_music";
+// Themes saved for this guild are the defaults of their users
+const DEFAULT_THEME_GUILD: u64 = 0;
+// Keeps /bossmusic list under the message length limit
+const BOSS_MUSIC_LIST_LIMIT: usize = 50;
+const DEFAULT_COOLDOWN_SECS: u64 = 300;
+const DEFAULT_MAX_CLIP_SECS: f64 = 15.0;
+const DEFAULT_MAX_UPLOAD_MB: u64 = 8;
+const URL_DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// When each (guild, user) last had their theme played
+pub struct BossMusicCooldowns;
+impl TypeMapKey for BossMusicCooldowns {
+    type Value = Arc<Mutex<HashMap<(u64, u64), Instant>>>;
+}
+
+/// Themes are kept in the media store so the media server can hand them to lavalink
+pub fn boss_music_dir() -> PathBuf {
+    media_root().join(BOSS_MUSIC_DIR)
+}
+
+/// Themes set before they were per guild live in LEGACY_BOSS_MUSIC_DIR. Copies the ones still in
+/// use into `boss_music_dir`, the old files are left for an admin to remove
+pub async fn copy_legacy_boss_music(ctx: &Context) {
+    let legacy_dir = match std::env::var("LEGACY_BOSS_MUSIC_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => return,
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let songs = get_all_boss_music_songs(&pool).await;
+    let copied = copy_legacy_themes(&songs, &legacy_dir, &boss_music_dir()).await;
+    if copied > 0 {
+        info!(
+            "Copied {} boss music themes from {}",
+            copied,
+            legacy_dir.display()
+        );
+    }
+}
+
+/// Copies the `songs` found in `from` and missing from `to`, returns how many were copied
+pub(crate) async fn copy_legacy_themes(songs: &[String], from: &Path, to: &Path) -> usize {
+    if let Err(err) = tokio::fs::create_dir_all(to).await {
+        warn!("Cannot create {}: {}", to.display(), err);
+        return 0;
+    }
+
+    let mut copied = 0;
+    for song in songs {
+        // Names come from the database, they must stay inside both directories
+        if Path::new(song).file_name() != Some(song.as_ref()) {
+            continue;
+        }
+
+        let target = to.join(song);
+        if tokio::fs::metadata(&target).await.is_ok() {
+            continue;
+        }
+
+        let source = from.join(song);
+        if tokio::fs::metadata(&source).await.is_err() {
+            continue;
+        }
+
+        match tokio::fs::copy(&source, &target).await {
+            Ok(_) => copied += 1,
+            Err(err) => warn!("Cannot copy {}: {}", source.display(), err),
+        }
+    }
+
+    copied
+}
+
+/// True if lavalink is playing an entrance theme
+pub fn is_boss_music_uri(uri: &str) -> bool {
+    match media_url(&boss_music_dir()) {
+        Some(url) => uri.starts_with(&format!("{}/", url)),
+        None => false,
+    }
+}
+
+/// `/bossmusic set <file> [start] [duration] [default]`,
+/// `/bossmusic url <link> <start> <duration> [default]`, `/bossmusic show`, `/bossmusic preview`,
+/// `/bossmusic clear [default]` and for admins `/bossmusic list`, `/bossmusic pending`,
+/// `/bossmusic approval [enabled]`, `/bossmusic channel [channel]` and `/bossmusic toggle [enabled]`
+pub async fn handle_boss_music(ctx: &Context, command: &ApplicationCommandInteraction) {
+    match get_subcommand(command) {
+        Some(("set", options)) => handle_boss_music_set(ctx, command, options).await,
+        Some(("url", options)) => handle_boss_music_url(ctx, command, options).await,
+        Some(("show", _)) => handle_boss_music_show(ctx, command).await,
+        Some(("preview", _)) => handle_boss_music_preview(ctx, command).await,
+        Some(("clear", options)) => handle_boss_music_clear(ctx, command, options).await,
+        Some(("list", _)) => handle_boss_music_list(ctx, command).await,
+        Some(("pending", _)) => handle_boss_music_pending(ctx, command).await,
+        Some(("approval", options)) => handle_boss_music_approval(ctx, command, options).await,
+        Some(("channel", options)) => handle_boss_music_channel(ctx, command, options).await,
+        Some(("toggle", options)) => handle_boss_music_toggle(ctx, command, options).await,
+        _ => send_interaction_message_ephemeral(command, ctx, "Unknown bossmusic command").await,
+    }
+}
+
+/// The `default` option of set, url and clear: the theme used in every guild without its own
+fn default_option(options: &[ApplicationCommandInteractionDataOption]) -> bool {
+    matches!(
+        get_option_by_name(options, "default"),
+        Some(ApplicationCommandInteractionDataOptionValue::Boolean(true))
+    )
+}
+
+/// Makes an uploaded file the theme of the member
+async fn handle_boss_music_set(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let attachment = match get_option_by_name(options, "file") {
+        Some(ApplicationCommandInteractionDataOptionValue::Attachment(attachment)) => attachment,
+        _ => {
+            send_interaction_message_ephemeral(command, ctx, "Attach an audio file").await;
+            return;
+        }
+    };
+    let max_clip_secs = env_or("BOSS_MUSIC_MAX_SECONDS", DEFAULT_MAX_CLIP_SECS);
+    let (start, duration) = match clip_bounds(ctx, command, options, max_clip_secs).await {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    let max_upload_bytes = max_upload_bytes("BOSS_MUSIC_MAX_UPLOAD_MB", DEFAULT_MAX_UPLOAD_MB);
+    if attachment.size > max_upload_bytes {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            format!(
+                "The file can be at most {} MB",
+                max_upload_bytes / 1024 / 1024
+            )
+            .as_str(),
+        )
+        .await;
+        return;
+    }
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    let upload = media_root()
+        .join(STAGING_DIR)
+        .join(format!("bossmusic-{}-{}", guild_id.0, command.user.id.0));
+    let result = match download_attachment(&attachment.url, &upload, max_upload_bytes).await {
+        Ok(_) => store_boss_clip(guild_id, command.user.id, &upload, start, duration).await,
+        Err(err) => Err(err),
+    };
+    let _ = tokio::fs::remove_file(&upload).await;
+
+    reply_with_clip(ctx, command, result, default_option(options)).await;
+}
+
+/// Cuts the theme out of a video or a track yt-dlp can download
+async fn handle_boss_music_url(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let link = match get_option_by_name(options, "link") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(link))
+            if link.starts_with("https://") || link.starts_with("http://") =>
+        {
+            link.trim()
+        }
+        _ => {
+            send_interaction_message_ephemeral(command, ctx, "Provide a link").await;
+            return;
+        }
+    };
+    let max_clip_secs = env_or("BOSS_MUSIC_MAX_SECONDS", DEFAULT_MAX_CLIP_SECS);
+    let (start, duration) = match clip_bounds(ctx, command, options, max_clip_secs).await {
+        Some(bounds) => bounds,
+        None => return,
+    };
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    // Only the clip is downloaded, under a name no other download uses
+    let name = format!(
+        "bossmusic-{}-{}-{}",
+        guild_id.0,
+        command.user.id.0,
+        chrono::Utc::now().timestamp_millis()
+    );
+    let download = download_section_to_staging(link, &name, start, duration);
+    let result = match tokio::time::timeout(URL_DOWNLOAD_TIMEOUT, download).await {
+        Ok(Ok(downloaded)) => {
+            let result =
+                store_boss_clip(guild_id, command.user.id, &downloaded, 0.0, duration).await;
+            let _ = tokio::fs::remove_file(&downloaded).await;
+            result
+        }
+        Ok(Err(err)) => {
+            warn!("Cannot download boss music {}: {}", link, err);
+            Err("cannot download the link".to_string())
+        }
+        Err(_) => Err("the download took too long".to_string()),
+    };
+
+    reply_with_clip(ctx, command, result, default_option(options)).await;
+}
+
+/// Reads the start and the duration of a clip, the soundboard uses it too. Replies and returns
+/// `None` when they are out of the limits
+pub(crate) async fn clip_bounds(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+    max_clip_secs: f64,
+) -> Option<(f64, f64)> {
+    let start = get_seconds_option(options, "start").unwrap_or(0.0);
+    let duration = get_seconds_option(options, "duration").unwrap_or(max_clip_secs);
+
+    if start < 0.0 || duration <= 0.0 || duration > max_clip_secs {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            format!(
+                "The clip has to start after 0 and last at most {} seconds",
+                max_clip_secs
+            )
+            .as_str(),
+        )
+        .await;
+        return None;
+    }
+
+    Some((start, duration))
+}
+
+/// Converts the theme a member provided and makes it theirs. Returns the file name and the length
+/// of the clip
+async fn store_boss_clip(
+    guild_id: GuildId,
+    user_id: UserId,
+    input: &Path,
+    start: f64,
+    duration: f64,
+) -> Result<(String, f64), String> {
+    // A new name for every clip so a preview never shows an older one
+    let file_name = format!(
+        "{}-{}-{}.ogg",
+        guild_id.0,
+        user_id.0,
+        chrono::Utc::now().timestamp_millis()
+    );
+    let length = store_opus_clip(input, &boss_music_dir(), &file_name, start, duration).await?;
+
+    Ok((file_name, length))
+}
+
+/// Saves the new theme, or submits it when the guild reviews themes, and answers with a player to
+/// preview it. Defaults are saved right away, they never play in guilds that review themes
+async fn reply_with_clip(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    result: Result<(String, f64), String>,
+    default: bool,
+) {
+    let (file_name, length) = match result {
+        Ok(clip) => clip,
+        Err(err) => {
+            edit_original_response_simple_content(
+                command,
+                ctx,
+                format!("Cannot use this file: {}", err).as_str(),
+            )
+            .await;
+            return;
+        }
+    };
+
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+    let content = if default {
+        save_boss_music(ctx, DEFAULT_THEME_GUILD, command.user.id, &file_name).await;
+        format!(
+            "Default boss music set ({:.1} seconds). It plays in servers where you have none",
+            length
+        )
+    } else if get_boss_music_approval(&pool, guild_id.0).await {
+        submit_boss_music(&pool, guild_id, command.user.id, &file_name).await;
+        format!(
+            "Boss music submitted ({:.1} seconds). It plays once a moderator approves it",
+            length
+        )
+    } else {
+        save_boss_music(ctx, guild_id.0, command.user.id, &file_name).await;
+        format!("Boss music set ({:.1} seconds)", length)
+    };
+    edit_original_response_simple_content(command, ctx, content.as_str()).await;
+
+    let path = boss_music_dir().join(&file_name);
+    if let Err(err) = command
+        .create_followup_message(ctx, |message| message.add_file(path.as_path()))
+        .await
+    {
+        println!("Cannot send boss music preview: {}", err);
+    }
+}
+
+/// Records the theme of a user in a guild, or their default, and removes the file it replaces
+async fn save_boss_music(ctx: &Context, guild_id: u64, user_id: UserId, file_name: &str) {
+    let pool = get_pool_from_ctx(ctx).await;
+    let previous = get_boss_music_theme(&pool, guild_id, user_id.0).await;
+
+    set_boss_music_theme(&pool, guild_id, user_id.0, file_name).await;
+    // A default applies to every guild
+    forget_cached_boss_music(ctx, |_, user| user == user_id.0).await;
+
+    if let Some(previous) = previous.filter(|previous| previous != file_name) {
+        let _ = tokio::fs::remove_file(boss_music_dir().join(previous)).await;
+    }
+}
+
+async fn forget_cached_boss_music(ctx: &Context, forget: impl Fn(u64, u64) -> bool) {
+    if let Some(has_boss_music) = ctx.data.write().await.get_mut::<HasBossMusic>() {
+        has_boss_music.retain(|(guild_id, user_id), _| !forget(*guild_id, *user_id));
+    }
+}
+
+/// Puts a clip up for review, replacing the one the member already had waiting
+async fn submit_boss_music(pool: &Pool, guild_id: GuildId, user_id: UserId, file_name: &str) {
+    let previous = get_user_boss_music_submission(pool, guild_id.0, user_id.0).await;
+
+    add_boss_music_submission(pool, guild_id.0, user_id.0, file_name).await;
+
+    if let Some(previous) = previous.filter(|previous| previous.song_name != file_name) {
+        let _ = tokio::fs::remove_file(boss_music_dir().join(previous.song_name)).await;
+    }
+}
+
+/// A line describing a theme file
+async fn describe_theme(name: &str, file_name: Option<String>) -> Option<String> {
+    let file_name = file_name?;
+    Some(
+        match probe_audio_duration(&boss_music_dir().join(file_name)).await {
+            Ok(length) => format!("{} is set ({:.1} seconds)", name, length),
+            Err(_) => format!(
+                "The file of {} is missing, set it again",
+                name.to_lowercase()
+            ),
+        },
+    )
+}
+
+async fn handle_boss_music_show(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let user_id = command.user.id;
+    let pool = get_pool_from_ctx(ctx).await;
+    let approval = get_boss_music_approval(&pool, guild_id.0).await;
+
+    let theme = get_boss_music_theme(&pool, guild_id.0, user_id.0).await;
+    let default = get_boss_music_theme(&pool, DEFAULT_THEME_GUILD, user_id.0).await;
+    let mut lines = Vec::new();
+    lines.extend(describe_theme("Your boss music in this server", theme.clone()).await);
+    lines.extend(describe_theme("Your default boss music", default.clone()).await);
+    if theme.is_none() && default.is_none() {
+        lines.push(
+            "You have no boss music. Set it with /bossmusic set or /bossmusic url".to_string(),
+        );
+    } else if theme.is_none() && approval {
+        lines.push(
+            "Default boss music does not play in this server, themes have to be approved here"
+                .to_string(),
+        );
+    }
+    if get_user_boss_music_submission(&pool, guild_id.0, user_id.0)
+        .await
+        .is_some()
+    {
+        lines.push("A new clip is waiting for approval".to_string());
+    }
+    if !get_boss_music_enabled(&pool, guild_id.0).await {
+        lines.push("Boss music is disabled in this server".to_string());
+    }
+
+    send_interaction_message_ephemeral(command, ctx, lines.join("\n").as_str()).await;
+}
+
+/// Sends the themes of the member, and the clip they have waiting, as players
+async fn handle_boss_music_preview(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let user_id = command.user.id;
+    let pool = get_pool_from_ctx(ctx).await;
+
+    let submission = get_user_boss_music_submission(&pool, guild_id.0, user_id.0)
+        .await
+        .map(|submission| submission.song_name);
+    let clips = vec![
+        (
+            "your boss music in this server",
+            get_boss_music_theme(&pool, guild_id.0, user_id.0).await,
+        ),
+        (
+            "your default boss music",
+            get_boss_music_theme(&pool, DEFAULT_THEME_GUILD, user_id.0).await,
+        ),
+        ("the clip waiting for approval", submission),
+    ];
+    let (names, files): (Vec<_>, Vec<_>) = clips
+        .into_iter()
+        .filter_map(|(name, file_name)| {
+            let path = boss_music_dir().join(file_name?);
+            path.is_file().then(|| (name, path))
+        })
+        .unzip();
+
+    if files.is_empty() {
+        send_interaction_message_ephemeral(command, ctx, "You have no boss music").await;
+        return;
+    }
+    let mut content = names.join(", then ");
+    content[..1].make_ascii_uppercase();
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+    edit_original_response_simple_content(command, ctx, content.as_str()).await;
+
+    if let Err(err) = command
+        .create_followup_message(ctx, |message| {
+            message.add_files(files.iter().map(|path| path.as_path()))
+        })
+        .await
+    {
+        println!("Cannot send boss music preview: {}", err);
+    }
+}
+
+/// Removes the theme of the member in the guild and the clip they have waiting, or their default
+async fn handle_boss_music_clear(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let user_id = command.user.id;
+    let pool = get_pool_from_ctx(ctx).await;
+    let default = default_option(options);
+
+    let slot = if default {
+        DEFAULT_THEME_GUILD
+    } else {
+        guild_id.0
+    };
+    let theme = get_boss_music_theme(&pool, slot, user_id.0).await;
+    if let Some(file_name) = &theme {
+        remove_boss_music_theme(&pool, slot, user_id.0).await;
+        let _ = tokio::fs::remove_file(boss_music_dir().join(file_name)).await;
+        forget_cached_boss_music(ctx, |_, user| user == user_id.0).await;
+    }
+
+    let submission = if default {
+        None
+    } else {
+        get_user_boss_music_submission(&pool, guild_id.0, user_id.0).await
+    };
+    if let Some(submission) = &submission {
+        remove_boss_music_submission(&pool, submission.id).await;
+        let _ = tokio::fs::remove_file(boss_music_dir().join(&submission.song_name)).await;
+    }
+
+    let content = match (theme.is_some() || submission.is_some(), default) {
+        (true, true) => "Default boss music cleared",
+        (true, false) => "Boss music cleared for this server",
+        (false, true) => "You have no default boss music",
+        (false, false) => "You have no boss music in this server",
+    };
+    send_interaction_message_ephemeral(command, ctx, content).await;
+}
+
+/// The members of the guild that have a theme
+async fn handle_boss_music_list(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let members = get_guild_boss_music_users(&pool, guild_id.0).await;
+
+    let mut content = if members.is_empty() {
+        "Nobody has boss music in this server".to_string()
+    } else {
+        format!("Members with boss music ({}):", members.len())
+    };
+    for user_id in members.iter().take(BOSS_MUSIC_LIST_LIMIT) {
+        write!(&mut content, "\n<@{}>", user_id).expect("cannot write to buffer");
+    }
+    if members.len() > BOSS_MUSIC_LIST_LIMIT {
+        write!(
+            &mut content,
+            "\nand {} more",
+            members.len() - BOSS_MUSIC_LIST_LIMIT
+        )
+        .expect("cannot write to buffer");
+    }
+    let pending = count_boss_music_submissions(&pool, guild_id.0).await;
+    if pending > 0 {
+        write!(
+            &mut content,
+            "\n{} clips waiting for approval, review them with /bossmusic pending",
+            pending
+        )
+        .expect("cannot write to buffer");
+    }
+
+    send_interaction_message_ephemeral(command, ctx, content.as_str()).await;
+}
+
+/// Shows the oldest clip waiting for approval with buttons to approve or reject it
+async fn handle_boss_music_pending(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let submission = match get_next_boss_music_submission(&pool, guild_id.0).await {
+        Some(submission) => submission,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, "No boss music waiting for approval")
+                .await;
+            return;
+        }
+    };
+    let count = count_boss_music_submissions(&pool, guild_id.0).await;
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+    edit_original_response_simple_content(
+        command,
+        ctx,
+        format!("{} clips waiting for approval", count).as_str(),
+    )
+    .await;
+
+    let path = boss_music_dir().join(&submission.song_name);
+    if let Err(err) = command
+        .create_followup_message(ctx, |message| {
+            message
+                .content(format!("Boss music of <@{}>", submission.user_id))
+                .allowed_mentions(|mentions| mentions.empty_parse())
+                .add_file(path.as_path())
+                .components(|comp| {
+                    comp.create_action_row(|row| {
+                        row.create_button(|btn| {
+                            btn.custom_id(format!("{}{}", BOSS_MUSIC_APPROVE, submission.id))
+                                .label("Approve")
+                                .style(ButtonStyle::Success)
+                        })
+                        .create_button(|btn| {
+                            btn.custom_id(format!("{}{}", BOSS_MUSIC_REJECT, submission.id))
+                                .label("Reject")
+                                .style(ButtonStyle::Danger)
+                        })
+                    })
+                })
+        })
+        .await
+    {
+        println!("Cannot send boss music for review: {}", err);
+    }
+}
+
+/// The approve and reject buttons of `/bossmusic pending`. Rejected files are deleted
+pub async fn handle_boss_music_review(ctx: &Context, command: &MessageComponentInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        match command
+            .create_interaction_response(ctx, |f| {
+                f.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|message| {
+                        message
+                            .content("You need the Manage Server permission")
+                            .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                    })
+            })
+            .await
+        {
+            Ok(_) => {}
+            Err(err) => {
+                println!("Cannot respond to message component {}", err)
+            }
+        };
+        return;
+    }
+
+    let custom_id = command.data.custom_id.as_str();
+    let (approve, id) = match custom_id.strip_prefix(BOSS_MUSIC_APPROVE) {
+        Some(id) => (true, id),
+        None => (false, custom_id.trim_start_matches(BOSS_MUSIC_REJECT)),
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let submission = match id.parse::<u64>() {
+        Ok(id) => get_boss_music_submission(&pool, id)
+            .await
+            .filter(|submission| submission.guild_id == guild_id.0),
+        Err(_) => None,
+    };
+
+    let content = match submission {
+        Some(submission) => {
+            remove_boss_music_submission(&pool, submission.id).await;
+            if approve {
+                save_boss_music(
+                    ctx,
+                    guild_id.0,
+                    UserId(submission.user_id),
+                    &submission.song_name,
+                )
+                .await;
+                format!("Approved the boss music of <@{}>", submission.user_id)
+            } else {
+                let _ = tokio::fs::remove_file(boss_music_dir().join(&submission.song_name)).await;
+                format!("Rejected the boss music of <@{}>", submission.user_id)
+            }
+        }
+        None => "This clip was already reviewed".to_string(),
+    };
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                        .components(|comp| comp)
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err)
+        }
+    };
+}
+
+/// New themes wait for a moderator when enabled. Toggles when no value is given
+async fn handle_boss_music_approval(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let approval = match get_option_by_name(options, "enabled") {
+        Some(ApplicationCommandInteractionDataOptionValue::Boolean(approval)) => *approval,
+        _ => !get_boss_music_approval(&pool, guild_id.0).await,
+    };
+
+    set_boss_music_approval(&pool, guild_id.0, approval).await;
+    // Defaults only play in guilds that do not review themes
+    forget_cached_boss_music(ctx, |guild, _| guild == guild_id.0).await;
+
+    send_interaction_message_ephemeral(
+        command,
+        ctx,
+        if approval {
+            "New boss music has to be approved with /bossmusic pending"
+        } else {
+            "New boss music plays right away. Clips already waiting still need /bossmusic pending"
+        },
+    )
+    .await;
+}
+
+/// Adds or removes a voice channel from the ones that play themes. Without a channel every voice
+/// channel plays them again
+async fn handle_boss_music_channel(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    match get_option_by_name(options, "channel") {
+        Some(ApplicationCommandInteractionDataOptionValue::Channel(channel))
+            if matches!(channel.kind, ChannelType::Voice | ChannelType::Stage) =>
+        {
+            if !remove_boss_music_channel(&pool, guild_id.0, channel.id.0).await {
+                add_boss_music_channel(&pool, guild_id.0, channel.id.0).await;
+            }
+        }
+        Some(_) => {
+            send_interaction_message_ephemeral(command, ctx, "Pick a voice channel").await;
+            return;
+        }
+        None => clear_boss_music_channels(&pool, guild_id.0).await,
+    }
+
+    let channels = get_boss_music_channels(&pool, guild_id.0).await;
+    let content = if channels.is_empty() {
+        "Boss music plays in every voice channel".to_string()
+    } else {
+        let channels = channels
+            .iter()
+            .map(|channel_id| format!("<#{}>", channel_id))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("Boss music only plays in {}", channels)
+    };
+    send_interaction_message_ephemeral(command, ctx, content.as_str()).await;
+}
+
+/// Turns entrance themes on or off for the guild. Toggles when no value is given
+async fn handle_boss_music_toggle(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let enabled = match get_option_by_name(options, "enabled") {
+        Some(ApplicationCommandInteractionDataOptionValue::Boolean(enabled)) => *enabled,
+        _ => !get_boss_music_enabled(&pool, guild_id.0).await,
+    };
+
+    set_boss_music_enabled(&pool, guild_id.0, enabled).await;
+
+    send_interaction_message_ephemeral(
+        command,
+        ctx,
+        if enabled {
+            "Boss music enabled"
+        } else {
+            "Boss music disabled"
+        },
+    )
+    .await;
+}
+
+/// Called from `voice_state_update`. Plays the theme of a member who joined a voice channel
+pub async fn on_voice_state_update(
+    ctx: &Context,
+    guild_id: GuildId,
+    old_state: Option<&VoiceState>,
+    new_state: &VoiceState,
+) {
+    let channel_id = match new_state.channel_id {
+        Some(channel_id) => channel_id,
+        None => return,
+    };
+    // Mute, deafen and stream updates keep the same channel
+    if old_state.and_then(|state| state.channel_id) == Some(channel_id) {
+        return;
+    }
+    let is_bot = new_state
+        .member
+        .as_ref()
+        .map_or(false, |member| member.user.bot);
+    if is_bot || new_state.user_id == ctx.cache.current_user_id().await {
+        return;
+    }
+
+    if is_recording(&ctx.data, guild_id).await
+        || is_listening(&ctx.data, guild_id).await
+        || is_mixing(&ctx.data, guild_id).await
+    {
+        return;
+    }
+    let pool = get_pool_from_ctx(ctx).await;
+    if !get_boss_music_enabled(&pool, guild_id.0).await {
+        return;
+    }
+    let channels = get_boss_music_channels(&pool, guild_id.0).await;
+    if !channels.is_empty() && !channels.contains(&channel_id.0) {
+        return;
+    }
+    let file_name = match user_boss_music(ctx, guild_id, new_state.user_id).await {
+        Some(file_name) => file_name,
+        None => return,
+    };
+    if !music_backend_available(&ctx.data).await
+        || !take_cooldown(ctx, guild_id, new_state.user_id).await
+    {
+        return;
+    }
+
+    let path = boss_music_dir().join(file_name);
+    if !path.is_file() {
+        warn!("Boss music file {} is missing", path.display());
+        return;
+    }
+    info!(
+        "Playing boss music of {} in guild {}",
+        new_state.user_id, guild_id
+    );
+    play_short_clip(
+        ctx,
+        guild_id,
+        channel_id,
+        new_state.user_id,
+        &path,
+        "Boss music",
+    )
+    .await;
+}
+
+/// The theme file played for a user in a guild: theirs for the guild, otherwise their default unless
+/// the guild reviews themes. `HasBossMusic` caches the lookups, including users without one
+async fn user_boss_music(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Option<String> {
+    let key = (guild_id.0, user_id.0);
+    let cached = ctx
+        .data
+        .read()
+        .await
+        .get::<HasBossMusic>()
+        .and_then(|has_boss_music| has_boss_music.get(&key).cloned());
+    if let Some(file_name) = cached {
+        return file_name;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let mut file_name = get_boss_music_theme(&pool, guild_id.0, user_id.0).await;
+    if file_name.is_none() && !get_boss_music_approval(&pool, guild_id.0).await {
+        file_name = get_boss_music_theme(&pool, DEFAULT_THEME_GUILD, user_id.0).await;
+    }
+    if let Some(has_boss_music) = ctx.data.write().await.get_mut::<HasBossMusic>() {
+        has_boss_music.insert(key, file_name.clone());
+    }
+
+    file_name
+}
+
+/// Returns false if the theme of the user was played too recently, otherwise starts a new cooldown
+async fn take_cooldown(ctx: &Context, guild_id: GuildId, user_id: UserId) -> bool {
+    let cooldowns = match ctx.data.read().await.get::<BossMusicCooldowns>() {
+        Some(cooldowns) => cooldowns.clone(),
+        None => return false,
+    };
+    let mut cooldowns = cooldowns.lock().await;
+
+    main::take_cooldown(
+        &mut cooldowns,
+        (guild_id.0, user_id.0),
+        Duration::from_secs(env_or("BOSS_MUSIC_COOLDOWN_SECS", DEFAULT_COOLDOWN_SECS)),
+        Instant::now(),
+    )
+    .is_none()
+}
+
+/// Entrance themes and soundboard clips. They interrupt the music instead of queueing after it
+pub fn is_short_clip_uri(uri: &str) -> bool {
+    is_boss_music_uri(uri) || is_soundboard_uri(uri)
+}
+
+/// Plays a clip of the media store through lavalink, joining the channel when the bot is not in
+/// voice and leaving afterwards. When music is already playing there the clip interrupts it and the
+/// music resumes where it was. Returns false if the clip cannot be played
+pub async fn play_short_clip(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+    path: &Path,
+    title: &str,
+) -> bool {
+    let url = match media_url(path) {
+        Some(url) => url,
+        None => return false,
+    };
+
+    let manager = get_songbird_manager(ctx).await;
+    let visit = match manager.get(guild_id) {
+        Some(handle_lock) => {
+            // Never pull the bot away from people listening somewhere else
+            if handle_lock.lock().await.current_channel() != Some(channel_id.into()) {
+                return false;
+            }
+            false
+        }
+        None => {
+            // There is no text channel to report to, the voice channel stands in for it
+            if join_or_get_voice_channel(ctx, guild_id, channel_id, channel_id)
+                .await
+                .is_err()
+            {
+                return false;
+            }
+            true
+        }
+    };
+
+    let lavalink = get_lavalink_client(ctx, guild_id).await;
+    let played = match &lavalink {
+        Some(lavalink) => {
+            queue_short_clip(ctx, lavalink, guild_id, user_id, &url, title, visit).await
+        }
+        None => false,
+    };
+    // The bot joined for this clip alone, nothing else would make it leave
+    if visit && !played {
+        stop_and_leave(&ctx.data, lavalink.as_ref(), guild_id).await;
+    }
+
+    played
+}
+
+async fn queue_short_clip(
+    ctx: &Context,
+    lavalink: &LavalinkClient,
+    guild_id: GuildId,
+    user_id: UserId,
+    url: &str,
+    title: &str,
+    visit: bool,
+) -> bool {
+    let mut track = match lavalink.get_tracks(url).await {
+        Ok(tracks) => match tracks.tracks.into_iter().next() {
+            Some(track) => track,
+            None => {
+                warn!("Lavalink cannot load clip {}", url);
+                return false;
+            }
+        },
+        Err(err) => {
+            warn!("Cannot load clip {}: {}", url, err);
+            return false;
+        }
+    };
+    if let Some(info) = track.info.as_mut() {
+        info.title = title.to_string();
+    }
+
+    if visit {
+        let guild_track = ctx
+            .data
+            .read()
+            .await
+            .get::<GuildTrackMap>()
+            .cloned()
+            .unwrap();
+        if let Some(guild_track) = guild_track.lock().await.get_mut(&guild_id.0) {
+            guild_track.boss_music_visit = true;
+        }
+    }
+
+    // Several members joining at once get their themes one after the other
+    let playing_short_clip = match lavalink.nodes().await.get(&guild_id.0) {
+        Some(node) => node
+            .now_playing
+            .as_ref()
+            .and_then(|now_playing| now_playing.track.info.as_ref())
+            .map_or(false, |info| is_short_clip_uri(&info.uri)),
+        None => false,
+    };
+    let priority = if playing_short_clip {
+        QueuePriority::Next
+    } else {
+        QueuePriority::Now
+    };
+
+    if !insert_with_priority(ctx, lavalink, guild_id, &track, user_id, priority).await {
+        // Nothing is playing, a normal queue starts it right away
+        if let Err(err) = lavalink
+            .play(guild_id.0, track)
+            .requester(user_id)
+            .queue()
+            .await
+        {
+            warn!("Cannot play clip: {}", err);
+            return false;
+        }
+    }
+
+    true
+}
+
+fn is_short_clip(queued: &TrackQueue) -> bool {
+    queued
+        .track
+        .info
+        .as_ref()
+        .map_or(false, |info| is_short_clip_uri(&info.uri))
+}
+
+/// Called from `LavalinkHandler::track_finish`. Leaves once the last short clip ended if the bot
+/// only joined to play it
+pub async fn on_track_finish(
+    data: &Arc<RwLock<TypeMap>>,
+    lavalink: &LavalinkClient,
+    guild_id: u64,
+    track: &str,
+) {
+    let guild_track = data.read().await.get::<GuildTrackMap>().cloned().unwrap();
+    let visit = match guild_track.lock().await.get(&guild_id) {
+        Some(guild_track) => guild_track.boss_music_visit,
+        None => false,
+    };
+    if !visit {
+        return;
+    }
+
+    let (remaining, only_boss_music) = match lavalink.nodes().await.get(&guild_id) {
+        Some(node) => {
+            // The finished track can still be at the front of the queue
+            let remaining = node
+                .queue
+                .iter()
+                .filter(|queued| queued.track.track != track)
+                .collect::<Vec<_>>();
+            (
+                remaining.len(),
+                remaining.iter().all(|queued| is_short_clip(queued)),
+            )
+        }
+        None => return,
+    };
+
+    if remaining == 0 {
+        stop_and_leave(data, Some(lavalink), guild_id).await;
+    } else if !only_boss_music {
+        // Someone queued music in the meantime, the bot stays for it
+        if let Some(guild_track) = guild_track.lock().await.get_mut(&guild_id) {
+            guild_track.boss_music_visit = false;
+        }
+    }
+}
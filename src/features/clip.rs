@@ -0,0 +1,448 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serenity::{
+    async_trait,
+    client::Context,
+    model::{
+        id::{ChannelId, GuildId},
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        },
+    },
+    prelude::{Mutex, RwLock, TypeMap, TypeMapKey},
+};
+use songbird::{Call, CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler};
+use tracing::{info, warn};
+
+use crate::{
+    database::settings::{get_clip_buffer_enabled, set_clip_buffer_enabled},
+    events::interactions::{
+        application_command::{
+            edit_original_response_simple_content, get_option_by_name, send_defered_response,
+            send_interaction_message_basic, send_interaction_message_ephemeral,
+        },
+        get_songbird_manager,
+        helpers::{get_guild_channel_id_from_interaction_application, member_can_manage_guild},
+    },
+    features::{
+        recording::is_recording,
+        voice_stats::{start_speaking_tracker, stop_speaking_tracker},
+    },
+    helpers::{db_helper::get_pool_from_ctx, main::mix_pcm_to_opus},
+};
+
+pub const CLIP_BUFFER_LISTENING: &str =
+    "Music is unavailable while I listen for /clip, /clipbuffer enabled:False stops it";
+
+const CLIPS_DIR: &str = "clips";
+const BUFFER_LENGTH: Duration = Duration::from_secs(120);
+const DEFAULT_CLIP_SECS: i64 = 30;
+const MIN_CLIP_SECS: i64 = 5;
+// Decoded voice is 48kHz stereo
+const SAMPLES_PER_SECOND: f64 = 48_000.0 * 2.0;
+const ENCODE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The rolling voice buffer of each guild that opted in and has a call songbird listens to
+pub struct ClipBuffers;
+impl TypeMapKey for ClipBuffers {
+    type Value = Arc<Mutex<HashMap<u64, Arc<ClipBuffer>>>>;
+}
+
+pub struct ClipBuffer {
+    voice_channel_id: ChannelId,
+    /// The bot joined only to fill the buffer. Otherwise the buffer lives as long as a recording
+    listening: AtomicBool,
+    /// Cleared once the buffer is dropped so its handler unregisters
+    active: AtomicBool,
+    /// Decoded packets of every speaker with the moment they arrived, oldest first
+    packets: Mutex<HashMap<u32, VecDeque<(Instant, Vec<i16>)>>>,
+}
+
+/// Songbird event handler keeping the last two minutes of every speaker
+struct ClipReceiver {
+    buffer: Arc<ClipBuffer>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for ClipReceiver {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if !self.buffer.active.load(Ordering::Relaxed) {
+            return Some(Event::Cancel);
+        }
+        if let EventContext::VoicePacket(data) = ctx {
+            if let Some(audio) = data.audio {
+                let now = Instant::now();
+                let mut packets = self.buffer.packets.lock().await;
+                packets
+                    .entry(data.packet.ssrc)
+                    .or_default()
+                    .push_back((now, audio.clone()));
+                // Speakers who went quiet are pruned too, their packets are old
+                for speaker in packets.values_mut() {
+                    while let Some((at, _)) = speaker.front() {
+                        if now.duration_since(*at) <= BUFFER_LENGTH {
+                            break;
+                        }
+                        speaker.pop_front();
+                    }
+                }
+                packets.retain(|_, speaker| !speaker.is_empty());
+            }
+        }
+        None
+    }
+}
+
+/// Clips only live until they are uploaded, so they stay out of the media store the media server
+/// hands out
+fn clips_dir() -> PathBuf {
+    std::env::temp_dir().join(CLIPS_DIR)
+}
+
+async fn get_clip_buffers(
+    data: &Arc<RwLock<TypeMap>>,
+) -> Arc<Mutex<HashMap<u64, Arc<ClipBuffer>>>> {
+    data.read().await.get::<ClipBuffers>().cloned().unwrap()
+}
+
+/// True while the bot sits in a voice channel of the guild only to fill the buffer
+pub async fn is_listening(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) -> bool {
+    let buffers = get_clip_buffers(data).await;
+    let listening = match buffers.lock().await.get(&guild_id.0) {
+        Some(buffer) => buffer.listening.load(Ordering::Relaxed),
+        None => false,
+    };
+    listening
+}
+
+/// Starts buffering the audio of a call songbird listens to. Does nothing if the guild did not opt
+/// in, unless `listening` says the bot joined for it
+pub async fn start_clip_buffer(
+    ctx: &Context,
+    guild_id: GuildId,
+    voice_channel_id: ChannelId,
+    handle_lock: &Arc<Mutex<Call>>,
+    listening: bool,
+) {
+    if !listening && !get_clip_buffer_enabled(&get_pool_from_ctx(ctx).await, guild_id.0).await {
+        return;
+    }
+
+    let buffers = get_clip_buffers(&ctx.data).await;
+    let buffer = {
+        let mut buffers = buffers.lock().await;
+        if let Some(buffer) = buffers.get(&guild_id.0) {
+            // The buffer of a recording outlives it now
+            buffer.listening.fetch_or(listening, Ordering::Relaxed);
+            return;
+        }
+        let buffer = Arc::new(ClipBuffer {
+            voice_channel_id,
+            listening: AtomicBool::new(listening),
+            active: AtomicBool::new(true),
+            packets: Mutex::new(HashMap::new()),
+        });
+        buffers.insert(guild_id.0, buffer.clone());
+        buffer
+    };
+
+    handle_lock
+        .lock()
+        .await
+        .add_global_event(Event::Core(CoreEvent::VoicePacket), ClipReceiver { buffer });
+}
+
+/// Called when a recording stops. Returns true if the bot keeps listening for clips, the buffer of
+/// a guild that was only recorded is dropped
+pub async fn release_clip_buffer(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) -> bool {
+    let buffers = get_clip_buffers(data).await;
+    let mut buffers = buffers.lock().await;
+    match buffers.get(&guild_id.0) {
+        Some(buffer) if buffer.listening.load(Ordering::Relaxed) => true,
+        Some(buffer) => {
+            buffer.active.store(false, Ordering::Relaxed);
+            buffers.remove(&guild_id.0);
+            false
+        }
+        None => false,
+    }
+}
+
+/// `/clipbuffer [enabled]`. Opts the guild in to clips and joins the voice channel of the member
+/// to listen, or forgets the buffer and leaves
+pub async fn handle_clip_buffer(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let (guild_id, voice_channel_id) =
+        get_guild_channel_id_from_interaction_application(command, ctx).await;
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let enabled = match get_option_by_name(&command.data.options, "enabled") {
+        Some(ApplicationCommandInteractionDataOptionValue::Boolean(enabled)) => *enabled,
+        _ => !get_clip_buffer_enabled(&pool, guild_id.0).await,
+    };
+    set_clip_buffer_enabled(&pool, guild_id.0, enabled).await;
+
+    let manager = get_songbird_manager(ctx).await;
+    if !enabled {
+        let buffer = get_clip_buffers(&ctx.data)
+            .await
+            .lock()
+            .await
+            .remove(&guild_id.0);
+        let listening = match buffer {
+            Some(buffer) => {
+                buffer.active.store(false, Ordering::Relaxed);
+                buffer.listening.load(Ordering::Relaxed)
+            }
+            None => false,
+        };
+        // A recording keeps the call
+        if listening && !is_recording(&ctx.data, guild_id).await {
+            stop_speaking_tracker(ctx, guild_id).await;
+            if let Err(err) = manager.remove(guild_id).await {
+                warn!("Cannot leave channel: {}", err);
+            }
+        }
+        if listening {
+            send_interaction_message_basic(
+                command,
+                ctx,
+                "Clips are disabled, I stopped listening and dropped the voice buffer",
+            )
+            .await;
+        } else {
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                "Clips are disabled, the voice buffer was dropped",
+            )
+            .await;
+        }
+        return;
+    }
+
+    let voice_channel_id = match voice_channel_id {
+        Some(channel_id) => channel_id,
+        None => {
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                "Clips are enabled. Join a voice channel and run this again to start listening",
+            )
+            .await;
+            return;
+        }
+    };
+
+    match manager.get(guild_id) {
+        Some(handle_lock) if is_recording(&ctx.data, guild_id).await => {
+            start_clip_buffer(ctx, guild_id, voice_channel_id, &handle_lock, true).await;
+        }
+        Some(_) if is_listening(&ctx.data, guild_id).await => {
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                "Clips are enabled, I am already listening",
+            )
+            .await;
+            return;
+        }
+        Some(_) => {
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                "Clips are enabled. I am already in a voice channel here, stop the music and run \
+                 this again to start listening",
+            )
+            .await;
+            return;
+        }
+        None => {
+            let (handle_lock, joined) = manager.join(guild_id, voice_channel_id).await;
+            if let Err(err) = joined {
+                warn!("Cannot join voice channel to listen: {}", err);
+                let _ = manager.remove(guild_id).await;
+                send_interaction_message_ephemeral(
+                    command,
+                    ctx,
+                    "Clips are enabled but I cannot join your voice channel",
+                )
+                .await;
+                return;
+            }
+            start_clip_buffer(ctx, guild_id, voice_channel_id, &handle_lock, true).await;
+            start_speaking_tracker(ctx, guild_id, voice_channel_id, &handle_lock).await;
+        }
+    }
+    info!("Listening for clips in guild {}", guild_id);
+
+    // Everyone in the channel must know the bot keeps what they say
+    send_interaction_message_basic(
+        command,
+        ctx,
+        format!(
+            "👂 **Listening for clips** in <#{}>, enabled by <@{}>. The last two minutes of that \
+             channel are kept so anyone can post them with /clip. Music and boss music are blocked \
+             until someone runs /clipbuffer enabled:False",
+            voice_channel_id, command.user.id
+        )
+        .as_str(),
+    )
+    .await;
+}
+
+/// `/clip [seconds]`. Posts the last seconds of the voice channel mixed in one file
+pub async fn handle_clip(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let max_secs = BUFFER_LENGTH.as_secs() as i64;
+    let seconds = match get_option_by_name(&command.data.options, "seconds") {
+        Some(ApplicationCommandInteractionDataOptionValue::Integer(seconds)) => *seconds,
+        _ => DEFAULT_CLIP_SECS,
+    };
+    if !(MIN_CLIP_SECS..=max_secs).contains(&seconds) {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            format!(
+                "Clips are between {} and {} seconds long",
+                MIN_CLIP_SECS, max_secs
+            )
+            .as_str(),
+        )
+        .await;
+        return;
+    }
+
+    let buffer = get_clip_buffers(&ctx.data)
+        .await
+        .lock()
+        .await
+        .get(&guild_id.0)
+        .cloned();
+    let buffer = match buffer {
+        Some(buffer) => buffer,
+        None => {
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                "I am not listening here, an admin can start with /clipbuffer",
+            )
+            .await;
+            return;
+        }
+    };
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    let window = Duration::from_secs(seconds as u64);
+    let samples = mix_window(&buffer, window).await;
+    if samples.iter().all(|sample| *sample == 0) {
+        edit_original_response_simple_content(
+            command,
+            ctx,
+            format!("Nobody spoke in the last {} seconds", seconds).as_str(),
+        )
+        .await;
+        return;
+    }
+
+    let path = match encode_clip(guild_id, &samples).await {
+        Ok(path) => path,
+        Err(err) => {
+            warn!("Cannot encode clip: {}", err);
+            edit_original_response_simple_content(command, ctx, "Cannot encode the clip").await;
+            return;
+        }
+    };
+
+    edit_original_response_simple_content(
+        command,
+        ctx,
+        format!(
+            "The last {} seconds of <#{}>, clipped by <@{}>",
+            seconds, buffer.voice_channel_id, command.user.id
+        )
+        .as_str(),
+    )
+    .await;
+    if let Err(err) = command
+        .create_followup_message(ctx, |message| message.add_file(path.as_path()))
+        .await
+    {
+        println!("Cannot send clip: {}", err);
+    }
+    if let Err(err) = tokio::fs::remove_file(&path).await {
+        warn!("Cannot remove {}: {}", path.display(), err);
+    }
+}
+
+/// Sums the packets of every speaker that arrived during the last `window`
+async fn mix_window(buffer: &ClipBuffer, window: Duration) -> Vec<i16> {
+    let packets = buffer.packets.lock().await;
+    mix_packets(&packets, Instant::now(), window)
+}
+
+/// Sums the packets that arrived during the `window` before `now`, each at the moment it arrived
+pub(crate) fn mix_packets(
+    packets: &HashMap<u32, VecDeque<(Instant, Vec<i16>)>>,
+    now: Instant,
+    window: Duration,
+) -> Vec<i16> {
+    let start = now.checked_sub(window).unwrap_or(now);
+    let len = (window.as_secs_f64() * SAMPLES_PER_SECOND) as usize;
+    let mut mix = vec![0i32; len];
+
+    for (arrived_at, audio) in packets.values().flatten() {
+        if *arrived_at < start {
+            continue;
+        }
+        // A packet is placed where it arrived, silence between packets is never sent
+        let offset =
+            (arrived_at.duration_since(start).as_secs_f64() * SAMPLES_PER_SECOND) as usize & !1;
+        for (slot, sample) in mix.iter_mut().skip(offset).zip(audio) {
+            *slot += *sample as i32;
+        }
+    }
+
+    mix.into_iter()
+        .map(|sample| sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+        .collect()
+}
+
+async fn encode_clip(guild_id: GuildId, samples: &[i16]) -> Result<PathBuf, String> {
+    let dir = clips_dir();
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|err| format!("cannot create {}: {}", dir.display(), err))?;
+
+    let name = format!("{}-{}", guild_id.0, chrono::Utc::now().timestamp_millis());
+    let raw = dir.join(format!(".{}.pcm", name));
+    let output = dir.join(format!("{}.ogg", name));
+    let bytes = samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect::<Vec<_>>();
+    tokio::fs::write(&raw, bytes)
+        .await
+        .map_err(|err| format!("cannot write {}: {}", raw.display(), err))?;
+
+    let result = mix_pcm_to_opus(&[raw.clone()], &output, ENCODE_TIMEOUT).await;
+    let _ = tokio::fs::remove_file(&raw).await;
+    result.map(|_| output)
+}
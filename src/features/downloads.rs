@@ -0,0 +1,248 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serenity::{
+    client::Context,
+    model::{
+        id::{GuildId, UserId},
+        interactions::{
+            application_command::ApplicationCommandInteraction, InteractionResponseType,
+        },
+    },
+    prelude::TypeMapKey,
+};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{
+    database::downloads::{
+        claim_next_download, count_downloads_by_status, enqueue_download, fail_download,
+        finish_download, get_downloads, reset_running_downloads, retry_download, DownloadJob,
+    },
+    events::interactions::database::add_track_to_db,
+    features::storage::ensure_storage_quota,
+    helpers::{db_helper::get_pool_from_ctx, media_store::download_to_store},
+};
+
+const DEFAULT_WORKERS: usize = 2;
+// A single yt-dlp run, it is killed after that
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const MAX_ATTEMPTS: u32 = 3;
+// Doubled after every failed attempt
+const RETRY_BACKOFF_SECS: u64 = 30;
+// Workers also wake up on their own to pick up retries that became due
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+// Has to fit in download_jobs.query
+const MAX_QUERY_LENGTH: usize = 255;
+const MAX_ERROR_LENGTH: usize = 1000;
+const DOWNLOADS_LIMIT: u64 = 10;
+const MAX_TITLE_LENGTH: usize = 80;
+
+static WORKERS_STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Wakes up idle download workers when a job is queued
+pub struct DownloadQueue;
+impl TypeMapKey for DownloadQueue {
+    type Value = Arc<Notify>;
+}
+
+/// Queues a download of `query` (a url or a youtube search) into the jam library. Identical
+/// requests are only downloaded once
+pub async fn queue_download(ctx: &Context, query: &str, guild_id: GuildId, requester: UserId) {
+    let ctx = ctx.clone();
+    let query: String = query.chars().take(MAX_QUERY_LENGTH).collect();
+    tokio::spawn(async move {
+        let pool = get_pool_from_ctx(&ctx).await;
+        if enqueue_download(&pool, guild_id.0, Some(requester.0), &query).await {
+            info!("Queued download for guild {}: {}", guild_id, query);
+            if let Some(queue) = ctx.data.read().await.get::<DownloadQueue>() {
+                queue.notify_one();
+            }
+        }
+    });
+}
+
+/// Starts the download workers. Safe to call on every `ready`. The number of workers comes from
+/// DOWNLOAD_WORKERS
+pub fn start_download_workers(ctx: &Context) {
+    if WORKERS_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let workers = std::env::var("DOWNLOAD_WORKERS")
+        .ok()
+        .and_then(|workers| workers.parse().ok())
+        .filter(|workers| *workers > 0)
+        .unwrap_or(DEFAULT_WORKERS);
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let queue = Arc::new(Notify::new());
+        ctx.data
+            .write()
+            .await
+            .insert::<DownloadQueue>(queue.clone());
+
+        let pool = get_pool_from_ctx(&ctx).await;
+        reset_running_downloads(&pool).await;
+
+        info!("Starting {} download workers", workers);
+        for _ in 0..workers {
+            let ctx = ctx.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                loop {
+                    let pool = get_pool_from_ctx(&ctx).await;
+                    match claim_next_download(&pool).await {
+                        Some(job) => run_download(&ctx, job).await,
+                        None => {
+                            let _ = tokio::time::timeout(POLL_INTERVAL, queue.notified()).await;
+                        }
+                    }
+                }
+            });
+        }
+    });
+}
+
+async fn run_download(ctx: &Context, job: DownloadJob) {
+    let pool = get_pool_from_ctx(ctx).await;
+
+    if let Err(err) = ensure_storage_quota(&pool, job.guild_id).await {
+        warn!("Download {} refused: {}", job.id, err);
+        fail_download(&pool, job.id, &err).await;
+        return;
+    }
+
+    let result =
+        match tokio::time::timeout(DOWNLOAD_TIMEOUT, download_to_store(&pool, &job.query)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!(
+                "timed out after {} seconds",
+                DOWNLOAD_TIMEOUT.as_secs()
+            )),
+        };
+
+    match result {
+        Ok(media) => {
+            info!("Downloaded track: {} ({})", media.title, media.checksum);
+            match add_track_to_db(ctx.clone(), GuildId(job.guild_id), &media, job.requester_id)
+                .await
+            {
+                Ok(_) => finish_download(&pool, job.id, &media).await,
+                Err(err) => {
+                    warn!(
+                        "Download {} cannot be added to the jam library: {}",
+                        job.id, err
+                    );
+                    fail_download(&pool, job.id, "cannot add the track to the jam library").await;
+                }
+            }
+        }
+        Err(err) => {
+            let err: String = err.chars().take(MAX_ERROR_LENGTH).collect();
+            if job.attempts < MAX_ATTEMPTS {
+                let delay = RETRY_BACKOFF_SECS << (job.attempts - 1);
+                warn!(
+                    "Download {} failed (attempt {}), retrying in {}s: {}",
+                    job.id, job.attempts, delay, err
+                );
+                retry_download(&pool, job.id, &err, delay).await;
+            } else {
+                warn!(
+                    "Download {} failed after {} attempts: {}",
+                    job.id, job.attempts, err
+                );
+                fail_download(&pool, job.id, &err).await;
+            }
+        }
+    }
+}
+
+/// `/downloads`, the status of the downloads of the guild
+pub async fn handle_downloads(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+
+    let counts = count_downloads_by_status(&pool, guild_id.0).await;
+    let jobs = get_downloads(&pool, guild_id.0, DOWNLOADS_LIMIT).await;
+
+    let content = if jobs.is_empty() {
+        "Nothing has been downloaded yet".to_string()
+    } else {
+        let count = |status: &str| {
+            counts
+                .iter()
+                .find(|(s, _)| s == status)
+                .map_or(0, |(_, count)| *count)
+        };
+        let mut output = format!(
+            "Downloads: {} pending, {} running, {} done, {} failed\n\n",
+            count("pending"),
+            count("running"),
+            count("done"),
+            count("failed")
+        );
+        for (i, job) in jobs.iter().enumerate() {
+            writeln!(&mut output, "{}) {}", i + 1, describe_job(job))
+                .expect("cannot write to buffer");
+        }
+        output
+    };
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
+fn describe_job(job: &DownloadJob) -> String {
+    let query = truncate(&job.query);
+    match job.status.as_str() {
+        "done" => format!(
+            "{} - done: {}",
+            query,
+            truncate(job.title.as_deref().unwrap_or(""))
+        ),
+        "failed" => format!(
+            "{} - failed after {} attempts: {}",
+            query,
+            job.attempts,
+            truncate(job.error.as_deref().unwrap_or("unknown error"))
+        ),
+        "pending" if job.attempts > 0 => format!(
+            "{} - waiting to retry ({}/{} attempts)",
+            query, job.attempts, MAX_ATTEMPTS
+        ),
+        status => format!("{} - {}", query, status),
+    }
+}
+
+fn truncate(text: &str) -> String {
+    if text.chars().count() > MAX_TITLE_LENGTH {
+        format!(
+            "{}...",
+            text.chars().take(MAX_TITLE_LENGTH).collect::<String>()
+        )
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,400 @@
+use std::{fmt::Write, sync::Arc};
+
+use lavalink_rs::LavalinkClient;
+use serenity::{
+    builder::CreateComponents,
+    client::Context,
+    model::{
+        channel::ReactionType,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            },
+            autocomplete::AutocompleteInteraction,
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionResponseType,
+        },
+    },
+    prelude::{RwLock, TypeMap},
+};
+use tracing::warn;
+
+use crate::{
+    database::history::{
+        add_play_history, count_play_history, finish_play_history, get_play_history,
+        get_play_history_entry, search_play_history_titles, PlayHistory,
+    },
+    events::interactions::{
+        helpers::{
+            get_guild_channel_id_from_interaction_message, join_or_get_voice_channel,
+            CANNOT_JOIN_VOICE,
+        },
+        lavalink::{get_lavalink_client, nodes::MUSIC_BACKEND_UNAVAILABLE},
+        message_component::{
+            edit_original_response_simple_content, not_in_a_voice_channel_message,
+            send_defered_response,
+        },
+    },
+    features::boss_music::is_short_clip_uri,
+    helpers::db_helper::get_pool_from_ctx,
+    GuildTrackMap, MysqlConnection,
+};
+
+pub const HISTORY_REPLAY: &str = "history_replay_";
+pub const HISTORY_PAGE: &str = "history_page_";
+
+const HISTORY_PAGE_SIZE: u64 = 5;
+// Discord limit for autocomplete choices
+const MAX_AUTOCOMPLETE_CHOICES: u64 = 25;
+const MAX_AUTOCOMPLETE_LENGTH: usize = 100;
+
+/// Called from `LavalinkHandler::track_start`. Adds the track to the play history and remembers
+/// the entry so it can be completed when the track finishes
+pub async fn record_track_start(
+    data: &Arc<RwLock<TypeMap>>,
+    lavalink: &LavalinkClient,
+    guild_id: u64,
+    track: &str,
+) {
+    // The event only contains the encoded track. The requester is only known by the queue
+    let (requester_id, info) = match lavalink.nodes().await.get(&guild_id) {
+        Some(node) => match &node.now_playing {
+            Some(now_playing) if now_playing.track.track == track => (
+                now_playing.requester.map(|user| user.0),
+                now_playing.track.info.clone(),
+            ),
+            _ => (None, None),
+        },
+        None => (None, None),
+    };
+
+    let info = match info {
+        Some(info) => Some(info),
+        None => lavalink.decode_track(track).await.ok(),
+    };
+
+    let (title, uri, length) = match info {
+        Some(info) => (info.title, info.uri, info.length),
+        None => ("Unkown title".to_string(), String::new(), 0),
+    };
+
+    let (pool, guild_track) = {
+        let data = data.read().await;
+        (
+            data.get::<MysqlConnection>().cloned().unwrap(),
+            data.get::<GuildTrackMap>().cloned().unwrap(),
+        )
+    };
+
+    // Entrance themes and soundboard clips are not music anyone asked for
+    let id = if is_short_clip_uri(&uri) {
+        None
+    } else {
+        add_play_history(&pool, guild_id, requester_id, &title, &uri, length).await
+    };
+
+    let mut mutex_guard = guild_track.lock().await;
+    if let Some(guild_track) = mutex_guard.get_mut(&guild_id) {
+        guild_track.history_id = id;
+        guild_track.position = 0;
+        guild_track.how_long = std::time::Instant::now();
+    }
+}
+
+/// Called from `LavalinkHandler::track_finish`. Anything other than `FINISHED` (stopped, replaced
+/// by a skip, failed to load) counts as skipped
+pub async fn record_track_finish(data: &Arc<RwLock<TypeMap>>, guild_id: u64, reason: &str) {
+    let (pool, guild_track) = {
+        let data = data.read().await;
+        (
+            data.get::<MysqlConnection>().cloned().unwrap(),
+            data.get::<GuildTrackMap>().cloned().unwrap(),
+        )
+    };
+
+    let (id, played_ms) = {
+        let mut mutex_guard = guild_track.lock().await;
+        match mutex_guard.get_mut(&guild_id) {
+            Some(guild_track) => (
+                guild_track.history_id.take(),
+                guild_track.position.max(0) as u64
+                    + guild_track.how_long.elapsed().as_millis() as u64,
+            ),
+            None => return,
+        }
+    };
+
+    if let Some(id) = id {
+        finish_play_history(&pool, id, played_ms, reason != "FINISHED").await;
+    }
+}
+
+pub async fn handle_history(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let page = match command
+        .data
+        .options
+        .get(0)
+        .and_then(|option| option.resolved.as_ref())
+    {
+        Some(ApplicationCommandInteractionDataOptionValue::Integer(page)) if *page > 0 => {
+            (*page - 1) as u64
+        }
+        _ => 0,
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let (content, entries, pages) = build_history_page(&pool, guild_id.0, page).await;
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        // Don't ping the requesters
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                        .components(|comp| history_components(comp, &entries, page, pages))
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
+/// The previous/next buttons of the `/history` message
+pub async fn handle_history_page(ctx: &Context, command: &MessageComponentInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let page = command
+        .data
+        .custom_id
+        .trim_start_matches(HISTORY_PAGE)
+        .parse::<u64>()
+        .unwrap_or(0);
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let (content, entries, pages) = build_history_page(&pool, guild_id.0, page).await;
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        // Don't ping the requesters
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                        .components(|comp| history_components(comp, &entries, page, pages))
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err)
+        }
+    };
+}
+
+/// Queues a track from the history again
+pub async fn handle_history_replay(ctx: &Context, command: &MessageComponentInteraction) {
+    let id = match command
+        .data
+        .custom_id
+        .trim_start_matches(HISTORY_REPLAY)
+        .parse::<u64>()
+    {
+        Ok(id) => id,
+        Err(_) => {
+            warn!("Invalid replay id: {}", command.data.custom_id);
+            return;
+        }
+    };
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    let (guild_id, channel_id) = get_guild_channel_id_from_interaction_message(command, ctx).await;
+    let connect_to = match not_in_a_voice_channel_message(channel_id, command, ctx).await {
+        Some(value) => value,
+        None => return,
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let entry = match get_play_history_entry(&pool, guild_id.0, id).await {
+        Some(entry) => entry,
+        None => {
+            edit_original_response_simple_content(command, ctx, "This entry no longer exists")
+                .await;
+            return;
+        }
+    };
+
+    if join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id)
+        .await
+        .is_err()
+    {
+        edit_original_response_simple_content(command, ctx, CANNOT_JOIN_VOICE).await;
+        return;
+    }
+
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => {
+            edit_original_response_simple_content(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+            return;
+        }
+    };
+    let tracks = match lavalink.get_tracks(&entry.uri).await {
+        Ok(tracks) => tracks,
+        Err(err) => {
+            warn!("Cannot load track {}: {}", entry.uri, err);
+            edit_original_response_simple_content(command, ctx, "Cannot load track").await;
+            return;
+        }
+    };
+
+    if tracks.tracks.is_empty() {
+        edit_original_response_simple_content(command, ctx, "Track is no longer available").await;
+        return;
+    }
+
+    match lavalink
+        .play(guild_id.0, tracks.tracks[0].clone())
+        .requester(command.user.id)
+        .queue()
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            warn!("Cannot play track: {}", err);
+            edit_original_response_simple_content(command, ctx, "Cannot play track").await;
+            return;
+        }
+    };
+
+    edit_original_response_simple_content(
+        command,
+        ctx,
+        format!("Replaying: {}", entry.title).as_str(),
+    )
+    .await;
+}
+
+/// Suggests previously played tracks. The value is the uri so that the command can play it directly
+pub async fn handle_history_autocomplete(ctx: &Context, autocomplete: &AutocompleteInteraction) {
+    let guild_id = match autocomplete.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    let partial = autocomplete
+        .data
+        .options
+        .iter()
+        .find(|option| option.focused)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .unwrap_or("");
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let titles =
+        search_play_history_titles(&pool, guild_id.0, partial, MAX_AUTOCOMPLETE_CHOICES).await;
+
+    match autocomplete
+        .create_autocomplete_response(&ctx.http, |response| {
+            for (title, uri) in titles
+                .iter()
+                .filter(|(_, uri)| !uri.is_empty() && uri.len() <= MAX_AUTOCOMPLETE_LENGTH)
+            {
+                let name: String = title.chars().take(MAX_AUTOCOMPLETE_LENGTH).collect();
+                response.add_string_choice(name, uri);
+            }
+            response
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to autocomplete {}", err)
+        }
+    };
+}
+
+async fn build_history_page(
+    pool: &mysql_async::Pool,
+    guild_id: u64,
+    page: u64,
+) -> (String, Vec<PlayHistory>, u64) {
+    let total = count_play_history(pool, guild_id).await;
+    let pages = ((total + HISTORY_PAGE_SIZE - 1) / HISTORY_PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let entries =
+        get_play_history(pool, guild_id, page * HISTORY_PAGE_SIZE, HISTORY_PAGE_SIZE).await;
+
+    if entries.is_empty() {
+        return ("Nothing has been played yet".to_string(), entries, pages);
+    }
+
+    let mut output = format!("Play history (page {}/{})\n", page + 1, pages);
+    for (i, entry) in entries.iter().enumerate() {
+        write!(
+            &mut output,
+            "{}) {} - <t:{}:R>",
+            page * HISTORY_PAGE_SIZE + i as u64 + 1,
+            entry.title,
+            entry.started_at
+        )
+        .expect("cannot write to buffer");
+        if let Some(requester_id) = entry.requester_id {
+            write!(&mut output, " by <@{}>", requester_id).expect("cannot write to buffer");
+        }
+        if entry.skipped == Some(true) {
+            write!(&mut output, " (skipped)").expect("cannot write to buffer");
+        }
+        writeln!(&mut output).expect("cannot write to buffer");
+    }
+
+    (output, entries, pages)
+}
+
+fn history_components<'a>(
+    comp: &'a mut CreateComponents,
+    entries: &[PlayHistory],
+    page: u64,
+    pages: u64,
+) -> &'a mut CreateComponents {
+    if entries.is_empty() {
+        return comp;
+    }
+
+    comp.create_action_row(|row| {
+        for (i, entry) in entries.iter().enumerate() {
+            row.create_button(|btn| {
+                btn.custom_id(format!("{}{}", HISTORY_REPLAY, entry.id))
+                    .label(format!("{}", page * HISTORY_PAGE_SIZE + i as u64 + 1))
+                    .emoji(ReactionType::Unicode("🔁".to_string()))
+                    .style(ButtonStyle::Secondary)
+            });
+        }
+        row
+    })
+    .create_action_row(|row| {
+        row.create_button(|btn| {
+            btn.custom_id(format!("{}{}", HISTORY_PAGE, page.saturating_sub(1)))
+                .emoji(ReactionType::Unicode("⬅️".to_string()))
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|btn| {
+            btn.custom_id(format!("{}{}", HISTORY_PAGE, page + 1))
+                .emoji(ReactionType::Unicode("➡️".to_string()))
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages)
+        })
+    })
+}
@@ -0,0 +1,624 @@
+use std::fmt::Write;
+
+use lavalink_rs::LavalinkClient;
+use mysql_async::Pool;
+use serenity::{
+    builder::{CreateActionRow, CreateComponents},
+    client::Context,
+    model::{
+        channel::ReactionType,
+        id::{GuildId, UserId},
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+                ApplicationCommandInteractionDataOptionValue,
+            },
+            autocomplete::AutocompleteInteraction,
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+        },
+    },
+};
+use tracing::warn;
+
+use crate::{
+    database::{
+        downloads::get_downloaded_media_id,
+        jam::{
+            count_jam_tracks, get_jam_track, get_jam_tracks, get_top_rated_jam_tracks,
+            get_weighted_jam_track, mark_jam_played, rate_jam_track, remove_jam_track,
+            search_jam_titles, JamTrack,
+        },
+        media::{get_media_file, touch_media_file},
+    },
+    events::interactions::{
+        application_command::{
+            edit_original_response_simple_content, get_option_by_name, get_subcommand,
+            play_jam_audio_from_string, send_defered_response, send_interaction_message_ephemeral,
+        },
+        database::add_track_to_db,
+        helpers::{
+            get_guild_channel_id_from_interaction_application, join_or_get_voice_channel,
+            member_is_dj, not_in_a_voice_channel_application, CANNOT_JOIN_VOICE,
+        },
+        interactions::voice_connection_taken,
+        lavalink::{
+            get_lavalink_client,
+            nodes::{music_backend_available, MUSIC_BACKEND_UNAVAILABLE},
+        },
+    },
+    features::downloads::queue_download,
+    helpers::{
+        db_helper::get_pool_from_ctx,
+        media_server::media_url,
+        media_store::{find_media_path, legacy_media_path},
+    },
+};
+
+pub const JAM_PAGE: &str = "jam_page_";
+pub const JAM_RATE_UP: &str = "jam_rate_up_";
+pub const JAM_RATE_DOWN: &str = "jam_rate_down_";
+
+const JAM_PAGE_SIZE: u64 = 10;
+// The jam button does not pick any of the last tracks it played
+const JAM_AVOID_RECENT: u64 = 5;
+const JAM_TOP_LIMIT: u64 = 10;
+// Keeps the page buttons under the 100 characters custom id limit
+const MAX_SEARCH_LENGTH: usize = 80;
+// Discord limit for autocomplete choices
+const MAX_AUTOCOMPLETE_CHOICES: u64 = 25;
+const MAX_AUTOCOMPLETE_LENGTH: usize = 100;
+
+/// Picks the next track for the jam button, favouring well rated ones
+pub async fn pick_jam_track(pool: &Pool, guild_id: u64) -> Option<JamTrack> {
+    match get_weighted_jam_track(pool, guild_id, JAM_AVOID_RECENT).await {
+        Some(track) => Some(track),
+        // The library is smaller than what we want to avoid
+        None => get_weighted_jam_track(pool, guild_id, 0).await,
+    }
+}
+
+/// Queues a jam track on lavalink, which loads it from the media server. Returns the message to
+/// show when it cannot
+pub async fn queue_jam_track(
+    ctx: &Context,
+    guild_id: GuildId,
+    track: &JamTrack,
+    requester: UserId,
+) -> Result<(), &'static str> {
+    let pool = get_pool_from_ctx(ctx).await;
+    let lavalink = get_lavalink_client(ctx, guild_id)
+        .await
+        .ok_or(MUSIC_BACKEND_UNAVAILABLE)?;
+
+    play_jam_track(&pool, &lavalink, guild_id.0, track, Some(requester)).await
+}
+
+/// `queue_jam_track` for callers without a `Context`. Autoplay queues without a requester
+pub async fn play_jam_track(
+    pool: &Pool,
+    lavalink: &LavalinkClient,
+    guild_id: u64,
+    track: &JamTrack,
+    requester: Option<UserId>,
+) -> Result<(), &'static str> {
+    let url = jam_track_url(pool, track)
+        .await
+        .ok_or("The file of this track is missing")?;
+
+    let tracks = match lavalink.get_tracks(&url).await {
+        Ok(tracks) => tracks,
+        Err(err) => {
+            warn!("Cannot load jam track {}: {}", url, err);
+            return Err("Cannot load track");
+        }
+    };
+    let mut lavalink_track = tracks
+        .tracks
+        .into_iter()
+        .next()
+        .ok_or("Cannot load track")?;
+    // The file name means nothing in /que
+    if let Some(info) = lavalink_track.info.as_mut() {
+        info.title = track.audio_name.to_owned();
+    }
+
+    let mut play = lavalink.play(guild_id, lavalink_track);
+    if let Some(requester) = requester {
+        play = play.requester(requester);
+    }
+    match play.queue().await {
+        Ok(_) => {
+            mark_jam_played(pool, track.id).await;
+            Ok(())
+        }
+        Err(err) => {
+            warn!("Cannot play jam track: {}", err);
+            Err("Cannot play track")
+        }
+    }
+}
+
+/// Url of the file of a jam track on the media server, `None` when the file is gone. Marks the file
+/// as used
+async fn jam_track_url(pool: &Pool, track: &JamTrack) -> Option<String> {
+    if let Some(media_id) = track.media_id {
+        touch_media_file(pool, media_id).await;
+    }
+    jam_track_uri(pool, track).await
+}
+
+/// The uri lavalink reports while playing the track
+pub async fn jam_track_uri(pool: &Pool, track: &JamTrack) -> Option<String> {
+    let path = match track.media_id {
+        Some(media_id) => find_media_path(pool, media_id).await?,
+        None => legacy_media_path(&track.audio_name, &track.ext)?,
+    };
+
+    media_url(&path)
+}
+
+/// `/jam list [search] [page]`, `/jam play <title>`, `/jam add <query>`, `/jam remove <title>`,
+/// `/jam info <title>` and `/jam top`
+pub async fn handle_jam(ctx: &Context, command: &ApplicationCommandInteraction) {
+    match get_subcommand(command) {
+        Some(("list", options)) => handle_jam_list(ctx, command, options).await,
+        Some(("play", options)) => handle_jam_play(ctx, command, options).await,
+        Some(("add", options)) => handle_jam_add(ctx, command, options).await,
+        Some(("remove", options)) => handle_jam_remove(ctx, command, options).await,
+        Some(("info", options)) => handle_jam_info(ctx, command, options).await,
+        Some(("top", _)) => handle_jam_top(ctx, command).await,
+        _ => send_interaction_message_ephemeral(command, ctx, "Unknown jam command").await,
+    }
+}
+
+fn get_string_option<'a>(
+    options: &'a [ApplicationCommandInteractionDataOption],
+    name: &str,
+) -> Option<&'a str> {
+    match get_option_by_name(options, name) {
+        Some(ApplicationCommandInteractionDataOptionValue::String(value)) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+async fn handle_jam_list(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let search = truncate_search(get_string_option(options, "search").unwrap_or(""));
+    let page = match get_option_by_name(options, "page") {
+        Some(ApplicationCommandInteractionDataOptionValue::Integer(page)) if *page > 0 => {
+            (*page - 1) as u64
+        }
+        _ => 0,
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let (content, page, pages) = build_jam_page(&pool, guild_id.0, &search, page).await;
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                        .components(|comp| jam_components(comp, &search, page, pages))
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
+/// The previous/next buttons of the `/jam list` message
+pub async fn handle_jam_page(ctx: &Context, command: &MessageComponentInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    // jam_page_<page>_<search>
+    let (page, search) = command
+        .data
+        .custom_id
+        .trim_start_matches(JAM_PAGE)
+        .split_once('_')
+        .unwrap_or(("0", ""));
+    let page = page.parse::<u64>().unwrap_or(0);
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let (content, page, pages) = build_jam_page(&pool, guild_id.0, search, page).await;
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                        .components(|comp| jam_components(comp, search, page, pages))
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err)
+        }
+    };
+}
+
+/// Returns the content, the page that is actually shown and the number of pages
+async fn build_jam_page(pool: &Pool, guild_id: u64, search: &str, page: u64) -> (String, u64, u64) {
+    let total = count_jam_tracks(pool, guild_id, search).await;
+    let pages = ((total + JAM_PAGE_SIZE - 1) / JAM_PAGE_SIZE).max(1);
+    let page = page.min(pages - 1);
+    let tracks = get_jam_tracks(pool, guild_id, search, page * JAM_PAGE_SIZE, JAM_PAGE_SIZE).await;
+
+    if tracks.is_empty() {
+        let content = if search.is_empty() {
+            "The jam library is empty".to_string()
+        } else {
+            format!("No jam tracks matching \"{}\"", search)
+        };
+        return (content, page, pages);
+    }
+
+    let mut output = if search.is_empty() {
+        format!("Jam library (page {}/{})\n", page + 1, pages)
+    } else {
+        format!(
+            "Jam tracks matching \"{}\" (page {}/{})\n",
+            search,
+            page + 1,
+            pages
+        )
+    };
+    for (i, track) in tracks.iter().enumerate() {
+        writeln!(
+            &mut output,
+            "{}) {}",
+            page * JAM_PAGE_SIZE + i as u64 + 1,
+            track.audio_name
+        )
+        .expect("cannot write to buffer");
+    }
+
+    (output, page, pages)
+}
+
+fn jam_components<'a>(
+    comp: &'a mut CreateComponents,
+    search: &str,
+    page: u64,
+    pages: u64,
+) -> &'a mut CreateComponents {
+    if pages <= 1 {
+        return comp;
+    }
+
+    comp.create_action_row(|row| {
+        row.create_button(|btn| {
+            btn.custom_id(format!("{}{}_{}", JAM_PAGE, page.saturating_sub(1), search))
+                .emoji(ReactionType::Unicode("⬅️".to_string()))
+                .style(ButtonStyle::Secondary)
+                .disabled(page == 0)
+        })
+        .create_button(|btn| {
+            btn.custom_id(format!("{}{}_{}", JAM_PAGE, page + 1, search))
+                .emoji(ReactionType::Unicode("➡️".to_string()))
+                .style(ButtonStyle::Secondary)
+                .disabled(page + 1 >= pages)
+        })
+    })
+}
+
+fn truncate_search(search: &str) -> String {
+    let mut truncated = String::new();
+    for c in search.chars() {
+        if truncated.len() + c.len_utf8() > MAX_SEARCH_LENGTH {
+            break;
+        }
+        truncated.push(c);
+    }
+    truncated
+}
+
+async fn handle_jam_play(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    // The rest of /jam works without lavalink, so it is not gated with the music commands
+    if !music_backend_available(&ctx.data).await {
+        send_interaction_message_ephemeral(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+        return;
+    }
+    if let Some(taken) = voice_connection_taken(ctx, command.guild_id).await {
+        send_interaction_message_ephemeral(command, ctx, taken).await;
+        return;
+    }
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    let (guild_id, channel_id) =
+        get_guild_channel_id_from_interaction_application(command, ctx).await;
+    let connect_to = match not_in_a_voice_channel_application(channel_id, command, ctx).await {
+        Some(value) => value,
+        None => return,
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let title = get_string_option(options, "title").unwrap_or("");
+    let track = match get_jam_track(&pool, guild_id.0, title).await {
+        Some(track) => track,
+        None => {
+            edit_original_response_simple_content(command, ctx, "No such jam track").await;
+            return;
+        }
+    };
+
+    if join_or_get_voice_channel(ctx, guild_id, connect_to, command.channel_id)
+        .await
+        .is_err()
+    {
+        edit_original_response_simple_content(command, ctx, CANNOT_JOIN_VOICE).await;
+        return;
+    }
+    if let Err(content) = queue_jam_track(ctx, guild_id, &track, command.user.id).await {
+        edit_original_response_simple_content(command, ctx, content).await;
+        return;
+    }
+
+    play_jam_audio_from_string(command, ctx, &track.audio_name, track.id).await;
+}
+
+async fn handle_jam_add(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let query = match get_string_option(options, "query") {
+        Some(query) if !query.trim().is_empty() => query.trim(),
+        _ => {
+            send_interaction_message_ephemeral(command, ctx, "Provide a url or a search").await;
+            return;
+        }
+    };
+
+    // Downloaded before, the file only has to go back in the library
+    let pool = get_pool_from_ctx(ctx).await;
+    let media = match get_downloaded_media_id(&pool, guild_id.0, query).await {
+        Some(media_id) => get_media_file(&pool, media_id).await,
+        None => None,
+    };
+
+    let content = match media {
+        Some(media) => {
+            match add_track_to_db(ctx.clone(), guild_id, &media, Some(command.user.id.0)).await {
+                Ok(_) => format!("Added {} to the jam library", media.title),
+                Err(err) => {
+                    warn!("Cannot add {} to the jam library: {}", media.title, err);
+                    "Cannot add the track to the jam library".to_string()
+                }
+            }
+        }
+        None => {
+            queue_download(ctx, query, guild_id, command.user.id).await;
+            "Downloading, the track is added to the jam library once done. See /downloads"
+                .to_string()
+        }
+    };
+    respond(ctx, command, &content).await;
+}
+
+async fn handle_jam_remove(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    if !member_is_dj(ctx, guild_id, command.member.as_ref()).await {
+        send_interaction_message_ephemeral(command, ctx, "Only DJs can remove jam tracks").await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let title = get_string_option(options, "title").unwrap_or("");
+    let content = if remove_jam_track(&pool, guild_id.0, title).await {
+        format!("Removed {} from the jam library", title)
+    } else {
+        "No such jam track".to_string()
+    };
+    respond(ctx, command, &content).await;
+}
+
+async fn handle_jam_info(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+    let title = get_string_option(options, "title").unwrap_or("");
+    let track = match get_jam_track(&pool, guild_id.0, title).await {
+        Some(track) => track,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, "No such jam track").await;
+            return;
+        }
+    };
+
+    let added = match (track.added_by, track.added_at) {
+        (Some(added_by), Some(added_at)) => format!("Added by <@{}> <t:{}:R>", added_by, added_at),
+        (None, Some(added_at)) => format!("Added <t:{}:R>", added_at),
+        _ => "Added before this was tracked".to_string(),
+    };
+    let mut output = format!("{}\n{}\n", track.audio_name, added);
+
+    if let Some(media) = match track.media_id {
+        Some(media_id) => get_media_file(&pool, media_id).await,
+        None => None,
+    } {
+        let seconds = media.duration_ms / 1000;
+        writeln!(
+            &mut output,
+            "Length {}:{:02}, {} ({:.1} MB)",
+            seconds / 60,
+            seconds % 60,
+            media.codec,
+            media.size_bytes as f64 / (1024.0 * 1024.0)
+        )
+        .expect("cannot write to buffer");
+        if !media.source_url.is_empty() {
+            writeln!(&mut output, "Source: <{}>", media.source_url)
+                .expect("cannot write to buffer");
+        }
+    }
+
+    respond(ctx, command, &output).await;
+}
+
+async fn handle_jam_top(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+    let tracks = get_top_rated_jam_tracks(&pool, guild_id.0, JAM_TOP_LIMIT).await;
+
+    if tracks.is_empty() {
+        respond(ctx, command, "No jam track has been rated up yet").await;
+        return;
+    }
+
+    let mut output = "Best rated jam tracks\n".to_string();
+    for (i, (title, score, votes)) in tracks.iter().enumerate() {
+        writeln!(
+            &mut output,
+            "{}) {} - {:+} ({} votes)",
+            i + 1,
+            title,
+            score,
+            votes
+        )
+        .expect("cannot write to buffer");
+    }
+    respond(ctx, command, &output).await;
+}
+
+/// The thumbs up/down buttons of the jam player
+pub fn jam_rating_buttons(row: &mut CreateActionRow, jam_id: u64) -> &mut CreateActionRow {
+    row.create_button(|btn| {
+        btn.custom_id(format!("{}{}", JAM_RATE_UP, jam_id))
+            .emoji(ReactionType::Unicode("👍".to_string()))
+            .style(ButtonStyle::Secondary)
+    })
+    .create_button(|btn| {
+        btn.custom_id(format!("{}{}", JAM_RATE_DOWN, jam_id))
+            .emoji(ReactionType::Unicode("👎".to_string()))
+            .style(ButtonStyle::Secondary)
+    })
+}
+
+/// A click on one of `jam_rating_buttons`
+pub async fn handle_jam_rate(ctx: &Context, command: &MessageComponentInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let custom_id = command.data.custom_id.as_str();
+    let (rating, id) = match custom_id.strip_prefix(JAM_RATE_UP) {
+        Some(id) => (1, id),
+        None => (-1, custom_id.trim_start_matches(JAM_RATE_DOWN)),
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let content = match id.parse::<u64>() {
+        Ok(id) if rate_jam_track(&pool, guild_id.0, id, command.user.id.0, rating).await => {
+            if rating > 0 {
+                "Rated up, it will come up more often"
+            } else {
+                "Rated down, it will come up less often"
+            }
+        }
+        _ => "This track is no longer in the jam library",
+    };
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to message component {}", err)
+        }
+    };
+}
+
+/// Suggests jam tracks for the title options
+pub async fn handle_jam_autocomplete(ctx: &Context, autocomplete: &AutocompleteInteraction) {
+    let guild_id = match autocomplete.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+    // The focused option is inside the subcommand
+    let partial = autocomplete
+        .data
+        .options
+        .iter()
+        .flat_map(|subcommand| subcommand.options.iter())
+        .find(|option| option.focused)
+        .and_then(|option| option.value.as_ref())
+        .and_then(|value| value.as_str())
+        .unwrap_or("");
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let titles = search_jam_titles(&pool, guild_id.0, partial, MAX_AUTOCOMPLETE_CHOICES).await;
+
+    match autocomplete
+        .create_autocomplete_response(&ctx.http, |response| {
+            // The value has to be the full title, longer ones cannot be suggested
+            for title in titles
+                .iter()
+                .filter(|title| title.chars().count() <= MAX_AUTOCOMPLETE_LENGTH)
+            {
+                response.add_string_choice(title, title);
+            }
+            response
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to autocomplete {}", err)
+        }
+    };
+}
+
+async fn respond(ctx: &Context, command: &ApplicationCommandInteraction, content: &str) {
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
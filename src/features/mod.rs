@@ -1 +1,13 @@
+pub mod autoplay;
 pub mod boss_music;
+pub mod clip;
+pub mod downloads;
+pub mod history;
+pub mod jam;
+pub mod recording;
+pub mod sleep;
+pub mod soundboard;
+pub mod stats;
+pub mod storage;
+pub mod voice_log;
+pub mod voice_stats;
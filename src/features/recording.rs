@@ -0,0 +1,655 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serenity::{
+    async_trait,
+    client::Context,
+    model::{
+        id::{ChannelId, GuildId, UserId},
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+            ApplicationCommandInteractionDataOptionValue,
+        },
+    },
+    prelude::{Mutex, RwLock, TypeMap, TypeMapKey},
+};
+use songbird::{
+    model::payload::{ClientDisconnect, Speaking},
+    CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler,
+};
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::{info, warn};
+
+use crate::{
+    events::interactions::{
+        application_command::{
+            get_option_by_name, get_subcommand, send_defered_response,
+            send_interaction_message_basic, send_interaction_message_ephemeral,
+        },
+        get_songbird_manager,
+        helpers::{get_guild_channel_id_from_interaction_application, member_can_manage_guild},
+    },
+    features::{
+        clip::{is_listening, release_clip_buffer, start_clip_buffer},
+        voice_stats::{start_speaking_tracker, stop_speaking_tracker},
+    },
+    helpers::main::{max_upload_bytes, mix_pcm_to_opus},
+};
+
+const DEFAULT_RECORDINGS_DIR: &str = "recordings";
+const DEFAULT_MAX_MINUTES: u64 = 60;
+const DEFAULT_RETENTION_DAYS: u64 = 7;
+const DEFAULT_MAX_UPLOAD_MB: u64 = 8;
+// Discord refuses messages with more attachments
+const MAX_ATTACHMENTS: usize = 10;
+// Decoded voice is 48kHz stereo
+const SAMPLES_PER_SECOND: f64 = 48_000.0 * 2.0;
+// Late packets within this many samples are appended instead of padded, it hides network jitter
+const JITTER_SAMPLES: u64 = 3840;
+const ENCODE_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub const RECORDING_IN_PROGRESS: &str =
+    "Music is unavailable while this server is being recorded, /record stop ends it";
+
+/// The recording running in each guild
+pub struct Recordings;
+impl TypeMapKey for Recordings {
+    type Value = Arc<Mutex<HashMap<u64, Arc<Recording>>>>;
+}
+
+pub struct Recording {
+    started_by: UserId,
+    voice_channel_id: ChannelId,
+    text_channel_id: ChannelId,
+    started_at: Instant,
+    per_user: bool,
+    /// Tells the handlers to unregister, the call can outlive the recording
+    stopped: AtomicBool,
+    /// Raw tracks are written here until the recording stops
+    raw_dir: PathBuf,
+    speakers: Mutex<Speakers>,
+}
+
+#[derive(Default)]
+struct Speakers {
+    users: HashMap<u32, u64>,
+    tracks: HashMap<u32, SpeakerTrack>,
+}
+
+struct SpeakerTrack {
+    path: PathBuf,
+    file: File,
+    samples: u64,
+}
+
+/// Songbird event handler writing the decoded audio of every speaker to their own track
+struct Receiver {
+    recording: Arc<Recording>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for Receiver {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if self.recording.stopped.load(Ordering::Relaxed) {
+            return Some(Event::Cancel);
+        }
+        match ctx {
+            EventContext::SpeakingStateUpdate(Speaking { ssrc, user_id, .. }) => {
+                if let Some(user_id) = user_id {
+                    let mut speakers = self.recording.speakers.lock().await;
+                    speakers.users.insert(*ssrc, user_id.0);
+                }
+            }
+            EventContext::VoicePacket(data) => {
+                if let Some(audio) = data.audio {
+                    self.recording.write_audio(data.packet.ssrc, audio).await;
+                }
+            }
+            EventContext::ClientDisconnect(ClientDisconnect { user_id, .. }) => {
+                info!("User {} left a recorded channel", user_id.0);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+impl Recording {
+    async fn write_audio(&self, ssrc: u32, audio: &[i16]) {
+        let mut speakers = self.speakers.lock().await;
+        // The tracks are being encoded
+        if self.stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        if !speakers.tracks.contains_key(&ssrc) {
+            let path = self.raw_dir.join(format!("{}.pcm", ssrc));
+            match File::create(&path).await {
+                Ok(file) => {
+                    speakers.tracks.insert(
+                        ssrc,
+                        SpeakerTrack {
+                            path,
+                            file,
+                            samples: 0,
+                        },
+                    );
+                }
+                Err(err) => {
+                    warn!("Cannot create {}: {}", path.display(), err);
+                    return;
+                }
+            }
+        }
+        let track = match speakers.tracks.get_mut(&ssrc) {
+            Some(track) => track,
+            None => return,
+        };
+
+        let expected = (self.started_at.elapsed().as_secs_f64() * SAMPLES_PER_SECOND) as u64 & !1;
+        let silence = silence_before(expected, track.samples, audio.len() as u64) as usize;
+        let mut bytes = Vec::with_capacity((silence + audio.len()) * 2);
+        bytes.resize(silence * 2, 0);
+        track.samples += silence as u64;
+        for sample in audio {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        track.samples += audio.len() as u64;
+
+        if let Err(err) = track.file.write_all(&bytes).await {
+            warn!("Cannot write {}: {}", track.path.display(), err);
+        }
+    }
+}
+
+/// Samples of silence to write before a packet of `len` samples so it ends `expected` samples into
+/// the recording, when the track holds `written` samples. Silence is not sent, padding keeps every
+/// track in sync. Packets arriving late or within the jitter are appended as they come
+pub(crate) fn silence_before(expected: u64, written: u64, len: u64) -> u64 {
+    let end = written + len;
+    if expected > end + JITTER_SAMPLES {
+        expected - end
+    } else {
+        0
+    }
+}
+
+/// Names of the per user files of the recording `name`, one for every raw track and its speaker.
+/// Speakers who reconnected have several tracks, they are kept apart
+pub(crate) fn per_user_file_names(name: &str, speakers: &[Option<u64>]) -> Vec<String> {
+    let mut seen = HashMap::new();
+    speakers
+        .iter()
+        .map(|user_id| {
+            let speaker = match user_id {
+                Some(user_id) => user_id.to_string(),
+                None => "unknown".to_string(),
+            };
+            let count = seen.entry(speaker.clone()).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                format!("{}-{}.ogg", name, speaker)
+            } else {
+                format!("{}-{}-{}.ogg", name, speaker, count)
+            }
+        })
+        .collect()
+}
+
+fn max_duration() -> Duration {
+    Duration::from_secs(
+        std::env::var("RECORD_MAX_MINUTES")
+            .ok()
+            .and_then(|minutes| minutes.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MAX_MINUTES)
+            * 60,
+    )
+}
+
+/// Where recordings are written. Comes from RECORDINGS_DIR. Never put it in the media store, the
+/// media server hands out everything in there without asking who wants it
+fn recordings_dir() -> PathBuf {
+    std::env::var("RECORDINGS_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_RECORDINGS_DIR))
+}
+
+/// How long recordings too big to upload are kept. Comes from RECORD_RETENTION_DAYS
+fn retention() -> Duration {
+    Duration::from_secs(
+        std::env::var("RECORD_RETENTION_DAYS")
+            .ok()
+            .and_then(|days| days.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_RETENTION_DAYS)
+            * 24
+            * 60
+            * 60,
+    )
+}
+
+/// True while the guild is being recorded. Music and entrance themes wait until it stops, the bot
+/// only has one voice connection per guild
+pub async fn is_recording(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) -> bool {
+    let recordings = match data.read().await.get::<Recordings>().cloned() {
+        Some(recordings) => recordings,
+        None => return false,
+    };
+    let recording = recordings.lock().await.contains_key(&guild_id.0);
+    recording
+}
+
+/// `/record start [per_user]` and `/record stop`
+pub async fn handle_record(ctx: &Context, command: &ApplicationCommandInteraction) {
+    match get_subcommand(command) {
+        Some(("start", options)) => handle_record_start(ctx, command, options).await,
+        Some(("stop", _)) => handle_record_stop(ctx, command).await,
+        _ => send_interaction_message_ephemeral(command, ctx, "Unknown subcommand").await,
+    }
+}
+
+async fn handle_record_start(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let (guild_id, voice_channel_id) =
+        get_guild_channel_id_from_interaction_application(command, ctx).await;
+    let voice_channel_id = match voice_channel_id {
+        Some(channel_id) => channel_id,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, "Not in a voice channel").await;
+            return;
+        }
+    };
+    let per_user = matches!(
+        get_option_by_name(options, "per_user"),
+        Some(ApplicationCommandInteractionDataOptionValue::Boolean(true))
+    );
+
+    let recordings = ctx.data.read().await.get::<Recordings>().cloned().unwrap();
+    if recordings.lock().await.contains_key(&guild_id.0) {
+        send_interaction_message_ephemeral(command, ctx, "Already recording in this server").await;
+        return;
+    }
+    let manager = get_songbird_manager(ctx).await;
+    // Lavalink holds the voice connection while music plays, songbird has to own it to listen. The
+    // call kept for /clip already does
+    let listening = match manager.get(guild_id) {
+        Some(handle_lock) => {
+            let channel_id = handle_lock.lock().await.current_channel();
+            if !is_listening(&ctx.data, guild_id).await
+                || channel_id != Some(voice_channel_id.into())
+            {
+                send_interaction_message_ephemeral(
+                    command,
+                    ctx,
+                    "I am already in a voice channel here, stop the music before recording",
+                )
+                .await;
+                return;
+            }
+            Some(handle_lock)
+        }
+        None => None,
+    };
+
+    let raw_dir = recordings_dir().join(format!(".{}-{}", guild_id.0, now_millis()));
+    if let Err(err) = tokio::fs::create_dir_all(&raw_dir).await {
+        warn!("Cannot create {}: {}", raw_dir.display(), err);
+        send_interaction_message_ephemeral(command, ctx, "Cannot start recording").await;
+        return;
+    }
+
+    let handle_lock = match listening {
+        Some(handle_lock) => handle_lock,
+        None => {
+            let (handle_lock, joined) = manager.join(guild_id, voice_channel_id).await;
+            if let Err(err) = joined {
+                warn!("Cannot join voice channel to record: {}", err);
+                let _ = manager.remove(guild_id).await;
+                let _ = tokio::fs::remove_dir_all(&raw_dir).await;
+                send_interaction_message_ephemeral(command, ctx, "Cannot join your voice channel")
+                    .await;
+                return;
+            }
+            start_clip_buffer(ctx, guild_id, voice_channel_id, &handle_lock, false).await;
+            start_speaking_tracker(ctx, guild_id, voice_channel_id, &handle_lock).await;
+            handle_lock
+        }
+    };
+
+    let recording = Arc::new(Recording {
+        started_by: command.user.id,
+        voice_channel_id,
+        text_channel_id: command.channel_id,
+        started_at: Instant::now(),
+        per_user,
+        stopped: AtomicBool::new(false),
+        raw_dir,
+        speakers: Mutex::new(Speakers::default()),
+    });
+    {
+        let mut handler = handle_lock.lock().await;
+        for event in [
+            CoreEvent::SpeakingStateUpdate,
+            CoreEvent::VoicePacket,
+            CoreEvent::ClientDisconnect,
+        ] {
+            handler.add_global_event(
+                Event::Core(event),
+                Receiver {
+                    recording: recording.clone(),
+                },
+            );
+        }
+    }
+    recordings
+        .lock()
+        .await
+        .insert(guild_id.0, recording.clone());
+    spawn_max_duration_task(ctx, guild_id, recording);
+
+    info!("Recording {} in guild {}", voice_channel_id, guild_id);
+    send_interaction_message_basic(
+        command,
+        ctx,
+        format!(
+            "🔴 **Recording started** in <#{}> by <@{}>. Everything said in that channel is being \
+             recorded until someone runs /record stop (at most {} minutes)",
+            voice_channel_id,
+            command.user.id,
+            max_duration().as_secs() / 60
+        )
+        .as_str(),
+    )
+    .await;
+}
+
+async fn handle_record_stop(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    let recordings = ctx.data.read().await.get::<Recordings>().cloned().unwrap();
+    let started_by = match recordings.lock().await.get(&guild_id.0) {
+        Some(recording) => recording.started_by,
+        None => {
+            send_interaction_message_ephemeral(command, ctx, "Nothing is being recorded").await;
+            return;
+        }
+    };
+    if started_by != command.user.id && !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            "Only the member who started the recording or a moderator can stop it",
+        )
+        .await;
+        return;
+    }
+
+    // Immediatly respond to the interaction which we will edit later, encoding takes a while
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+    let (content, files) = match stop_recording(ctx, guild_id).await {
+        Some(finished) => finished,
+        None => ("Nothing is being recorded".to_string(), Vec::new()),
+    };
+
+    if let Err(err) = command
+        .edit_original_interaction_response(ctx, |response| {
+            response
+                .content(content)
+                .allowed_mentions(|mentions| mentions.empty_parse())
+        })
+        .await
+    {
+        println!("Cannot respond to application command {}", err);
+    }
+    if !files.is_empty() {
+        match command
+            .create_followup_message(ctx, |message| {
+                message.add_files(files.iter().map(|path| path.as_path()))
+            })
+            .await
+        {
+            Ok(_) => remove_files(&files).await,
+            Err(err) => println!(
+                "Cannot send recording, it stays in {}: {}",
+                recordings_dir().display(),
+                err
+            ),
+        }
+    }
+}
+
+/// Stops the recording once it reaches RECORD_MAX_MINUTES
+fn spawn_max_duration_task(ctx: &Context, guild_id: GuildId, recording: Arc<Recording>) {
+    let ctx = ctx.clone();
+
+    tokio::spawn(async move {
+        tokio::time::sleep(max_duration()).await;
+
+        // The recording could have been stopped and another one started since
+        let recordings = ctx.data.read().await.get::<Recordings>().cloned().unwrap();
+        let is_active = match recordings.lock().await.get(&guild_id.0) {
+            Some(active) => Arc::ptr_eq(active, &recording),
+            None => false,
+        };
+        if !is_active {
+            return;
+        }
+
+        info!("Recording in guild {} reached its maximum length", guild_id);
+        let (content, files) = match stop_recording(&ctx, guild_id).await {
+            Some(finished) => finished,
+            None => return,
+        };
+        match recording
+            .text_channel_id
+            .send_message(&ctx.http, |message| {
+                message
+                    .content(format!("Recording reached its maximum length. {}", content))
+                    .allowed_mentions(|mentions| mentions.empty_parse())
+                    .add_files(files.iter().map(|path| path.as_path()))
+            })
+            .await
+        {
+            Ok(_) => remove_files(&files).await,
+            Err(err) => warn!(
+                "Cannot send recording, it stays in {}: {}",
+                recordings_dir().display(),
+                err
+            ),
+        }
+    });
+}
+
+/// Leaves the channel and encodes the recording. Returns the message announcing it and the files to
+/// attach, the caller removes them once uploaded. Files too big to upload stay in the recordings
+/// directory for RECORD_RETENTION_DAYS
+async fn stop_recording(ctx: &Context, guild_id: GuildId) -> Option<(String, Vec<PathBuf>)> {
+    let recordings = ctx.data.read().await.get::<Recordings>().cloned().unwrap();
+    let recording = recordings.lock().await.remove(&guild_id.0)?;
+
+    recording.stopped.store(true, Ordering::Relaxed);
+
+    let manager = get_songbird_manager(ctx).await;
+    if !release_clip_buffer(&ctx.data, guild_id).await {
+        stop_speaking_tracker(ctx, guild_id).await;
+        if let Err(err) = manager.remove(guild_id).await {
+            warn!("Cannot leave channel: {}", err);
+        }
+    }
+    let length = recording.started_at.elapsed();
+
+    // Nothing writes to the tracks anymore
+    let mut tracks = Vec::new();
+    {
+        let mut speakers = recording.speakers.lock().await;
+        let Speakers {
+            users,
+            tracks: speaker_tracks,
+        } = &mut *speakers;
+        for (ssrc, track) in speaker_tracks.iter_mut() {
+            if let Err(err) = track.file.flush().await {
+                warn!("Cannot write {}: {}", track.path.display(), err);
+            }
+            tracks.push((users.get(ssrc).copied(), track.path.clone()));
+        }
+    }
+
+    // The raw tracks are the only copy until the recording is encoded
+    let result = encode_recording(&recording, guild_id, &tracks).await;
+    match &result {
+        Ok(_) => {
+            if let Err(err) = tokio::fs::remove_dir_all(&recording.raw_dir).await {
+                warn!("Cannot remove {}: {}", recording.raw_dir.display(), err);
+            }
+        }
+        Err(err) => warn!(
+            "Cannot encode recording, the raw tracks stay in {}: {}",
+            recording.raw_dir.display(),
+            err
+        ),
+    }
+    info!("Recording in guild {} stopped", guild_id);
+    prune_recordings().await;
+
+    let minutes = length.as_secs() / 60;
+    let seconds = length.as_secs() % 60;
+    let summary = format!(
+        "Recording of <#{}> stopped after {}:{:02}",
+        recording.voice_channel_id, minutes, seconds
+    );
+    Some(match result {
+        Ok(files) if files.is_empty() => (format!("{}. Nobody spoke", summary), files),
+        Ok(files) => {
+            let size = total_size(&files).await;
+            if size <= max_upload_bytes("RECORD_MAX_UPLOAD_MB", DEFAULT_MAX_UPLOAD_MB)
+                && files.len() <= MAX_ATTACHMENTS
+            {
+                (summary, files)
+            } else {
+                let names = files
+                    .iter()
+                    .filter_map(|path| path.file_name().and_then(|name| name.to_str()))
+                    .map(|name| format!("`{}`", name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                (
+                    format!(
+                        "{}. It is too big to upload and was stored as {} for {} days",
+                        summary,
+                        names,
+                        retention().as_secs() / (24 * 60 * 60)
+                    ),
+                    Vec::new(),
+                )
+            }
+        }
+        Err(_) => (
+            format!(
+                "{}. Cannot encode it, the raw audio was kept for an admin to recover",
+                summary
+            ),
+            Vec::new(),
+        ),
+    })
+}
+
+/// Writes the mix, and with `per_user` a track for every speaker, next to the raw tracks
+async fn encode_recording(
+    recording: &Recording,
+    guild_id: GuildId,
+    tracks: &[(Option<u64>, PathBuf)],
+) -> Result<Vec<PathBuf>, String> {
+    if tracks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let name = format!("{}-{}", guild_id.0, now_millis());
+    let mix = recordings_dir().join(format!("{}.ogg", name));
+    let inputs = tracks
+        .iter()
+        .map(|(_, path)| path.clone())
+        .collect::<Vec<_>>();
+    mix_pcm_to_opus(&inputs, &mix, ENCODE_TIMEOUT).await?;
+
+    let mut files = vec![mix];
+    if recording.per_user {
+        let speakers = tracks
+            .iter()
+            .map(|(user_id, _)| *user_id)
+            .collect::<Vec<_>>();
+        let file_names = per_user_file_names(&name, &speakers);
+        for ((_, path), file_name) in tracks.iter().zip(file_names) {
+            let output = recordings_dir().join(file_name);
+            mix_pcm_to_opus(std::slice::from_ref(path), &output, ENCODE_TIMEOUT).await?;
+            files.push(output);
+        }
+    }
+
+    Ok(files)
+}
+
+async fn remove_files(files: &[PathBuf]) {
+    for path in files {
+        if let Err(err) = tokio::fs::remove_file(path).await {
+            warn!("Cannot remove {}: {}", path.display(), err);
+        }
+    }
+}
+
+/// Removes the recordings older than RECORD_RETENTION_DAYS. Raw tracks of running recordings are
+/// in directories and left alone
+async fn prune_recordings() {
+    let dir = recordings_dir();
+    let mut files = match tokio::fs::read_dir(&dir).await {
+        Ok(files) => files,
+        Err(err) => {
+            warn!("Cannot read {}: {}", dir.display(), err);
+            return;
+        }
+    };
+    let retention = retention();
+    while let Ok(Some(file)) = files.next_entry().await {
+        let expired = match file.metadata().await {
+            Ok(metadata) if metadata.is_file() => metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .map_or(false, |age| age >= retention),
+            _ => false,
+        };
+        if expired {
+            let path = file.path();
+            if let Err(err) = tokio::fs::remove_file(&path).await {
+                warn!("Cannot remove {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+async fn total_size(files: &[PathBuf]) -> u64 {
+    let mut size = 0;
+    for path in files {
+        size += file_size(path).await;
+    }
+    size
+}
+
+async fn file_size(path: &Path) -> u64 {
+    tokio::fs::metadata(path)
+        .await
+        .map_or(0, |metadata| metadata.len())
+}
+
+fn now_millis() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
@@ -0,0 +1,247 @@
+use std::{sync::Arc, time::Duration};
+
+use lavalink_rs::LavalinkClient;
+use serenity::{
+    client::Context,
+    model::{
+        id::GuildId,
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+        },
+    },
+    prelude::{RwLock, TypeMap},
+};
+use songbird::serenity::SongbirdKey;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{
+    events::interactions::{
+        application_command::{
+            get_option_by_name, get_subcommand, send_interaction_message_basic,
+            send_interaction_message_ephemeral,
+        },
+        lavalink::nodes::lavalink_client_for_guild,
+    },
+    GuildTrack, GuildTrackMap, Lavalink,
+};
+
+pub enum SleepTimer {
+    /// Stop at a given time. `until` is a unix timestamp
+    At { until: i64, task: JoinHandle<()> },
+    /// Stop when the current track ends
+    AfterTrack,
+}
+
+/// `/sleep duration <minutes>`, `/sleep after-track` and `/sleep cancel`
+pub async fn handle_sleep(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let guild_track = ctx
+        .data
+        .read()
+        .await
+        .get::<GuildTrackMap>()
+        .expect("cannot get GuildTrackMap")
+        .clone();
+
+    let content = match get_subcommand(command) {
+        Some(("duration", options)) => {
+            let minutes = match get_option_by_name(options, "minutes") {
+                Some(ApplicationCommandInteractionDataOptionValue::Integer(minutes))
+                    if *minutes > 0 =>
+                {
+                    *minutes
+                }
+                _ => {
+                    send_interaction_message_ephemeral(
+                        command,
+                        ctx,
+                        "Provide a positive number of minutes",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            let until = chrono::Utc::now().timestamp() + minutes * 60;
+            let mut mutex_guard = guild_track.lock().await;
+            match mutex_guard.get_mut(&guild_id.0) {
+                Some(guild_track) => {
+                    let task = spawn_sleep_task(
+                        ctx,
+                        guild_id.0,
+                        until,
+                        Duration::from_secs(minutes as u64 * 60),
+                    );
+                    replace_timer(guild_track, Some(SleepTimer::At { until, task }));
+                    format!("Stopping <t:{}:R>", until)
+                }
+                None => "bot is not present in a voice channel".to_string(),
+            }
+        }
+        Some(("after-track", _)) => {
+            let mut mutex_guard = guild_track.lock().await;
+            match mutex_guard.get_mut(&guild_id.0) {
+                Some(guild_track) => {
+                    replace_timer(guild_track, Some(SleepTimer::AfterTrack));
+                    "Stopping after the current track".to_string()
+                }
+                None => "bot is not present in a voice channel".to_string(),
+            }
+        }
+        Some(("cancel", _)) => {
+            let mut mutex_guard = guild_track.lock().await;
+            match mutex_guard.get_mut(&guild_id.0) {
+                Some(guild_track) if replace_timer(guild_track, None) => {
+                    "Sleep timer cancelled".to_string()
+                }
+                _ => "No sleep timer set".to_string(),
+            }
+        }
+        _ => "Unkown sleep option".to_string(),
+    };
+
+    send_interaction_message_basic(command, ctx, &content).await;
+}
+
+/// Text appended to the now playing message
+pub async fn sleep_timer_status(ctx: &Context, guild_id: u64) -> String {
+    let guild_track = ctx
+        .data
+        .read()
+        .await
+        .get::<GuildTrackMap>()
+        .expect("cannot get GuildTrackMap")
+        .clone();
+    let mutex_guard = guild_track.lock().await;
+
+    match mutex_guard
+        .get(&guild_id)
+        .and_then(|guild_track| guild_track.sleep_timer.as_ref())
+    {
+        Some(SleepTimer::At { until, .. }) => format!("\nStopping <t:{}:R>", until),
+        Some(SleepTimer::AfterTrack) => "\nStopping after this track".to_string(),
+        None => String::new(),
+    }
+}
+
+/// Called from `LavalinkHandler::track_finish`. Leaves when the timer was set to the end of the track
+pub async fn on_track_finish(
+    data: &Arc<RwLock<TypeMap>>,
+    lavalink: &LavalinkClient,
+    guild_id: u64,
+) {
+    let guild_track = data.read().await.get::<GuildTrackMap>().cloned().unwrap();
+
+    let after_track = {
+        let mut mutex_guard = guild_track.lock().await;
+        match mutex_guard.get_mut(&guild_id) {
+            Some(guild_track)
+                if matches!(guild_track.sleep_timer, Some(SleepTimer::AfterTrack)) =>
+            {
+                guild_track.sleep_timer = None;
+                true
+            }
+            _ => false,
+        }
+    };
+
+    if after_track {
+        info!("Sleep timer reached in guild {}", guild_id);
+        stop_and_leave(data, Some(lavalink), guild_id).await;
+    }
+}
+
+/// Clears the lavalink player and queue and leaves the voice channel. Without a client, while
+/// every node is down, the bot still leaves
+pub async fn stop_and_leave(
+    data: &Arc<RwLock<TypeMap>>,
+    lavalink: Option<&LavalinkClient>,
+    guild_id: u64,
+) {
+    if let Some(lavalink) = lavalink {
+        match lavalink.destroy(guild_id).await {
+            Ok(_) => {}
+            Err(err) => {
+                warn!("Cannot destroy lavalink player: {}", err);
+            }
+        }
+        let nodes = lavalink.nodes().await;
+        nodes.remove(&guild_id);
+
+        let loops = lavalink.loops().await;
+        loops.remove(&guild_id);
+    }
+
+    {
+        let nodes = data.read().await.get::<Lavalink>().cloned().unwrap();
+        nodes.write().await.release_guild(guild_id);
+    }
+    {
+        let guild_track = data.read().await.get::<GuildTrackMap>().cloned().unwrap();
+        if let Some(guild_track) = guild_track.lock().await.get_mut(&guild_id) {
+            replace_timer(guild_track, None);
+            guild_track.boss_music_visit = false;
+        }
+    }
+
+    let manager = data.read().await.get::<SongbirdKey>().cloned();
+    if let Some(manager) = manager {
+        match manager.remove(GuildId(guild_id)).await {
+            Ok(_) => {
+                info!("Call removed")
+            }
+            Err(err) => {
+                warn!("Cannot leave channel: {}", err);
+            }
+        }
+    }
+}
+
+/// Sets the timer of the guild, aborting the previous one. Returns whether a timer was set before
+fn replace_timer(guild_track: &mut GuildTrack, timer: Option<SleepTimer>) -> bool {
+    match std::mem::replace(&mut guild_track.sleep_timer, timer) {
+        Some(SleepTimer::At { task, .. }) => {
+            task.abort();
+            true
+        }
+        Some(SleepTimer::AfterTrack) => true,
+        None => false,
+    }
+}
+
+fn spawn_sleep_task(
+    ctx: &Context,
+    guild_id: u64,
+    until: i64,
+    duration: Duration,
+) -> JoinHandle<()> {
+    let data = ctx.data.clone();
+
+    tokio::spawn(async move {
+        tokio::time::sleep(duration).await;
+
+        let guild_track = data.read().await.get::<GuildTrackMap>().cloned().unwrap();
+
+        // The bot could have left and joined again since, only act if this is still the active timer
+        let is_active = {
+            let mut mutex_guard = guild_track.lock().await;
+            match mutex_guard.get_mut(&guild_id) {
+                Some(guild_track) => match guild_track.sleep_timer {
+                    Some(SleepTimer::At { until: active, .. }) if active == until => {
+                        guild_track.sleep_timer = None;
+                        true
+                    }
+                    _ => false,
+                },
+                None => false,
+            }
+        };
+
+        if is_active {
+            info!("Sleep timer reached in guild {}", guild_id);
+            let lavalink = lavalink_client_for_guild(&data, guild_id).await;
+            stop_and_leave(&data, lavalink.as_ref(), guild_id).await;
+        }
+    })
+}
@@ -0,0 +1,771 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serenity::{
+    async_trait,
+    builder::CreateComponents,
+    client::Context,
+    model::{
+        id::{ChannelId, GuildId, UserId},
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+                ApplicationCommandInteractionDataOptionValue,
+            },
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionResponseType,
+        },
+    },
+    prelude::{Mutex, RwLock, TypeMap, TypeMapKey},
+};
+use songbird::{
+    input::restartable::Restartable, tracks::TrackHandle, Driver, Event, EventContext,
+    EventHandler as VoiceEventHandler, TrackEvent,
+};
+use tracing::{info, warn};
+
+use crate::{
+    database::soundboard::{
+        count_soundboard_clips, get_soundboard_clip, get_soundboard_clip_by_name,
+        get_soundboard_clips, remove_soundboard_clip, set_soundboard_clip, SoundboardClip,
+    },
+    events::interactions::{
+        application_command::{
+            self, get_option_by_name, get_subcommand, send_defered_response,
+            send_interaction_message_ephemeral,
+        },
+        get_songbird_manager,
+        helpers::{get_guild_channel_id_from_interaction_message, member_can_manage_guild},
+        lavalink::{
+            get_lavalink_client,
+            nodes::{music_backend_available, MUSIC_BACKEND_UNAVAILABLE},
+        },
+        message_component::{
+            edit_original_response_simple_content, send_defered_response_ephemeral,
+        },
+    },
+    features::{
+        boss_music::{clip_bounds, is_short_clip_uri, play_short_clip},
+        clip::is_listening,
+        recording::is_recording,
+    },
+    helpers::{
+        db_helper::get_pool_from_ctx,
+        main::{self, download_attachment, env_or, max_upload_bytes, store_opus_clip},
+        media_server::media_url,
+        media_store::{media_root, stream_url, STAGING_DIR},
+    },
+    GuildTrackMap, Lavalink,
+};
+
+pub const SOUNDBOARD_PLAY: &str = "soundboard_play_";
+pub const SOUNDBOARD_MIXING: &str =
+    "A soundboard clip is playing over the music, try again once it ended";
+
+const SOUNDBOARD_DIR: &str = "soundboard";
+// Five rows of five buttons, the most a message can hold
+const SOUNDBOARD_LIMIT: u64 = 25;
+const BUTTONS_PER_ROW: usize = 5;
+const MAX_NAME_LENGTH: usize = 32;
+const DEFAULT_COOLDOWN_SECS: u64 = 10;
+const DEFAULT_MAX_CLIP_SECS: f64 = 10.0;
+const DEFAULT_MAX_UPLOAD_MB: u64 = 8;
+
+/// The call songbird took over from lavalink in a guild to mix clips over the music
+pub struct Mix {
+    voice_channel_id: ChannelId,
+    // The music songbird plays in place of lavalink, `None` when lavalink was paused
+    music: Option<TrackHandle>,
+    // Where lavalink was when songbird took over
+    position: Duration,
+    started_at: Instant,
+    // Clips still playing, the call goes back to lavalink once none is left
+    clips: usize,
+}
+
+pub struct SoundboardMixes;
+impl TypeMapKey for SoundboardMixes {
+    type Value = Arc<Mutex<HashMap<u64, Mix>>>;
+}
+
+/// When each (guild, user) last played a clip
+pub struct SoundboardCooldowns;
+impl TypeMapKey for SoundboardCooldowns {
+    type Value = Arc<Mutex<HashMap<(u64, u64), Instant>>>;
+}
+
+/// Where the clips of every guild are kept, next to the entrance themes
+pub fn soundboard_dir() -> PathBuf {
+    media_root().join(SOUNDBOARD_DIR)
+}
+
+/// True if lavalink is playing a soundboard clip
+pub fn is_soundboard_uri(uri: &str) -> bool {
+    match media_url(&soundboard_dir()) {
+        Some(url) => uri.starts_with(&format!("{}/", url)),
+        None => false,
+    }
+}
+
+/// `/soundboard show` and for admins `/soundboard add <name> <file> [start] [duration]` and
+/// `/soundboard remove <name>`
+pub async fn handle_soundboard(ctx: &Context, command: &ApplicationCommandInteraction) {
+    match get_subcommand(command) {
+        Some(("show", _)) => handle_soundboard_show(ctx, command).await,
+        Some(("add", options)) => handle_soundboard_add(ctx, command, options).await,
+        Some(("remove", options)) => handle_soundboard_remove(ctx, command, options).await,
+        _ => send_interaction_message_ephemeral(command, ctx, "Unknown subcommand").await,
+    }
+}
+
+/// Posts a button for every clip of the guild
+async fn handle_soundboard_show(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+    let clips = get_soundboard_clips(&pool, guild_id.0, SOUNDBOARD_LIMIT).await;
+
+    if clips.is_empty() {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            "The soundboard is empty, an admin can add clips with /soundboard add",
+        )
+        .await;
+        return;
+    }
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content("Soundboard")
+                        .components(|comp| soundboard_components(comp, &clips))
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
+fn soundboard_components<'a>(
+    comp: &'a mut CreateComponents,
+    clips: &[SoundboardClip],
+) -> &'a mut CreateComponents {
+    for row_clips in clips.chunks(BUTTONS_PER_ROW) {
+        comp.create_action_row(|row| {
+            for clip in row_clips {
+                row.create_button(|btn| {
+                    btn.custom_id(format!("{}{}", SOUNDBOARD_PLAY, clip.id))
+                        .label(&clip.name)
+                        .style(ButtonStyle::Secondary)
+                });
+            }
+            row
+        });
+    }
+    comp
+}
+
+/// Converts an uploaded file to a clip of the soundboard. A clip with the same name is replaced
+async fn handle_soundboard_add(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let name = match get_option_by_name(options, "name") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(name))
+            if !name.trim().is_empty() && name.trim().chars().count() <= MAX_NAME_LENGTH =>
+        {
+            name.trim()
+        }
+        _ => {
+            send_interaction_message_ephemeral(
+                command,
+                ctx,
+                format!("Name the clip with at most {} characters", MAX_NAME_LENGTH).as_str(),
+            )
+            .await;
+            return;
+        }
+    };
+    let attachment = match get_option_by_name(options, "file") {
+        Some(ApplicationCommandInteractionDataOptionValue::Attachment(attachment)) => attachment,
+        _ => {
+            send_interaction_message_ephemeral(command, ctx, "Attach an audio file").await;
+            return;
+        }
+    };
+    let max_clip_secs = env_or("SOUNDBOARD_MAX_SECONDS", DEFAULT_MAX_CLIP_SECS);
+    let (start, duration) = match clip_bounds(ctx, command, options, max_clip_secs).await {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    let max_upload_bytes = max_upload_bytes("SOUNDBOARD_MAX_UPLOAD_MB", DEFAULT_MAX_UPLOAD_MB);
+    if attachment.size > max_upload_bytes {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            format!(
+                "The file can be at most {} MB",
+                max_upload_bytes / 1024 / 1024
+            )
+            .as_str(),
+        )
+        .await;
+        return;
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    if get_soundboard_clip_by_name(&pool, guild_id.0, name)
+        .await
+        .is_none()
+        && count_soundboard_clips(&pool, guild_id.0).await >= SOUNDBOARD_LIMIT
+    {
+        send_interaction_message_ephemeral(
+            command,
+            ctx,
+            format!(
+                "The soundboard holds at most {} clips, remove one first",
+                SOUNDBOARD_LIMIT
+            )
+            .as_str(),
+        )
+        .await;
+        return;
+    }
+
+    // Immediatly respond to the interaction which we will edit later
+    if send_defered_response(command, ctx).await {
+        return;
+    }
+
+    let upload = media_root()
+        .join(STAGING_DIR)
+        .join(format!("soundboard-{}-{}", guild_id.0, command.user.id.0));
+    let result = match download_attachment(&attachment.url, &upload, max_upload_bytes).await {
+        Ok(_) => store_soundboard_clip(guild_id, &upload, start, duration).await,
+        Err(err) => Err(err),
+    };
+    let _ = tokio::fs::remove_file(&upload).await;
+
+    let content = match result {
+        Ok((file_name, length)) => {
+            let previous =
+                set_soundboard_clip(&pool, guild_id.0, name, &file_name, command.user.id.0).await;
+            if let Some(previous) = previous.filter(|previous| *previous != file_name) {
+                let _ = tokio::fs::remove_file(soundboard_dir().join(previous)).await;
+            }
+            format!("Added {} to the soundboard ({:.1} seconds)", name, length)
+        }
+        Err(err) => format!("Cannot use this file: {}", err),
+    };
+    application_command::edit_original_response_simple_content(command, ctx, content.as_str())
+        .await;
+}
+
+/// Cuts and normalizes the clip. Returns the file name and the length of the clip
+async fn store_soundboard_clip(
+    guild_id: GuildId,
+    input: &Path,
+    start: f64,
+    duration: f64,
+) -> Result<(String, f64), String> {
+    let file_name = format!(
+        "{}-{}.ogg",
+        guild_id.0,
+        chrono::Utc::now().timestamp_millis()
+    );
+    let length = store_opus_clip(input, &soundboard_dir(), &file_name, start, duration).await?;
+
+    Ok((file_name, length))
+}
+
+async fn handle_soundboard_remove(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let name = match get_option_by_name(options, "name") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(name)) => name.trim(),
+        _ => "",
+    };
+    let pool = get_pool_from_ctx(ctx).await;
+    let content = match get_soundboard_clip_by_name(&pool, guild_id.0, name).await {
+        Some(clip) => {
+            remove_soundboard_clip(&pool, guild_id.0, clip.id).await;
+            let _ = tokio::fs::remove_file(soundboard_dir().join(&clip.file_name)).await;
+            format!("Removed {} from the soundboard", clip.name)
+        }
+        None => format!("There is no clip named {}", name),
+    };
+    send_interaction_message_ephemeral(command, ctx, content.as_str()).await;
+}
+
+/// A button of `/soundboard show`. The clip is mixed over the music, songbird takes the call over
+/// from lavalink for that. When nothing plays lavalink plays the clip alone
+pub async fn handle_soundboard_play(ctx: &Context, command: &MessageComponentInteraction) {
+    // Joining and loading the clip can take longer than an interaction may wait
+    if send_defered_response_ephemeral(command, ctx).await {
+        return;
+    }
+
+    let (guild_id, voice_channel_id) =
+        get_guild_channel_id_from_interaction_message(command, ctx).await;
+    let voice_channel_id = match voice_channel_id {
+        Some(channel_id) => channel_id,
+        None => {
+            edit_original_response_simple_content(command, ctx, "Join a voice channel first").await;
+            return;
+        }
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let clip = match command
+        .data
+        .custom_id
+        .trim_start_matches(SOUNDBOARD_PLAY)
+        .parse::<u64>()
+    {
+        Ok(id) => get_soundboard_clip(&pool, guild_id.0, id).await,
+        Err(_) => None,
+    };
+    let clip = match clip {
+        Some(clip) => clip,
+        None => {
+            edit_original_response_simple_content(
+                command,
+                ctx,
+                "This clip was removed from the soundboard",
+            )
+            .await;
+            return;
+        }
+    };
+    let path = soundboard_dir().join(&clip.file_name);
+    if !path.is_file() {
+        warn!("Soundboard file {} is missing", path.display());
+        edit_original_response_simple_content(command, ctx, "The file of this clip is missing")
+            .await;
+        return;
+    }
+
+    let songbird_call =
+        is_recording(&ctx.data, guild_id).await || is_listening(&ctx.data, guild_id).await;
+    if !songbird_call && !music_backend_available(&ctx.data).await {
+        edit_original_response_simple_content(command, ctx, MUSIC_BACKEND_UNAVAILABLE).await;
+        return;
+    }
+    if let Some(remaining) = cooldown_remaining(ctx, guild_id, command.user.id).await {
+        edit_original_response_simple_content(
+            command,
+            ctx,
+            format!(
+                "Wait {} more seconds before playing another clip",
+                remaining.as_secs() + 1
+            )
+            .as_str(),
+        )
+        .await;
+        return;
+    }
+
+    let played = if songbird_call {
+        play_through_songbird(ctx, guild_id, voice_channel_id, &path).await
+    } else if lavalink_plays_music(ctx, guild_id).await {
+        mix_over_lavalink(ctx, guild_id, voice_channel_id, &path).await
+    } else {
+        play_short_clip(
+            ctx,
+            guild_id,
+            voice_channel_id,
+            command.user.id,
+            &path,
+            &clip.name,
+        )
+        .await
+    };
+
+    if played {
+        start_cooldown(ctx, guild_id, command.user.id).await;
+    }
+
+    let content = if played {
+        info!(
+            "Playing soundboard clip {} in guild {}",
+            clip.name, guild_id
+        );
+        format!("Playing {}", clip.name)
+    } else {
+        "I cannot play it, I am in another voice channel".to_string()
+    };
+    edit_original_response_simple_content(command, ctx, content.as_str()).await;
+}
+
+/// Mixes the clip over whatever songbird plays. Only works in the channel the bot is in
+async fn play_through_songbird(
+    ctx: &Context,
+    guild_id: GuildId,
+    voice_channel_id: ChannelId,
+    path: &Path,
+) -> bool {
+    let manager = get_songbird_manager(ctx).await;
+    let handle_lock = match manager.get(guild_id) {
+        Some(handle_lock) => handle_lock,
+        None => return false,
+    };
+    let mut handler = handle_lock.lock().await;
+    if handler.current_channel() != Some(voice_channel_id.into()) {
+        return false;
+    }
+
+    match songbird::ffmpeg(path).await {
+        Ok(source) => {
+            handler.play_source(source);
+            true
+        }
+        Err(err) => {
+            warn!("Cannot open soundboard clip {}: {}", path.display(), err);
+            false
+        }
+    }
+}
+
+/// True while songbird holds the call to mix clips over the music. Lavalink gets it back once the
+/// clips ended
+pub async fn is_mixing(data: &Arc<RwLock<TypeMap>>, guild_id: GuildId) -> bool {
+    let mixes = get_mixes(data).await;
+    let mixing = mixes.lock().await.contains_key(&guild_id.0);
+    mixing
+}
+
+/// True while lavalink has music in the guild, paused or not. Entrance themes and clips queued
+/// through lavalink do not count, they are short enough to wait for
+async fn lavalink_plays_music(ctx: &Context, guild_id: GuildId) -> bool {
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => return false,
+    };
+    let nodes = lavalink.nodes().await;
+
+    match nodes
+        .get(&guild_id.0)
+        .and_then(|node| node.now_playing.as_ref())
+        .and_then(|now_playing| now_playing.track.info.as_ref())
+    {
+        Some(info) => !is_short_clip_uri(&info.uri),
+        None => false,
+    }
+}
+
+/// Lavalink streams straight to discord and cannot mix. Songbird takes the call over, plays the
+/// music from where lavalink was with the clip on top, and hands the call back once the last clip
+/// ended. Clips pressed in the meantime join the mix
+async fn mix_over_lavalink(
+    ctx: &Context,
+    guild_id: GuildId,
+    voice_channel_id: ChannelId,
+    path: &Path,
+) -> bool {
+    let clip = match songbird::ffmpeg(path).await {
+        Ok(clip) => clip,
+        Err(err) => {
+            warn!("Cannot open soundboard clip {}: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    // Held until the clip plays so that two clips cannot both take the call over
+    let mixes = get_mixes(&ctx.data).await;
+    let mut mixes = mixes.lock().await;
+    let manager = get_songbird_manager(ctx).await;
+    let handle_lock = match manager.get(guild_id) {
+        Some(handle_lock) => handle_lock,
+        None => return false,
+    };
+    if handle_lock.lock().await.current_channel() != Some(voice_channel_id.into()) {
+        return false;
+    }
+
+    if !mixes.contains_key(&guild_id.0) {
+        let mix = match take_call_from_lavalink(ctx, guild_id, voice_channel_id).await {
+            Some(mix) => mix,
+            None => return false,
+        };
+        mixes.insert(guild_id.0, mix);
+    }
+
+    let mut handler = handle_lock.lock().await;
+    let clip = handler.play_source(clip);
+    if let Err(err) = clip.add_event(
+        Event::Track(TrackEvent::End),
+        ClipEnd {
+            ctx: ctx.clone(),
+            guild_id,
+        },
+    ) {
+        warn!("Cannot follow soundboard clip {}: {}", path.display(), err);
+    }
+    if let Some(mix) = mixes.get_mut(&guild_id.0) {
+        mix.clips += 1;
+    }
+
+    true
+}
+
+/// Pauses lavalink and plays its track through songbird from the same position
+async fn take_call_from_lavalink(
+    ctx: &Context,
+    guild_id: GuildId,
+    voice_channel_id: ChannelId,
+) -> Option<Mix> {
+    let lavalink = get_lavalink_client(ctx, guild_id).await?;
+    let (uri, paused) = {
+        let nodes = lavalink.nodes().await;
+        let node = nodes.get(&guild_id.0)?;
+        let info = node.now_playing.as_ref()?.track.info.as_ref()?;
+        (info.uri.clone(), node.is_paused)
+    };
+    let position = {
+        let guild_track = ctx
+            .data
+            .read()
+            .await
+            .get::<GuildTrackMap>()
+            .cloned()
+            .unwrap();
+        let guild_track = guild_track.lock().await;
+        let guild_track = guild_track.get(&guild_id.0)?;
+        let position = Duration::from_millis(guild_track.position.max(0) as u64);
+        if paused {
+            position
+        } else {
+            position + guild_track.how_long.elapsed()
+        }
+    };
+
+    // A paused track stays silent, only the clip plays then
+    let music = if paused {
+        None
+    } else {
+        let url = match stream_url(&uri).await {
+            Ok(url) => url,
+            Err(err) => {
+                warn!("Cannot stream {} through songbird: {}", uri, err);
+                return None;
+            }
+        };
+        match Restartable::ffmpeg(url, true).await {
+            Ok(music) => Some(music),
+            Err(err) => {
+                warn!("Cannot stream {} through songbird: {}", uri, err);
+                return None;
+            }
+        }
+    };
+
+    if !paused {
+        if let Err(err) = lavalink.set_pause(guild_id.0, true).await {
+            warn!("Cannot pause lavalink in guild {}: {}", guild_id, err);
+            return None;
+        }
+    }
+    let manager = get_songbird_manager(ctx).await;
+    let (handle_lock, joined) = manager.join(guild_id, voice_channel_id).await;
+    if let Err(err) = joined {
+        warn!("Cannot take the voice connection from lavalink: {}", err);
+        give_call_back_to_lavalink(ctx, guild_id, voice_channel_id, position, paused).await;
+        return None;
+    }
+
+    let mut handler = handle_lock.lock().await;
+    // The notifier of the lavalink call reports songbird tracks, which it never had until now
+    handler.remove_all_global_events();
+    let music = music.map(|music| {
+        let music = handler.play_source(music.into());
+        if let Err(err) = music.seek_time(position) {
+            warn!("Cannot seek {} in songbird: {}", uri, err);
+        }
+        music
+    });
+    info!(
+        "Mixing soundboard clips over the music in guild {}",
+        guild_id
+    );
+
+    Some(Mix {
+        voice_channel_id,
+        music,
+        position,
+        started_at: Instant::now(),
+        clips: 0,
+    })
+}
+
+/// Called when a clip of the mix ended. The last one gives the call back to lavalink, the music
+/// goes on from where songbird was
+async fn end_mixed_clip(ctx: &Context, guild_id: GuildId) {
+    let mixes = get_mixes(&ctx.data).await;
+    let mut mixes = mixes.lock().await;
+    let mix = match mixes.get_mut(&guild_id.0) {
+        Some(mix) => mix,
+        None => return,
+    };
+    mix.clips = mix.clips.saturating_sub(1);
+    if mix.clips > 0 {
+        return;
+    }
+    let mix = match mixes.remove(&guild_id.0) {
+        Some(mix) => mix,
+        None => return,
+    };
+
+    let (position, paused) = match &mix.music {
+        Some(music) => {
+            // The track is gone if the music ended before the clip, lavalink moves on then
+            let position = match music.get_info().await {
+                Ok(state) => state.position,
+                Err(_) => mix.position + mix.started_at.elapsed(),
+            };
+            let _ = music.stop();
+            (position, false)
+        }
+        None => (mix.position, true),
+    };
+    // The bot left in the meantime, nothing to give back
+    if get_songbird_manager(ctx).await.get(guild_id).is_none() {
+        return;
+    }
+    give_call_back_to_lavalink(ctx, guild_id, mix.voice_channel_id, position, paused).await;
+}
+
+/// Songbird stops sending audio and lavalink gets a session on the call again, then resumes
+/// at `position`
+async fn give_call_back_to_lavalink(
+    ctx: &Context,
+    guild_id: GuildId,
+    voice_channel_id: ChannelId,
+    position: Duration,
+    paused: bool,
+) {
+    let manager = get_songbird_manager(ctx).await;
+    if let Some(handle_lock) = manager.get(guild_id) {
+        let mut handler = handle_lock.lock().await;
+        handler.stop();
+        Driver::leave(&mut handler);
+    }
+
+    let (_, joined) = manager.join_gateway(guild_id, voice_channel_id).await;
+    let connection_info = match joined {
+        Ok(connection_info) => connection_info,
+        Err(err) => {
+            warn!(
+                "Cannot give the voice connection back to lavalink in guild {}: {}",
+                guild_id, err
+            );
+            return;
+        }
+    };
+    let nodes = ctx.data.read().await.get::<Lavalink>().cloned().unwrap();
+    nodes
+        .write()
+        .await
+        .set_connection_info(guild_id.0, connection_info.clone());
+
+    // The guild may have moved to another node while songbird played
+    let lavalink = match get_lavalink_client(ctx, guild_id).await {
+        Some(lavalink) => lavalink,
+        None => return,
+    };
+    if let Err(err) = lavalink
+        .create_session_with_songbird(&connection_info)
+        .await
+    {
+        warn!(
+            "Cannot create lavalink session for guild {}: {}",
+            guild_id, err
+        );
+        return;
+    }
+    if let Err(err) = lavalink.seek(guild_id.0, position).await {
+        warn!("Cannot seek lavalink in guild {}: {}", guild_id, err);
+    }
+    if !paused {
+        if let Err(err) = lavalink.set_pause(guild_id.0, false).await {
+            warn!("Cannot resume lavalink in guild {}: {}", guild_id, err);
+        }
+    }
+}
+
+/// Songbird event handler ending a clip of the mix
+struct ClipEnd {
+    ctx: Context,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl VoiceEventHandler for ClipEnd {
+    async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+        end_mixed_clip(&self.ctx, self.guild_id).await;
+        None
+    }
+}
+
+async fn get_mixes(data: &Arc<RwLock<TypeMap>>) -> Arc<Mutex<HashMap<u64, Mix>>> {
+    data.read().await.get::<SoundboardMixes>().cloned().unwrap()
+}
+
+/// Returns how long the user still has to wait before playing another clip
+async fn cooldown_remaining(ctx: &Context, guild_id: GuildId, user_id: UserId) -> Option<Duration> {
+    let cooldowns = get_cooldowns(ctx).await;
+    let mut cooldowns = cooldowns.lock().await;
+
+    main::cooldown_remaining(
+        &mut cooldowns,
+        (guild_id.0, user_id.0),
+        Duration::from_secs(env_or("SOUNDBOARD_COOLDOWN_SECS", DEFAULT_COOLDOWN_SECS)),
+        Instant::now(),
+    )
+}
+
+/// Started once the clip plays, a clip that could not play does not count
+async fn start_cooldown(ctx: &Context, guild_id: GuildId, user_id: UserId) {
+    let cooldowns = get_cooldowns(ctx).await;
+    cooldowns
+        .lock()
+        .await
+        .insert((guild_id.0, user_id.0), Instant::now());
+}
+
+async fn get_cooldowns(ctx: &Context) -> Arc<Mutex<HashMap<(u64, u64), Instant>>> {
+    ctx.data
+        .read()
+        .await
+        .get::<SoundboardCooldowns>()
+        .cloned()
+        .unwrap()
+}
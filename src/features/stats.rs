@@ -0,0 +1,269 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use mysql_async::Pool;
+use serenity::{
+    client::Context,
+    http::HttpError,
+    model::{
+        id::ChannelId,
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            },
+            InteractionResponseType,
+        },
+    },
+};
+use tracing::{info, warn};
+
+use crate::{
+    database::{
+        history::{get_most_skipped, get_top_requesters, get_top_tracks, get_total_played_ms},
+        settings::{get_due_stats_summaries, mark_stats_summary_sent, set_stats_channel},
+    },
+    events::interactions::{
+        application_command::{
+            get_option_by_name, get_subcommand, send_interaction_message_ephemeral,
+        },
+        helpers::member_can_manage_guild,
+    },
+    helpers::db_helper::get_pool_from_ctx,
+};
+
+const STATS_LIMIT: u64 = 5;
+// Keeps the whole message under the 2000 characters discord limit
+const MAX_TITLE_LENGTH: usize = 80;
+// How often we check if a weekly summary is due
+const SUMMARY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Discord error codes meaning the summary channel will never take a message
+const UNKNOWN_CHANNEL: isize = 10003;
+const MISSING_ACCESS: isize = 50001;
+const MISSING_PERMISSIONS: isize = 50013;
+
+static SUMMARY_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsWindow {
+    Week,
+    Month,
+    AllTime,
+}
+
+impl StatsWindow {
+    pub fn from_option(value: Option<&str>) -> Self {
+        match value {
+            Some("week") => StatsWindow::Week,
+            Some("month") => StatsWindow::Month,
+            _ => StatsWindow::AllTime,
+        }
+    }
+
+    /// Unix timestamp of the start of the window
+    pub fn since(&self) -> i64 {
+        let now = chrono::Utc::now();
+        match self {
+            StatsWindow::Week => (now - chrono::Duration::days(7)).timestamp(),
+            StatsWindow::Month => (now - chrono::Duration::days(30)).timestamp(),
+            StatsWindow::AllTime => 0,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatsWindow::Week => "the last week",
+            StatsWindow::Month => "the last month",
+            StatsWindow::AllTime => "all time",
+        }
+    }
+}
+
+/// `/stats show [window]` and `/stats summary_channel [channel]`
+pub async fn handle_stats(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+
+    let content = match get_subcommand(command) {
+        Some(("summary_channel", options)) => {
+            if !member_can_manage_guild(command.member.as_ref()) {
+                send_interaction_message_ephemeral(
+                    command,
+                    ctx,
+                    "You need the Manage Server permission",
+                )
+                .await;
+                return;
+            }
+            match get_option_by_name(options, "channel") {
+                Some(ApplicationCommandInteractionDataOptionValue::Channel(channel)) => {
+                    set_stats_channel(&pool, guild_id.0, Some(channel.id.0)).await;
+                    format!("Weekly summary will be posted in <#{}>", channel.id.0)
+                }
+                _ => {
+                    set_stats_channel(&pool, guild_id.0, None).await;
+                    "Weekly summary disabled".to_string()
+                }
+            }
+        }
+        Some((_, options)) => {
+            let window = match get_option_by_name(options, "window") {
+                Some(ApplicationCommandInteractionDataOptionValue::String(window)) => {
+                    StatsWindow::from_option(Some(window.as_str()))
+                }
+                _ => StatsWindow::from_option(None),
+            };
+            build_stats(&pool, guild_id.0, window).await
+        }
+        None => build_stats(&pool, guild_id.0, StatsWindow::AllTime).await,
+    };
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
+pub async fn build_stats(pool: &Pool, guild_id: u64, window: StatsWindow) -> String {
+    let since = window.since();
+
+    let top_tracks = get_top_tracks(pool, guild_id, since, STATS_LIMIT).await;
+    let top_requesters = get_top_requesters(pool, guild_id, since, STATS_LIMIT).await;
+    let total_played_ms = get_total_played_ms(pool, guild_id, since).await;
+    let most_skipped = get_most_skipped(pool, guild_id, since, STATS_LIMIT).await;
+
+    if top_tracks.is_empty() {
+        return format!("Nothing has been played in {}", window.label());
+    }
+
+    let mut output = format!("Music stats for {}\n", window.label());
+    writeln!(
+        &mut output,
+        "Total listening time: {:.1} hours",
+        total_played_ms as f64 / 3_600_000.0
+    )
+    .expect("cannot write to buffer");
+
+    writeln!(&mut output, "\nTop tracks").expect("cannot write to buffer");
+    for (i, (title, plays)) in top_tracks.iter().enumerate() {
+        writeln!(
+            &mut output,
+            "{}) {} - {} plays",
+            i + 1,
+            truncate(title),
+            plays
+        )
+        .expect("cannot write to buffer");
+    }
+
+    if !top_requesters.is_empty() {
+        writeln!(&mut output, "\nTop requesters").expect("cannot write to buffer");
+        for (i, (user_id, plays)) in top_requesters.iter().enumerate() {
+            writeln!(&mut output, "{}) <@{}> - {} tracks", i + 1, user_id, plays)
+                .expect("cannot write to buffer");
+        }
+    }
+
+    if !most_skipped.is_empty() {
+        writeln!(&mut output, "\nMost skipped").expect("cannot write to buffer");
+        for (i, (title, skips)) in most_skipped.iter().enumerate() {
+            writeln!(
+                &mut output,
+                "{}) {} - {} skips",
+                i + 1,
+                truncate(title),
+                skips
+            )
+            .expect("cannot write to buffer");
+        }
+    }
+
+    output
+}
+
+fn truncate(title: &str) -> String {
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        format!(
+            "{}...",
+            title.chars().take(MAX_TITLE_LENGTH).collect::<String>()
+        )
+    } else {
+        title.to_string()
+    }
+}
+
+/// Starts the task posting the weekly summaries. Safe to call on every `ready`
+pub fn start_weekly_summary(ctx: &Context) {
+    if SUMMARY_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SUMMARY_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let pool = get_pool_from_ctx(&ctx).await;
+            for (guild_id, channel_id) in get_due_stats_summaries(&pool).await {
+                let content = build_stats(&pool, guild_id, StatsWindow::Week).await;
+                match ChannelId(channel_id)
+                    .send_message(&ctx.http, |message| {
+                        message
+                            .content(format!("Weekly summary\n{}", content))
+                            .allowed_mentions(|mentions| mentions.empty_parse())
+                    })
+                    .await
+                {
+                    Ok(_) => {
+                        info!("Sent weekly summary for guild {}", guild_id);
+                        mark_stats_summary_sent(&pool, guild_id).await;
+                    }
+                    Err(err) if summary_channel_unusable(&err) => {
+                        // Retrying every hour would fail the same way forever
+                        warn!(
+                            "Cannot send weekly summary for guild {}, forgetting channel {}: {}",
+                            guild_id, channel_id, err
+                        );
+                        set_stats_channel(&pool, guild_id, None).await;
+                    }
+                    Err(err) => {
+                        warn!("Cannot send weekly summary for guild {}: {}", guild_id, err);
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// True when the channel was deleted or the bot lost the right to post in it
+fn summary_channel_unusable(err: &serenity::Error) -> bool {
+    match err {
+        serenity::Error::Http(http_err) => match &**http_err {
+            HttpError::UnsuccessfulRequest(response) => {
+                is_permanent_channel_error(response.error.code)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub(crate) fn is_permanent_channel_error(code: isize) -> bool {
+    matches!(code, UNKNOWN_CHANNEL | MISSING_ACCESS | MISSING_PERMISSIONS)
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use mysql_async::Pool;
+use serenity::{
+    async_trait, client::Context,
+    model::interactions::application_command::ApplicationCommandInteraction,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    database::media::{
+        delete_media_file, get_all_media_files, get_evictable_media, get_media_usage, MediaFile,
+    },
+    events::interactions::{
+        application_command::{send_interaction_message_basic, send_interaction_message_ephemeral},
+        helpers::member_can_manage_guild,
+    },
+    helpers::{
+        db_helper::get_pool_from_ctx,
+        media_store::{media_path, media_root, STAGING_DIR},
+    },
+};
+
+const DEFAULT_GUILD_QUOTA_MB: u64 = 2 * 1024;
+const DEFAULT_GLOBAL_QUOTA_MB: u64 = 20 * 1024;
+const GC_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+// Files this recent may belong to a download that is not recorded yet
+const ORPHAN_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+const EVICTION_BATCH: u64 = 20;
+
+static GC_STARTED: AtomicBool = AtomicBool::new(false);
+
+fn quota_from_env(name: &str, default_mb: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|quota| quota.parse().ok())
+        .unwrap_or(default_mb)
+        * 1024
+        * 1024
+}
+
+/// Comes from MEDIA_GUILD_QUOTA_MB
+fn guild_quota() -> u64 {
+    quota_from_env("MEDIA_GUILD_QUOTA_MB", DEFAULT_GUILD_QUOTA_MB)
+}
+
+/// Comes from MEDIA_GLOBAL_QUOTA_MB
+fn global_quota() -> u64 {
+    quota_from_env("MEDIA_GLOBAL_QUOTA_MB", DEFAULT_GLOBAL_QUOTA_MB)
+}
+
+/// Makes room for a download of `guild_id`, evicting unused files when a quota is reached.
+/// Fails when everything left is in a jam library
+pub async fn ensure_storage_quota(pool: &Pool, guild_id: u64) -> Result<(), String> {
+    if !evict_until(pool, Some(guild_id), guild_quota()).await {
+        return Err(
+            "This server is out of storage, remove tracks from the jam library to make room"
+                .to_string(),
+        );
+    }
+    if !evict_until(pool, None, global_quota()).await {
+        return Err("The bot is out of storage".to_string());
+    }
+
+    Ok(())
+}
+
+/// What eviction needs from the media store, the database and the files behind it
+#[async_trait]
+pub(crate) trait EvictionStore {
+    /// Bytes used by the guild, or by every guild
+    async fn usage(&self, guild_id: Option<u64>) -> u64;
+    /// The least recently used files nothing else needs
+    async fn evictable(&self, guild_id: Option<u64>, limit: u64) -> Vec<MediaFile>;
+    /// Removes the file and its rows. Returns false if the file is still counted
+    async fn evict(&self, media: &MediaFile) -> bool;
+}
+
+#[async_trait]
+impl EvictionStore for Pool {
+    async fn usage(&self, guild_id: Option<u64>) -> u64 {
+        get_media_usage(self, guild_id).await.1
+    }
+
+    async fn evictable(&self, guild_id: Option<u64>, limit: u64) -> Vec<MediaFile> {
+        get_evictable_media(self, guild_id, limit).await
+    }
+
+    async fn evict(&self, media: &MediaFile) -> bool {
+        info!(
+            "Evicting {} ({}, {} bytes)",
+            media.title, media.checksum, media.size_bytes
+        );
+        let path = media_path(media);
+        match tokio::fs::remove_file(&path).await {
+            Ok(_) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                warn!("Cannot remove {}: {}", path.display(), err);
+                return false;
+            }
+        }
+        delete_media_file(self, media.id).await
+    }
+}
+
+/// Evicts the least recently used files until the usage is under `quota`. Returns false if there
+/// was nothing left to evict, or if none of the files could be removed
+pub(crate) async fn evict_until<S: EvictionStore + Sync>(
+    store: &S,
+    guild_id: Option<u64>,
+    quota: u64,
+) -> bool {
+    loop {
+        let used = store.usage(guild_id).await;
+        if used < quota {
+            return true;
+        }
+
+        let candidates = store.evictable(guild_id, EVICTION_BATCH).await;
+        if candidates.is_empty() {
+            return false;
+        }
+
+        // The same files come back on the next round if they cannot be removed
+        let mut evicted = 0;
+        let mut freed = 0;
+        for media in candidates {
+            if !store.evict(&media).await {
+                continue;
+            }
+            evicted += 1;
+            freed += media.size_bytes;
+            if used.saturating_sub(freed) < quota {
+                break;
+            }
+        }
+        if evicted == 0 {
+            warn!("No file of the eviction batch could be removed");
+            return false;
+        }
+    }
+}
+
+/// `/storage`, how much of the quotas is used
+pub async fn handle_storage(ctx: &Context, command: &ApplicationCommandInteraction) {
+    if !member_can_manage_guild(command.member.as_ref()) {
+        send_interaction_message_ephemeral(command, ctx, "You need the Manage Server permission")
+            .await;
+        return;
+    }
+
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let pool = get_pool_from_ctx(ctx).await;
+    let (guild_files, guild_used) = get_media_usage(&pool, Some(guild_id.0)).await;
+    let (global_files, global_used) = get_media_usage(&pool, None).await;
+
+    let content = format!(
+        "This server: {} of {} ({} files)\nAll servers: {} of {} ({} files)",
+        format_size(guild_used),
+        format_size(guild_quota()),
+        guild_files,
+        format_size(global_used),
+        format_size(global_quota()),
+        global_files
+    );
+    send_interaction_message_basic(command, ctx, &content).await;
+}
+
+fn format_size(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 * 1024 {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    } else {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Starts the task cleaning up the media store. Safe to call on every `ready`
+pub fn start_storage_gc(ctx: &Context) {
+    if GC_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(GC_INTERVAL);
+        let mut missing_before = HashSet::new();
+        loop {
+            interval.tick().await;
+
+            let pool = get_pool_from_ctx(&ctx).await;
+            collect_garbage(&pool, &mut missing_before).await;
+        }
+    });
+}
+
+/// Picks the rows to forget among the `missing` ones out of `total`. Only rows already missing on
+/// the previous run go, and nothing goes when most files are missing: the media store is more
+/// likely unmounted than emptied, and forgetting a row also drops it from the jam libraries
+pub(crate) fn rows_to_forget(
+    total: usize,
+    missing: &HashSet<u64>,
+    missing_before: &HashSet<u64>,
+) -> Option<Vec<u64>> {
+    if missing.len() * 2 > total {
+        return None;
+    }
+    let mut ids = missing
+        .intersection(missing_before)
+        .copied()
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    Some(ids)
+}
+
+/// Drops rows whose file is gone and files no row knows about, then enforces the global quota.
+/// `missing_before` holds the rows whose file was missing on the previous run
+async fn collect_garbage(pool: &Pool, missing_before: &mut HashSet<u64>) {
+    let root = media_root();
+    let mut dirs = match tokio::fs::read_dir(&root).await {
+        Ok(dirs) => dirs,
+        Err(err) => {
+            warn!(
+                "Cannot read {}, skipping garbage collection: {}",
+                root.display(),
+                err
+            );
+            return;
+        }
+    };
+
+    let media_files = get_all_media_files(pool).await;
+    let mut known = HashSet::new();
+    let mut missing = HashSet::new();
+    for media in media_files.iter() {
+        let path = media_path(media);
+        if tokio::fs::metadata(&path).await.is_ok() {
+            known.insert(path);
+        } else {
+            warn!("{} is missing ({})", path.display(), media.title);
+            missing.insert(media.id);
+        }
+    }
+
+    match rows_to_forget(media_files.len(), &missing, missing_before) {
+        Some(ids) => {
+            for media in media_files.iter().filter(|media| ids.contains(&media.id)) {
+                warn!("Forgetting {}, its file is still missing", media.title);
+                delete_media_file(pool, media.id).await;
+            }
+            *missing_before = missing;
+        }
+        None => {
+            error!(
+                "{} of {} media files are missing, is {} mounted? Skipping garbage collection",
+                missing.len(),
+                media_files.len(),
+                root.display()
+            );
+            return;
+        }
+    }
+
+    let mut removed = 0;
+    while let Ok(Some(dir)) = dirs.next_entry().await {
+        let name = dir.file_name();
+        let name = name.to_string_lossy();
+        // Checksum prefixes and the staging directory. Files in the root are legacy jam tracks
+        let is_store_dir =
+            name == STAGING_DIR || (name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit()));
+        let is_dir = dir
+            .file_type()
+            .await
+            .map_or(false, |file_type| file_type.is_dir());
+        if !is_store_dir || !is_dir {
+            continue;
+        }
+
+        let mut files = match tokio::fs::read_dir(dir.path()).await {
+            Ok(files) => files,
+            Err(err) => {
+                warn!("Cannot read {}: {}", dir.path().display(), err);
+                continue;
+            }
+        };
+        while let Ok(Some(file)) = files.next_entry().await {
+            let path = file.path();
+            if !known.contains(&path) && is_stale(&path).await {
+                match tokio::fs::remove_file(&path).await {
+                    Ok(_) => removed += 1,
+                    Err(err) => warn!("Cannot remove {}: {}", path.display(), err),
+                }
+            }
+        }
+    }
+    info!(
+        "Storage garbage collection removed {} orphan files",
+        removed
+    );
+
+    evict_until(pool, None, global_quota()).await;
+}
+
+async fn is_stale(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map_or(false, |age| age >= ORPHAN_MIN_AGE)
+}
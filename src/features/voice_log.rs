@@ -0,0 +1,343 @@
+use std::fmt::Write;
+
+use serenity::{
+    client::Context,
+    model::{
+        guild::Guild,
+        id::{ChannelId, GuildId},
+        interactions::application_command::{
+            ApplicationCommandInteraction, ApplicationCommandInteractionDataOption,
+            ApplicationCommandInteractionDataOptionValue,
+        },
+        prelude::VoiceState,
+    },
+};
+use tracing::warn;
+
+use crate::{
+    database::{
+        settings::{get_voice_log_channel, set_voice_log_channel},
+        voice::{
+            add_voice_state, close_voice_session, get_open_voice_session,
+            get_open_voice_session_users, get_voice_events, get_voice_sessions, open_voice_session,
+            VoiceEvent,
+        },
+    },
+    events::interactions::{
+        application_command::{
+            get_option_by_name, get_subcommand, send_interaction_message_ephemeral,
+        },
+        helpers::member_can_manage_guild,
+    },
+    features::voice_stats::format_duration,
+    helpers::db_helper::get_pool_from_ctx,
+};
+
+const VOICE_LOG_LIMIT: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VoiceChange {
+    Join(ChannelId),
+    Leave(ChannelId),
+    Move {
+        from: ChannelId,
+        to: ChannelId,
+    },
+    /// A flag of the voice state, by the name it is stored with
+    Toggle(&'static str, bool),
+}
+
+impl VoiceChange {
+    fn kind(&self) -> &'static str {
+        match self {
+            VoiceChange::Join(_) => "join",
+            VoiceChange::Leave(_) => "leave",
+            VoiceChange::Move { .. } => "move",
+            VoiceChange::Toggle(kind, _) => kind,
+        }
+    }
+}
+
+/// Compares two voice states of a member. Flags only count while they stay in a channel, a join
+/// brings whatever state they had before. Without the old state, which the cache lacks after a
+/// restart, `open_session` is the channel of the session the member still has open: staying there
+/// is no change, since the flags that changed are unknown
+pub(crate) fn voice_changes(
+    old_state: Option<&VoiceState>,
+    open_session: Option<ChannelId>,
+    new_state: &VoiceState,
+) -> Vec<VoiceChange> {
+    let old_state = match old_state {
+        Some(old_state) => old_state,
+        None => {
+            return match (open_session, new_state.channel_id) {
+                (Some(from), Some(to)) if from == to => Vec::new(),
+                (Some(from), Some(to)) => vec![VoiceChange::Move { from, to }],
+                (Some(from), None) => vec![VoiceChange::Leave(from)],
+                (None, to) => to.map(VoiceChange::Join).into_iter().collect(),
+            }
+        }
+    };
+    let old_channel = match old_state.channel_id {
+        Some(old_channel) => old_channel,
+        None => {
+            return new_state
+                .channel_id
+                .map(VoiceChange::Join)
+                .into_iter()
+                .collect()
+        }
+    };
+    let new_channel = match new_state.channel_id {
+        Some(new_channel) => new_channel,
+        None => return vec![VoiceChange::Leave(old_channel)],
+    };
+    if new_channel != old_channel {
+        return vec![VoiceChange::Move {
+            from: old_channel,
+            to: new_channel,
+        }];
+    }
+
+    let toggles = [
+        ("mute", old_state.mute, new_state.mute),
+        ("deafen", old_state.deaf, new_state.deaf),
+        ("self_mute", old_state.self_mute, new_state.self_mute),
+        ("self_deafen", old_state.self_deaf, new_state.self_deaf),
+        (
+            "stream",
+            old_state.self_stream.unwrap_or(false),
+            new_state.self_stream.unwrap_or(false),
+        ),
+        ("video", old_state.self_video, new_state.self_video),
+    ];
+    toggles
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(kind, _, new)| VoiceChange::Toggle(*kind, *new))
+        .collect()
+}
+
+/// Called from `voice_state_update`. Records every change, keeps the sessions and posts moves and
+/// disconnects to the log channel of the guild
+pub async fn on_voice_state_update(
+    ctx: &Context,
+    guild_id: GuildId,
+    old_state: Option<&VoiceState>,
+    new_state: &VoiceState,
+) {
+    if new_state.user_id == ctx.cache.current_user_id().await {
+        return;
+    }
+    let pool = get_pool_from_ctx(ctx).await;
+    let user_id = new_state.user_id;
+    let open_session = match old_state {
+        Some(_) => None,
+        None => get_open_voice_session(&pool, guild_id.0, user_id.0)
+            .await
+            .map(ChannelId),
+    };
+    let changes = voice_changes(old_state, open_session, new_state);
+    if changes.is_empty() {
+        return;
+    }
+
+    for change in changes.iter() {
+        let (channel_id, old_channel_id, enabled) = match *change {
+            VoiceChange::Join(channel_id) => (Some(channel_id.0), None, None),
+            VoiceChange::Leave(channel_id) => (None, Some(channel_id.0), None),
+            VoiceChange::Move { from, to } => (Some(to.0), Some(from.0), None),
+            VoiceChange::Toggle(_, enabled) => {
+                (new_state.channel_id.map(|id| id.0), None, Some(enabled))
+            }
+        };
+        add_voice_state(
+            &pool,
+            guild_id.0,
+            user_id.0,
+            change.kind(),
+            channel_id,
+            old_channel_id,
+            enabled,
+        )
+        .await;
+
+        match *change {
+            VoiceChange::Join(channel_id) | VoiceChange::Move { to: channel_id, .. } => {
+                open_voice_session(&pool, guild_id.0, user_id.0, channel_id.0).await
+            }
+            VoiceChange::Leave(_) => close_voice_session(&pool, guild_id.0, user_id.0).await,
+            VoiceChange::Toggle(..) => {}
+        }
+    }
+
+    let announcement = changes.iter().find_map(|change| match change {
+        VoiceChange::Leave(channel_id) => Some(format!(
+            "📤 <@{}> disconnected from <#{}>",
+            user_id, channel_id
+        )),
+        VoiceChange::Move { from, to } => Some(format!(
+            "🔀 <@{}> moved from <#{}> to <#{}>",
+            user_id, from, to
+        )),
+        _ => None,
+    });
+    if let Some(announcement) = announcement {
+        if let Some(log_channel) = get_voice_log_channel(&pool, guild_id.0).await {
+            if let Err(err) = ChannelId(log_channel)
+                .send_message(&ctx.http, |message| {
+                    message
+                        .content(announcement)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+                .await
+            {
+                warn!(
+                    "Cannot post to the voice log of guild {}: {}",
+                    guild_id, err
+                );
+            }
+        }
+    }
+}
+
+/// Called from `guild_create`. Ends the sessions of the members who left voice while the bot was
+/// away, their leave was never seen
+pub async fn close_stale_sessions(ctx: &Context, guild: &Guild) {
+    let pool = get_pool_from_ctx(ctx).await;
+    for user_id in get_open_voice_session_users(&pool, guild.id.0).await {
+        let in_voice = guild
+            .voice_states
+            .values()
+            .any(|state| state.user_id.0 == user_id && state.channel_id.is_some());
+        if !in_voice {
+            close_voice_session(&pool, guild.id.0, user_id).await;
+        }
+    }
+}
+
+/// `/voicelog show <user>` and for admins `/voicelog channel [channel]`
+pub async fn handle_voice_log(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let content = match get_subcommand(command) {
+        Some(("show", options)) => voice_log_show(ctx, command, options).await,
+        Some(("channel", options)) => voice_log_channel(ctx, command, options).await,
+        _ => "Unknown subcommand".to_string(),
+    };
+
+    send_interaction_message_ephemeral(command, ctx, content.as_str()).await;
+}
+
+async fn voice_log_show(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> String {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let user_id = match get_option_by_name(options, "user") {
+        Some(ApplicationCommandInteractionDataOptionValue::User(user, _)) => user.id,
+        _ => command.user.id,
+    };
+    if user_id != command.user.id && !member_can_manage_guild(command.member.as_ref()) {
+        return "You need the Manage Server permission to see the voice log of someone else"
+            .to_string();
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let sessions = get_voice_sessions(&pool, guild_id.0, user_id.0, VOICE_LOG_LIMIT).await;
+    let events = get_voice_events(&pool, guild_id.0, user_id.0, VOICE_LOG_LIMIT).await;
+    if events.is_empty() {
+        return format!("<@{}> has no voice activity recorded", user_id);
+    }
+
+    let mut output = format!("Voice log of <@{}>\n", user_id);
+    if !sessions.is_empty() {
+        writeln!(&mut output, "\nSessions").expect("cannot write to buffer");
+        for session in sessions.iter() {
+            match session.left_at {
+                Some(left_at) => writeln!(
+                    &mut output,
+                    "<#{}> <t:{}:f> for {}",
+                    session.channel_id,
+                    session.joined_at,
+                    format_duration((left_at - session.joined_at).max(0) as u64)
+                ),
+                None => writeln!(
+                    &mut output,
+                    "<#{}> since <t:{}:R>",
+                    session.channel_id, session.joined_at
+                ),
+            }
+            .expect("cannot write to buffer");
+        }
+    }
+
+    writeln!(&mut output, "\nLatest changes").expect("cannot write to buffer");
+    for event in events.iter() {
+        writeln!(
+            &mut output,
+            "<t:{}:f> {}",
+            event.created_at,
+            describe_event(event)
+        )
+        .expect("cannot write to buffer");
+    }
+
+    output
+}
+
+fn describe_event(event: &VoiceEvent) -> String {
+    let channel = |channel_id: Option<u64>| match channel_id {
+        Some(channel_id) => format!("<#{}>", channel_id),
+        None => "a channel".to_string(),
+    };
+    let enabled = event.enabled.unwrap_or(false);
+
+    match event.kind.as_str() {
+        "join" => format!("joined {}", channel(event.channel_id)),
+        "leave" => format!("left {}", channel(event.old_channel_id)),
+        "move" => format!(
+            "moved from {} to {}",
+            channel(event.old_channel_id),
+            channel(event.channel_id)
+        ),
+        "mute" if enabled => "was server muted".to_string(),
+        "mute" => "was server unmuted".to_string(),
+        "deafen" if enabled => "was server deafened".to_string(),
+        "deafen" => "was server undeafened".to_string(),
+        "self_mute" if enabled => "muted".to_string(),
+        "self_mute" => "unmuted".to_string(),
+        "self_deafen" if enabled => "deafened".to_string(),
+        "self_deafen" => "undeafened".to_string(),
+        "stream" if enabled => "started streaming".to_string(),
+        "stream" => "stopped streaming".to_string(),
+        "video" if enabled => "turned their camera on".to_string(),
+        "video" => "turned their camera off".to_string(),
+        kind => kind.to_string(),
+    }
+}
+
+async fn voice_log_channel(
+    ctx: &Context,
+    command: &ApplicationCommandInteraction,
+    options: &[ApplicationCommandInteractionDataOption],
+) -> String {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    if !member_can_manage_guild(command.member.as_ref()) {
+        return "You need the Manage Server permission".to_string();
+    }
+
+    let pool = get_pool_from_ctx(ctx).await;
+    match get_option_by_name(options, "channel") {
+        Some(ApplicationCommandInteractionDataOptionValue::Channel(channel)) => {
+            set_voice_log_channel(&pool, guild_id.0, Some(channel.id.0)).await;
+            format!(
+                "Voice moves and disconnects will be posted in <#{}>",
+                channel.id.0
+            )
+        }
+        _ => {
+            set_voice_log_channel(&pool, guild_id.0, None).await;
+            "Voice log channel disabled".to_string()
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use serenity::{
+    async_trait,
+    client::Context,
+    model::{
+        id::{ChannelId, GuildId},
+        interactions::{
+            application_command::{
+                ApplicationCommandInteraction, ApplicationCommandInteractionDataOptionValue,
+            },
+            InteractionResponseType,
+        },
+        prelude::VoiceState,
+    },
+    prelude::{Mutex, TypeMapKey},
+};
+use songbird::{
+    model::payload::Speaking, Call, CoreEvent, Event, EventContext,
+    EventHandler as VoiceEventHandler,
+};
+
+use crate::{
+    database::voice::{add_speaking_session, get_time_in_channel, get_top_talkers},
+    events::interactions::application_command::get_option_by_name,
+    features::stats::StatsWindow,
+    helpers::db_helper::get_pool_from_ctx,
+};
+
+const VOICE_STATS_LIMIT: u64 = 5;
+// Decoded voice is 48kHz stereo
+const SAMPLES_PER_MS: u64 = 96;
+// Discord sends 20ms of audio per packet
+const PACKET_MS: u64 = 20;
+// SSRCs of members who never said who they are, this many at most are waited for
+const MAX_UNMAPPED_SSRCS: usize = 64;
+
+/// Speaking time of the members in the channel songbird listens to, per guild
+pub struct SpeakingTrackers;
+impl TypeMapKey for SpeakingTrackers {
+    type Value = Arc<Mutex<HashMap<u64, Arc<SpeakingTracker>>>>;
+}
+
+pub struct SpeakingTracker {
+    channel_id: ChannelId,
+    /// Cleared once the tracker is stopped so its handlers unregister
+    active: AtomicBool,
+    state: Mutex<TrackerState>,
+}
+
+#[derive(Default)]
+pub(crate) struct TrackerState {
+    /// Discord says which user an SSRC belongs to when they start speaking
+    users: HashMap<u32, u64>,
+    /// Audio that arrived before its SSRC was mapped
+    unmapped_ms: HashMap<u32, u64>,
+    sessions: HashMap<u64, VoiceSession>,
+}
+
+pub(crate) struct VoiceSession {
+    /// Unix timestamp
+    joined_at: i64,
+    speaking_ms: u64,
+}
+
+impl TrackerState {
+    fn session(&mut self, user_id: u64) -> &mut VoiceSession {
+        self.sessions
+            .entry(user_id)
+            .or_insert_with(|| VoiceSession {
+                joined_at: chrono::Utc::now().timestamp(),
+                speaking_ms: 0,
+            })
+    }
+
+    /// The audio received from `ssrc` before it was mapped goes to the user
+    pub(crate) fn map_ssrc(&mut self, ssrc: u32, user_id: u64) {
+        self.users.insert(ssrc, user_id);
+        let unmapped = self.unmapped_ms.remove(&ssrc).unwrap_or(0);
+        self.session(user_id).speaking_ms += unmapped;
+    }
+
+    /// Counts a packet of `samples` decoded samples. Audio past one packet stands in for packets
+    /// that never came, the member was silent then
+    pub(crate) fn add_audio(&mut self, ssrc: u32, samples: usize) {
+        let ms = (samples as u64 / SAMPLES_PER_MS).min(PACKET_MS);
+        match self.users.get(&ssrc).copied() {
+            Some(user_id) => self.session(user_id).speaking_ms += ms,
+            None => {
+                if !self.unmapped_ms.contains_key(&ssrc)
+                    && self.unmapped_ms.len() >= MAX_UNMAPPED_SSRCS
+                {
+                    return;
+                }
+                *self.unmapped_ms.entry(ssrc).or_insert(0) += ms;
+            }
+        }
+    }
+
+    /// Ends the session of a member who left. The SSRCs waiting for a user are dropped once the
+    /// channel is empty, whoever they belonged to is gone
+    pub(crate) fn leave(&mut self, user_id: u64) -> Option<VoiceSession> {
+        self.users.retain(|_, user| *user != user_id);
+        let session = self.sessions.remove(&user_id);
+        if self.sessions.is_empty() {
+            self.unmapped_ms.clear();
+        }
+        session
+    }
+
+    pub(crate) fn speaking_ms(&self, user_id: u64) -> Option<u64> {
+        self.sessions
+            .get(&user_id)
+            .map(|session| session.speaking_ms)
+    }
+}
+
+/// Songbird event handler adding up how long every member spoke
+struct SpeakingReceiver {
+    tracker: Arc<SpeakingTracker>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for SpeakingReceiver {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if !self.tracker.active.load(Ordering::Relaxed) {
+            return Some(Event::Cancel);
+        }
+        match ctx {
+            EventContext::SpeakingStateUpdate(Speaking { ssrc, user_id, .. }) => {
+                if let Some(user_id) = user_id {
+                    let mut state = self.tracker.state.lock().await;
+                    state.map_ssrc(*ssrc, user_id.0);
+                }
+            }
+            EventContext::VoicePacket(data) => {
+                if let Some(audio) = data.audio {
+                    let mut state = self.tracker.state.lock().await;
+                    state.add_audio(data.packet.ssrc, audio.len());
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+async fn get_speaking_trackers(ctx: &Context) -> Arc<Mutex<HashMap<u64, Arc<SpeakingTracker>>>> {
+    ctx.data
+        .read()
+        .await
+        .get::<SpeakingTrackers>()
+        .cloned()
+        .unwrap()
+}
+
+/// Starts counting speaking time in a call songbird listens to. The members already in the channel
+/// start their session now
+pub async fn start_speaking_tracker(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    handle_lock: &Arc<Mutex<Call>>,
+) {
+    let trackers = get_speaking_trackers(ctx).await;
+    let mut trackers = trackers.lock().await;
+    if trackers.contains_key(&guild_id.0) {
+        return;
+    }
+
+    let mut state = TrackerState::default();
+    let bot_id = ctx.cache.current_user_id().await;
+    if let Some(guild) = ctx.cache.guild(guild_id).await {
+        for (user_id, voice_state) in guild.voice_states.iter() {
+            if voice_state.channel_id == Some(channel_id) && *user_id != bot_id {
+                state.session(user_id.0);
+            }
+        }
+    }
+    let tracker = Arc::new(SpeakingTracker {
+        channel_id,
+        active: AtomicBool::new(true),
+        state: Mutex::new(state),
+    });
+    trackers.insert(guild_id.0, tracker.clone());
+
+    let mut handler = handle_lock.lock().await;
+    for event in [CoreEvent::SpeakingStateUpdate, CoreEvent::VoicePacket] {
+        handler.add_global_event(
+            Event::Core(event),
+            SpeakingReceiver {
+                tracker: tracker.clone(),
+            },
+        );
+    }
+}
+
+/// Saves the sessions of everyone still in the channel. Called when the bot stops listening
+pub async fn stop_speaking_tracker(ctx: &Context, guild_id: GuildId) {
+    let tracker = match get_speaking_trackers(ctx)
+        .await
+        .lock()
+        .await
+        .remove(&guild_id.0)
+    {
+        Some(tracker) => tracker,
+        None => return,
+    };
+    tracker.active.store(false, Ordering::Relaxed);
+
+    let sessions = std::mem::take(&mut tracker.state.lock().await.sessions);
+    for (user_id, session) in sessions {
+        save_session(ctx, guild_id, tracker.channel_id, user_id, session).await;
+    }
+}
+
+/// Called from `voice_state_update`. Opens and closes the sessions of the members joining and
+/// leaving the channel songbird listens to
+pub async fn on_voice_state_update(ctx: &Context, guild_id: GuildId, new_state: &VoiceState) {
+    let tracker = match get_speaking_trackers(ctx)
+        .await
+        .lock()
+        .await
+        .get(&guild_id.0)
+    {
+        Some(tracker) => tracker.clone(),
+        None => return,
+    };
+    if new_state.user_id == ctx.cache.current_user_id().await {
+        return;
+    }
+
+    let in_channel = new_state.channel_id == Some(tracker.channel_id);
+    let left = {
+        let mut state = tracker.state.lock().await;
+        if in_channel {
+            state.session(new_state.user_id.0);
+            None
+        } else {
+            state.leave(new_state.user_id.0)
+        }
+    };
+    if let Some(session) = left {
+        save_session(
+            ctx,
+            guild_id,
+            tracker.channel_id,
+            new_state.user_id.0,
+            session,
+        )
+        .await;
+    }
+}
+
+async fn save_session(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: u64,
+    session: VoiceSession,
+) {
+    let pool = get_pool_from_ctx(ctx).await;
+    add_speaking_session(
+        &pool,
+        guild_id.0,
+        channel_id.0,
+        user_id,
+        session.joined_at,
+        chrono::Utc::now().timestamp(),
+        session.speaking_ms,
+    )
+    .await;
+}
+
+/// `/voicestats [window]`
+pub async fn handle_voice_stats(ctx: &Context, command: &ApplicationCommandInteraction) {
+    let guild_id = command.guild_id.expect("cannot get guild id from command");
+    let window = match get_option_by_name(&command.data.options, "window") {
+        Some(ApplicationCommandInteractionDataOptionValue::String(window)) => {
+            StatsWindow::from_option(Some(window.as_str()))
+        }
+        _ => StatsWindow::from_option(None),
+    };
+
+    let pool = get_pool_from_ctx(ctx).await;
+    let since = window.since();
+    let top_talkers = get_top_talkers(&pool, guild_id.0, since, VOICE_STATS_LIMIT).await;
+    let time_in_channel = get_time_in_channel(&pool, guild_id.0, since, VOICE_STATS_LIMIT).await;
+
+    let content = if time_in_channel.is_empty() {
+        format!(
+            "No voice activity was tracked in {}. It is only counted while I record or listen for \
+             clips",
+            window.label()
+        )
+    } else {
+        let mut output = format!("Voice stats for {}\n", window.label());
+        if !top_talkers.is_empty() {
+            writeln!(&mut output, "\nTop talkers").expect("cannot write to buffer");
+            for (i, (user_id, spoken_ms)) in top_talkers.iter().enumerate() {
+                writeln!(
+                    &mut output,
+                    "{}) <@{}> - {}",
+                    i + 1,
+                    user_id,
+                    format_duration(spoken_ms / 1000)
+                )
+                .expect("cannot write to buffer");
+            }
+        }
+        writeln!(&mut output, "\nTime in channel").expect("cannot write to buffer");
+        for (i, (user_id, present_secs)) in time_in_channel.iter().enumerate() {
+            writeln!(
+                &mut output,
+                "{}) <@{}> - {}",
+                i + 1,
+                user_id,
+                format_duration(*present_secs)
+            )
+            .expect("cannot write to buffer");
+        }
+        output
+    };
+
+    match command
+        .create_interaction_response(ctx, |f| {
+            f.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|message| {
+                    message
+                        .content(content)
+                        .allowed_mentions(|mentions| mentions.empty_parse())
+                })
+        })
+        .await
+    {
+        Ok(_) => {}
+        Err(err) => {
+            println!("Cannot respond to application command {}", err)
+        }
+    };
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    if seconds >= 3600 {
+        format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    }
+}
@@ -1,107 +1,247 @@
-use std::{io::Write, process::Command};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
-use serenity::{client::Context, futures::StreamExt};
+use serenity::futures::StreamExt;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::warn;
 
-use crate::{check_msg, database, HasBossMusic};
+// ffprobe and ffmpeg only ever work on short clips
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(120);
 
-pub async fn download_attachment(
-    ctx: &Context,
-    url: &str,
-    boss_music_file_path: &str,
-    file_name: &str,
-    msg: &serenity::model::channel::Message,
-    user_id: &u64,
-) {
-    // the attachment data
-    let response = reqwest::get(url).await.expect("attchment request failed");
-    // Create the file that we will store the attachment in (no extenstion). I will be later deleted
-    let mut file = std::fs::File::create(format!("{}{}", boss_music_file_path, file_name))
-        .expect("cannot create file");
-    let mut stream = response.bytes_stream();
+/// Downloads an attachment to `path`. Stops and removes the file once it grows past `max_bytes`
+pub async fn download_attachment(url: &str, path: &Path, max_bytes: u64) -> Result<(), String> {
+    let result = write_attachment(url, path, max_bytes).await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(path).await;
+    }
+
+    result
+}
+
+async fn write_attachment(url: &str, path: &Path, max_bytes: u64) -> Result<(), String> {
+    let response = reqwest::get(url)
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| format!("cannot download the attachment: {}", err))?;
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|err| format!("cannot create {}: {}", path.display(), err))?;
 
-    while let Some(item) = stream.next().await {
-        let chunk = match item {
-            Ok(data) => data,
-            Err(_) => {
-                println!("Error while downloading file");
-                return;
-            }
-        };
-        match file.write(&chunk) {
-            Ok(_) => {}
-            Err(_) => {
-                println!("Error while writing to file");
-                return;
-            }
+    let mut written = 0;
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|err| format!("cannot download the attachment: {}", err))?;
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err("the file is too big".to_string());
         }
+        file.write_all(&chunk)
+            .await
+            .map_err(|err| format!("cannot write {}: {}", path.display(), err))?;
     }
-    // // ??
-    // let mut content = Cursor::new(response.bytes().await.expect("??"));
-    // // copy memory content to the file
-    // std::io::copy(&mut content, &mut file).expect("cannot copy file");
-
-    let output = Command::new("ffprobe")
-        .arg(format!("{}{}", boss_music_file_path, file_name))
-        .args(["-show_streams", "-show_format"])
-        .output()
-        .expect("failed to laucnh ffprobe");
-    let s = String::from_utf8_lossy(&output.stdout);
-    for line in s.lines() {
-        if line.starts_with("duration=") {
-            let after: Vec<&str> = line.split('=').collect();
-            let float: f32 = after[1].parse().unwrap();
-            println!("duration {}", float);
-        } else if line.starts_with("probe_score=") {
-            let after: Vec<&str> = line.split('=').collect();
-            let int: i32 = after[1].parse().unwrap();
-            println!("score {}", int);
-        } else if line.starts_with("codec_type=") {
-            let after: Vec<&str> = line.split('=').collect();
-            let codec_type = after[1];
-            println!("codec {}", codec_type);
-        }
+
+    Ok(())
+}
+
+/// Returns the duration in seconds of a file with an audio stream
+pub async fn probe_audio_duration(path: &Path) -> Result<f64, String> {
+    let output = tokio::time::timeout(
+        FFMPEG_TIMEOUT,
+        Command::new("ffprobe")
+            .args([
+                "-v",
+                "error",
+                "-select_streams",
+                "a:0",
+                "-show_entries",
+                "stream=codec_type:format=duration",
+                "-of",
+                "default=noprint_wrappers=1",
+            ])
+            .arg(path)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| "ffprobe timed out".to_string())?
+    .map_err(|err| format!("cannot start ffprobe: {}", err))?;
+
+    if !output.status.success() {
+        return Err("the file is not a media file".to_string());
     }
 
-    let command1 = match Command::new("ffmpeg")
-        // .arg(format!("-ss {}", 0))
-        .arg("-ss")
-        .arg("0")
-        .arg("-t")
-        .arg("0")
-        .arg("-i")
-        .arg(format!("{}{}", boss_music_file_path, file_name))
-        .arg("-y")
-        .args(["-c:a", "libopus", "-b:a", "96k"])
-        .arg(format!("{}{}.ogg", boss_music_file_path, file_name))
-        .output()
-    {
-        Ok(result) => {
-            check_msg(msg.reply(ctx, "Ok piss").await);
-            result
-        }
-        Err(err) => {
-            println!("Cannot convert: {}", err);
-            check_msg(msg.reply(ctx, "Cannot convert file").await);
-            return;
-        }
-    };
-    // remove temporary file
-    match std::fs::remove_file(format!("{}{}", boss_music_file_path, file_name)) {
-        Ok(_) => {}
-        Err(err) => {
-            println!("Error when remove file {}", err)
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut has_audio = false;
+    let mut duration = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("codec_type", "audio")) => has_audio = true,
+            Some(("duration", value)) => duration = value.parse::<f64>().ok(),
+            _ => {}
         }
-    };
+    }
 
-    {
-        let mut data = ctx.data.write().await;
-        let has_boss_music = data.get_mut::<HasBossMusic>().unwrap();
-        has_boss_music.insert(*user_id, Some(format!("{}.ogg", file_name)));
+    if !has_audio {
+        return Err("the file has no audio".to_string());
     }
+    duration.ok_or_else(|| "cannot read the duration of the file".to_string())
+}
+
+/// Cuts `duration` seconds from `start` and encodes them to Opus with a normalized loudness
+pub async fn convert_to_opus_clip(
+    input: &Path,
+    output: &Path,
+    start: f64,
+    duration: f64,
+) -> Result<(), String> {
+    let result = tokio::time::timeout(
+        FFMPEG_TIMEOUT,
+        Command::new("ffmpeg")
+            .args(["-v", "error", "-y", "-ss"])
+            .arg(format!("{:.3}", start))
+            .arg("-t")
+            .arg(format!("{:.3}", duration))
+            .arg("-i")
+            .arg(input)
+            .args([
+                "-vn",
+                "-af",
+                "loudnorm=I=-16:TP=-1.5:LRA=11",
+                "-c:a",
+                "libopus",
+                "-b:a",
+                "96k",
+            ])
+            .arg(output)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| "ffmpeg timed out".to_string())?
+    .map_err(|err| format!("cannot start ffmpeg: {}", err))?;
 
-    database::voice::add_user_boss_music(ctx, user_id, file_name).await;
+    if !result.status.success() {
+        let _ = tokio::fs::remove_file(output).await;
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            stderr.trim()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Cuts a clip of at most `duration` seconds from `start` out of `input` and stores it in `dir` as
+/// `file_name`. Returns the length of the clip
+pub async fn store_opus_clip(
+    input: &Path,
+    dir: &Path,
+    file_name: &str,
+    start: f64,
+    duration: f64,
+) -> Result<f64, String> {
+    let length = probe_audio_duration(input).await?;
+    if start >= length {
+        return Err(format!("the file is only {:.1} seconds long", length));
+    }
+    let duration = duration.min(length - start);
+
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(|err| format!("cannot create {}: {}", dir.display(), err))?;
+    if let Err(err) = convert_to_opus_clip(input, &dir.join(file_name), start, duration).await {
+        warn!("Cannot convert clip {}: {}", file_name, err);
+        return Err("cannot convert the file".to_string());
+    }
+
+    Ok(duration)
+}
+
+/// Reads the environment variable `var`, `default` when it is unset or does not parse
+pub fn env_or<T: FromStr>(var: &str, default: T) -> T {
+    std::env::var(var)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads a size limit in MB from the environment variable `var`
+pub fn max_upload_bytes(var: &str, default_mb: u64) -> u64 {
+    env_or(var, default_mb) * 1024 * 1024
+}
+
+/// Returns how long `key` still has to wait, otherwise starts a new cooldown
+pub fn take_cooldown(
+    cooldowns: &mut HashMap<(u64, u64), Instant>,
+    key: (u64, u64),
+    cooldown: Duration,
+    now: Instant,
+) -> Option<Duration> {
+    let remaining = cooldown_remaining(cooldowns, key, cooldown, now);
+    if remaining.is_none() {
+        cooldowns.insert(key, now);
+    }
+
+    remaining
+}
+
+/// Returns how long `key` still has to wait without starting a cooldown. Expired cooldowns are
+/// forgotten
+pub fn cooldown_remaining(
+    cooldowns: &mut HashMap<(u64, u64), Instant>,
+    key: (u64, u64),
+    cooldown: Duration,
+    now: Instant,
+) -> Option<Duration> {
+    cooldowns.retain(|_, started_at| now.duration_since(*started_at) < cooldown);
+    cooldowns
+        .get(&key)
+        .map(|started_at| cooldown - now.duration_since(*started_at))
+}
+
+/// Mixes raw 48kHz stereo s16le tracks that all start at the same moment into one Opus file
+pub async fn mix_pcm_to_opus(
+    inputs: &[PathBuf],
+    output: &Path,
+    timeout: Duration,
+) -> Result<(), String> {
+    let mut command = Command::new("ffmpeg");
+    command.args(["-v", "error", "-y"]);
+    for input in inputs {
+        command
+            .args(["-f", "s16le", "-ar", "48000", "-ac", "2", "-i"])
+            .arg(input);
+    }
+    if inputs.len() > 1 {
+        // amix lowers every input to keep the sum from clipping, speakers rarely overlap
+        command.arg("-filter_complex").arg(format!(
+            "amix=inputs={}:duration=longest:dropout_transition=0,volume={}",
+            inputs.len(),
+            inputs.len()
+        ));
+    }
+    command.args(["-c:a", "libopus", "-b:a", "64k"]).arg(output);
+
+    let result = tokio::time::timeout(timeout, command.kill_on_drop(true).output())
+        .await
+        .map_err(|_| "ffmpeg timed out".to_string())?
+        .map_err(|err| format!("cannot start ffmpeg: {}", err))?;
+
+    if !result.status.success() {
+        let _ = tokio::fs::remove_file(output).await;
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            stderr.trim()
+        ));
+    }
 
-    println!("err {}", String::from_utf8_lossy(&command1.stderr));
-    println!("out {}", String::from_utf8_lossy(&command1.stdout));
-    println!("status {}", command1.status.to_string());
+    Ok(())
 }
@@ -0,0 +1,204 @@
+use std::{
+    convert::Infallible,
+    io::SeekFrom,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use tracing::{info, warn};
+
+use crate::helpers::media_store::{media_root, STAGING_DIR};
+
+const DEFAULT_MEDIA_HTTP_BIND: &str = "127.0.0.1:7777";
+const MEDIA_PREFIX: &str = "/media/";
+
+/// Where the media server listens. Comes from MEDIA_HTTP_BIND
+fn media_http_bind() -> SocketAddr {
+    std::env::var("MEDIA_HTTP_BIND")
+        .ok()
+        .and_then(|bind| bind.parse().ok())
+        .unwrap_or_else(|| {
+            DEFAULT_MEDIA_HTTP_BIND
+                .parse()
+                .expect("invalid default media bind address")
+        })
+}
+
+/// How lavalink reaches the media server. Comes from MEDIA_HTTP_URL, set it when lavalink runs on
+/// another machine
+pub fn media_base_url() -> String {
+    std::env::var("MEDIA_HTTP_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| format!("http://{}", media_http_bind()))
+}
+
+/// The url lavalink can load a file of the media store from
+pub fn media_url(path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(media_root()).ok()?;
+    let segments = relative
+        .iter()
+        .map(|segment| {
+            segment
+                .to_str()
+                .map(|segment| utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string())
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(format!(
+        "{}{}{}",
+        media_base_url(),
+        MEDIA_PREFIX,
+        segments.join("/")
+    ))
+}
+
+/// Serves the media store over http so lavalink can play jam tracks. Runs until the bot exits
+pub async fn start_media_server() {
+    let addr = media_http_bind();
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(serve_media)) });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(err) => {
+            warn!("Cannot start the media server on {}: {}", addr, err);
+            return;
+        }
+    };
+
+    info!("Media server listening on {}", addr);
+    if let Err(err) = server.serve(make_service).await {
+        warn!("Media server stopped: {}", err);
+    }
+}
+
+/// Maps a request path to a file of the store. Anything that could leave the root is refused
+pub(crate) fn resolve_media_request(request_path: &str) -> Option<PathBuf> {
+    let relative = request_path.strip_prefix(MEDIA_PREFIX)?;
+    let mut path = media_root();
+
+    for (i, segment) in relative.split('/').enumerate() {
+        let segment = percent_decode_str(segment).decode_utf8().ok()?;
+        let unsafe_segment = segment.is_empty()
+            || segment.starts_with('.')
+            || segment.contains('/')
+            || segment.contains('\\');
+        // Files there are still being downloaded
+        if unsafe_segment || (i == 0 && segment == STAGING_DIR) {
+            return None;
+        }
+        path.push(segment.as_ref());
+    }
+
+    Some(path)
+}
+
+/// Parses a single "bytes=start-end" range. Returns inclusive bounds
+pub(crate) fn parse_range(range: &str, len: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // Suffix range, the last n bytes
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().ok()?.min(len);
+            (len - suffix, len.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, len.checked_sub(1)?),
+        (start, end) => (
+            start.parse().ok()?,
+            end.parse::<u64>().ok()?.min(len.checked_sub(1)?),
+        ),
+    };
+
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start, end))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("webm") => "audio/webm",
+        Some("m4a") | Some("mp4") => "audio/mp4",
+        Some("ogg") | Some("opus") => "audio/ogg",
+        Some("mp3") => "audio/mpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+async fn serve_media(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let path = match resolve_media_request(request.uri().path()) {
+        Some(path) => path,
+        None => return Ok(status_response(StatusCode::NOT_FOUND)),
+    };
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(_) => return Ok(status_response(StatusCode::NOT_FOUND)),
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata.len(),
+        _ => return Ok(status_response(StatusCode::NOT_FOUND)),
+    };
+
+    // Lavalink seeks with range requests
+    let range = request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok());
+    let (status, start, end) = match range {
+        Some(range) => match parse_range(range, len) {
+            Some((start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+            None => {
+                let mut response = status_response(StatusCode::RANGE_NOT_SATISFIABLE);
+                if let Ok(value) = format!("bytes */{}", len).parse() {
+                    response.headers_mut().insert(header::CONTENT_RANGE, value);
+                }
+                return Ok(response);
+            }
+        },
+        None => (StatusCode::OK, 0, len.saturating_sub(1)),
+    };
+    let body_len = if len == 0 { 0 } else { end - start + 1 };
+
+    let body = if request.method() == Method::HEAD || body_len == 0 {
+        Body::empty()
+    } else {
+        if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+            warn!("Cannot seek in {}: {}", path.display(), err);
+            return Ok(status_response(StatusCode::INTERNAL_SERVER_ERROR));
+        }
+        Body::wrap_stream(ReaderStream::new(file.take(body_len)))
+    };
+
+    let mut response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, content_type(&path))
+        .header(header::CONTENT_LENGTH, body_len)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if status == StatusCode::PARTIAL_CONTENT {
+        response = response.header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, len),
+        );
+    }
+
+    Ok(response
+        .body(body)
+        .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR)))
+}
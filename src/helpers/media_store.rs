@@ -0,0 +1,281 @@
+use std::path::{Path, PathBuf};
+
+use mysql_async::Pool;
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+
+use crate::{
+    database::media::{add_media_file, get_media_file, MediaFile},
+    helpers::media_server::media_base_url,
+};
+
+const DEFAULT_MEDIA_ROOT: &str = "/home/ubuntu/projects/sakiot_rouvas/media";
+// yt-dlp writes here first, the file is moved next to the others once its checksum is known
+pub const STAGING_DIR: &str = "staging";
+const MAX_FILE_NAME_LENGTH: usize = 200;
+
+/// Where every downloaded file lives. Comes from MEDIA_ROOT
+pub fn media_root() -> PathBuf {
+    std::env::var("MEDIA_ROOT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_MEDIA_ROOT))
+}
+
+/// Path of a file of the store, named after its checksum
+pub fn media_path(media: &MediaFile) -> PathBuf {
+    media_root()
+        .join(&media.checksum[..2])
+        .join(format!("{}.{}", media.checksum, media.ext))
+}
+
+/// Path of a media row, `None` when the row or the file is gone
+pub async fn find_media_path(pool: &Pool, media_id: u64) -> Option<PathBuf> {
+    let media = get_media_file(pool, media_id).await?;
+    let path = media_path(&media);
+
+    match tokio::fs::metadata(&path).await {
+        Ok(_) => Some(path),
+        Err(_) => None,
+    }
+}
+
+/// Tracks downloaded before the store were saved under their title. `None` when the file is gone
+pub fn legacy_media_path(title: &str, ext: &str) -> Option<PathBuf> {
+    find_legacy_media_path(&media_root(), title, ext)
+}
+
+/// The raw title comes first, that is how the files were named. The sanitized name covers the ones
+/// renamed since
+pub(crate) fn find_legacy_media_path(root: &Path, title: &str, ext: &str) -> Option<PathBuf> {
+    let raw = format!("{}.{}", title, ext);
+    let sanitized = format!("{}.{}", sanitize_file_name(title), sanitize_file_name(ext));
+
+    [raw, sanitized]
+        .iter()
+        // A title with a separator would point outside of the store
+        .filter(|name| Path::new(name).file_name() == Some(name.as_ref()))
+        .map(|name| root.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Keeps a name usable as a single path component on every platform
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '(' | ')' | '[' | ']') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_FILE_NAME_LENGTH)
+        .collect();
+    let sanitized = sanitized.trim();
+
+    if sanitized.is_empty() {
+        "_".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+/// Downloads `query` into the store and records it. Anything that is not a url is searched on
+/// youtube. The process is
+/// killed if the future is dropped, callers put a timeout on it
+pub async fn download_to_store(pool: &Pool, query: &str) -> Result<MediaFile, String> {
+    let (downloaded, json) = download_to_staging(query).await?;
+
+    let (checksum, size_bytes) = hash_file(&downloaded).await?;
+    let ext = json["ext"]
+        .as_str()
+        .map(sanitize_file_name)
+        .ok_or("ext not found in yt-dlp output")?;
+    let mut media = MediaFile {
+        id: 0,
+        source_id: format!(
+            "{}:{}",
+            json["extractor_key"]
+                .as_str()
+                .unwrap_or("unknown")
+                .to_lowercase(),
+            json["id"].as_str().unwrap_or_default()
+        ),
+        source_url: json["webpage_url"].as_str().unwrap_or_default().to_owned(),
+        title: json["title"]
+            .as_str()
+            .ok_or("title not found in yt-dlp output")?
+            .to_owned(),
+        duration_ms: json["duration"]
+            .as_f64()
+            .map_or(0, |duration| (duration * 1000.0) as u64),
+        codec: json["acodec"].as_str().unwrap_or("unknown").to_owned(),
+        ext,
+        size_bytes,
+        checksum,
+    };
+
+    let path = media_path(&media);
+    if tokio::fs::metadata(&path).await.is_ok() {
+        // Same content downloaded again, keep the file we have
+        let _ = tokio::fs::remove_file(&downloaded).await;
+    } else {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| format!("cannot create {}: {}", parent.display(), err))?;
+        }
+        tokio::fs::rename(&downloaded, &path)
+            .await
+            .map_err(|err| format!("cannot move {}: {}", downloaded.display(), err))?;
+    }
+
+    media.id = add_media_file(pool, &media)
+        .await
+        .ok_or("cannot record the media file")?;
+
+    Ok(media)
+}
+
+/// Runs yt-dlp on `query` and returns the file it wrote to the staging directory with the info
+/// yt-dlp printed. The caller moves or removes the file
+pub async fn download_to_staging(query: &str) -> Result<(PathBuf, serde_json::Value), String> {
+    let search = if query.starts_with("https://") || query.starts_with("http://") {
+        query.to_string()
+    } else {
+        format!("ytsearch:{}", query)
+    };
+
+    run_yt_dlp(&search, "%(extractor_key)s-%(id)s", &[]).await
+}
+
+/// Downloads `duration` seconds from `start` of a link to the staging directory as `name`. Only
+/// that section is fetched, however long the video is. The file starts at `start`
+pub async fn download_section_to_staging(
+    url: &str,
+    name: &str,
+    start: f64,
+    duration: f64,
+) -> Result<PathBuf, String> {
+    let section = format!("*{:.3}-{:.3}", start, start + duration);
+    let (downloaded, _) = run_yt_dlp(url, name, &["--download-sections", section.as_str()]).await?;
+
+    Ok(downloaded)
+}
+
+/// A link ffmpeg can stream the audio of a lavalink track from. Files of the media server are
+/// linked as they are, yt-dlp resolves the others
+pub async fn stream_url(uri: &str) -> Result<String, String> {
+    if uri.starts_with(&format!("{}/", media_base_url())) {
+        return Ok(uri.to_string());
+    }
+
+    let child = Command::new("yt-dlp")
+        .args([
+            "-g",
+            "-f",
+            "webm[abr>0]/bestaudio/best",
+            "--no-playlist",
+            "--ignore-config",
+            "--no-warnings",
+            uri,
+        ])
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|err| format!("cannot start yt-dlp: {}", err))?;
+
+    if !child.status.success() {
+        let stderr = String::from_utf8_lossy(&child.stderr);
+        return Err(format!(
+            "yt-dlp exited with {}: {}",
+            child.status,
+            stderr.trim()
+        ));
+    }
+
+    String::from_utf8_lossy(&child.stdout)
+        .lines()
+        .next()
+        .map(|url| url.to_string())
+        .ok_or_else(|| "yt-dlp returned no link".to_string())
+}
+
+/// `name` is the output template of the file without its extension. Names shared by concurrent
+/// downloads have to point to the same content
+async fn run_yt_dlp(
+    search: &str,
+    name: &str,
+    extra_args: &[&str],
+) -> Result<(PathBuf, serde_json::Value), String> {
+    let staging = media_root().join(STAGING_DIR);
+    tokio::fs::create_dir_all(&staging)
+        .await
+        .map_err(|err| format!("cannot create {}: {}", staging.display(), err))?;
+
+    let output_template = staging.join(format!("{}.%(ext)s", name));
+    let child = Command::new("yt-dlp")
+        .args([
+            "-j",
+            "--no-simulate",
+            "--embed-metadata",
+            "--restrict-filenames",
+            // The garbage collector goes by the modification time
+            "--no-mtime",
+            "-f",
+            "webm[abr>0]/bestaudio/best",
+            "-R",
+            "3",
+            "--no-playlist",
+            "--ignore-config",
+            "--no-warnings",
+        ])
+        .args(extra_args)
+        .args([search, "-o"])
+        .arg(&output_template)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|err| format!("cannot start yt-dlp: {}", err))?;
+
+    if !child.status.success() {
+        let stderr = String::from_utf8_lossy(&child.stderr);
+        return Err(format!(
+            "yt-dlp exited with {}: {}",
+            child.status,
+            stderr.trim()
+        ));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&child.stdout)
+        .map_err(|err| format!("cannot parse yt-dlp output: {}", err))?;
+    let downloaded = json["filename"]
+        .as_str()
+        .or_else(|| json["_filename"].as_str())
+        .map(PathBuf::from)
+        .ok_or("filename not found in yt-dlp output")?;
+    if !downloaded.starts_with(&staging) {
+        return Err(format!(
+            "yt-dlp wrote outside of the staging directory: {}",
+            downloaded.display()
+        ));
+    }
+
+    Ok((downloaded, json))
+}
+
+/// Returns the hex encoded sha256 and the size of a file
+async fn hash_file(path: &Path) -> Result<(String, u64), String> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)
+            .map_err(|err| format!("cannot open {}: {}", path.display(), err))?;
+        let mut hasher = Sha256::new();
+        let size = std::io::copy(&mut file, &mut hasher)
+            .map_err(|err| format!("cannot read {}: {}", path.display(), err))?;
+
+        Ok((format!("{:x}", hasher.finalize()), size))
+    })
+    .await
+    .map_err(|err| format!("cannot hash file: {}", err))?
+}
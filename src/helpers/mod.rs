@@ -1,2 +1,4 @@
 pub mod db_helper;
 pub mod main;
+pub mod media_server;
+pub mod media_store;
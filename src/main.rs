@@ -1,7 +1,7 @@
 // use std::env;
 use std::{collections::HashMap, env, sync::Arc};
 
-use lavalink_rs::{gateway::*, model::*, LavalinkClient};
+use lavalink_rs::{error::LavalinkResult, gateway::*, model::*, LavalinkClient};
 use serenity::{
     async_trait,
     client::bridge::gateway::GatewayIntents,
@@ -13,11 +13,17 @@ use songbird::{driver::DecodeMode, Config, SerenityInit};
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
+use crate::events::interactions::lavalink::nodes::{
+    node_addresses_from_env, start_health_check, start_node_connection, LavalinkNodes,
+};
+
 pub mod config;
 pub mod database;
 pub mod events;
 pub mod features;
 pub mod helpers;
+#[cfg(test)]
+mod testing;
 
 struct Handler;
 
@@ -303,8 +309,12 @@ impl EventHandler for Handler {
     ) {
     }
 
-    async fn ready(&self, _ctx: Context, ready: Ready) {
+    async fn ready(&self, ctx: Context, ready: Ready) {
         println!("{} is connected!", ready.user.name);
+        features::stats::start_weekly_summary(&ctx);
+        features::downloads::start_download_workers(&ctx);
+        features::storage::start_storage_gc(&ctx);
+        features::boss_music::copy_legacy_boss_music(&ctx).await;
         // println!("ready: {:#?}", ready.guilds);
     }
     // TODO
@@ -423,18 +433,21 @@ impl EventHandler for Handler {
 struct Lavalink;
 
 impl TypeMapKey for Lavalink {
-    type Value = LavalinkClient;
+    type Value = Arc<RwLock<LavalinkNodes>>;
 }
 
 struct LavalinkHandler {
     client: Arc<RwLock<TypeMap>>,
+    // Index of the node in LavalinkNodes
+    node: usize,
 }
 
 #[async_trait]
 impl LavalinkEventHandler for LavalinkHandler {
     /// Periodic event that returns the statistics of the server.
-    async fn stats(&self, _client: LavalinkClient, _event: Stats) {
-        // ??
+    async fn stats(&self, _client: LavalinkClient, event: Stats) {
+        events::interactions::lavalink::nodes::update_node_stats(&self.client, self.node, &event)
+            .await;
     }
     /// Event that triggers when a player updates.
     async fn player_update(&self, _client: LavalinkClient, event: PlayerUpdate) {
@@ -450,11 +463,35 @@ impl LavalinkEventHandler for LavalinkHandler {
             hash_map.how_long = std::time::Instant::now();
         }
     }
-    async fn track_start(&self, _client: LavalinkClient, event: TrackStart) {
+    async fn track_start(&self, client: LavalinkClient, event: TrackStart) {
         info!("Track started! Guild: {}", event.guild_id);
+        features::history::record_track_start(
+            &self.client,
+            &client,
+            event.guild_id.0,
+            &event.track,
+        )
+        .await;
     }
-    async fn track_finish(&self, _client: LavalinkClient, event: TrackFinish) {
+    async fn track_finish(&self, client: LavalinkClient, event: TrackFinish) {
         info!("Track finished! Guild: {}", event.guild_id);
+        features::history::record_track_finish(&self.client, event.guild_id.0, &event.reason).await;
+        features::sleep::on_track_finish(&self.client, &client, event.guild_id.0).await;
+        features::boss_music::on_track_finish(
+            &self.client,
+            &client,
+            event.guild_id.0,
+            &event.track,
+        )
+        .await;
+        features::autoplay::on_track_finish(
+            &self.client,
+            &client,
+            event.guild_id.0,
+            &event.track,
+            &event.reason,
+        )
+        .await;
     }
     /// Event that triggers when an exception happens with a track.
     async fn track_exception(&self, _client: LavalinkClient, _event: TrackException) {
@@ -475,21 +512,44 @@ impl TypeMapKey for MysqlConnection {
     type Value = mysql_async::Pool;
 }
 
+/// The theme played for (guild_id, user_id), `None` for users without one
 pub struct HasBossMusic;
 impl TypeMapKey for HasBossMusic {
-    type Value = HashMap<u64, Option<String>>;
+    type Value = HashMap<(u64, u64), Option<String>>;
 }
 
 pub struct GuildTrack {
     volume: u16,
     position: i64,
     how_long: std::time::Instant,
+    // The play history entry of the current track
+    history_id: Option<u64>,
+    sleep_timer: Option<features::sleep::SleepTimer>,
+    // Joined only to play an entrance theme or a soundboard clip, leaves when it ends
+    boss_music_visit: bool,
 }
 pub struct GuildTrackMap;
 impl TypeMapKey for GuildTrackMap {
     type Value = Arc<Mutex<HashMap<u64, GuildTrack>>>;
 }
 
+/// A single connection attempt. Retries are done by `start_node_connection`
+async fn connect_lavalink_node(
+    host: &str,
+    port: u16,
+    data: Arc<RwLock<TypeMap>>,
+    node: usize,
+) -> LavalinkResult<LavalinkClient> {
+    LavalinkClient::builder(UserId(890262092668624917))
+        .set_host(host)
+        .set_port(port)
+        .set_password(
+            env::var("LAVALINK_PASSWORD").unwrap_or_else(|_| "youshallnotpass".to_string()),
+        )
+        .build(LavalinkHandler { client: data, node })
+        .await
+}
+
 #[tokio::main]
 async fn main() {
     let mysql_pool = mysql_async::Pool::new(config::DB_URL);
@@ -514,7 +574,7 @@ async fn main() {
 
     // Here, we need to configure Songbird to decode all incoming voice packets.
     // If you want, you can do this on a per-call basis---here, we need it to
-    // read the audio data that other people are sending us!
+    // read the audio data that other people are sending us! /record relies on it
     let songbird_config = Config::default().decode_mode(DecodeMode::Decode);
 
     // Create a new instance of the Client, logging in as a bot. This will
@@ -528,51 +588,12 @@ async fn main() {
         .await
         .expect("Err creating client");
 
-    let lavalink = match LavalinkClient::builder(UserId(890262092668624917))
-        //let lavalink = LavalinkClient::builder(bot_id, &token)
-        .set_host("127.0.0.1")
-        .set_password(
-            env::var("LAVALINK_PASSWORD").unwrap_or_else(|_| "youshallnotpass".to_string()),
-        )
-        .build(LavalinkHandler {
-            client: client.data.clone(),
-        })
-        .await
-    {
-        Ok(ok) => ok,
-        Err(err) => {
-            // try to reconect
-            let mut restart_counter = 0;
-            loop {
-                if restart_counter > 10 {
-                    // lavalink is probably not initiated
-                    panic!("lavalink error: {}", err);
-                } else {
-                    restart_counter += 1;
-                    if let Ok(ok) = LavalinkClient::builder(UserId(890262092668624917))
-                        .set_host("127.0.0.1")
-                        .set_password(
-                            env::var("LAVALINK_PASSWORD")
-                                .unwrap_or_else(|_| "youshallnotpass".to_string()),
-                        )
-                        .build(LavalinkHandler {
-                            client: client.data.clone(),
-                        })
-                        .await
-                    {
-                        break ok;
-                    } else {
-                        info!(
-                            "Trying to reconect to lavalink for the {} time",
-                            restart_counter
-                        );
-                    }
-                }
-
-                std::thread::sleep(std::time::Duration::from_millis(1000));
-            }
-        }
-    };
+    // Lavalink is connected in the background so the bot works without music until it is up
+    let mut lavalink_nodes = LavalinkNodes::default();
+    let node_addresses: Vec<(usize, String, u16)> = node_addresses_from_env()
+        .into_iter()
+        .map(|(host, port)| (lavalink_nodes.add_node(host.clone(), port), host, port))
+        .collect();
 
     {
         let mut data = client.data.write().await;
@@ -580,9 +601,27 @@ async fn main() {
         data.insert::<MysqlConnection>(mysql_pool.clone());
         // Custom data
         data.insert::<GuildTrackMap>(Arc::new(Mutex::new(HashMap::new())));
+        data.insert::<HasBossMusic>(HashMap::new());
+        data.insert::<features::boss_music::BossMusicCooldowns>(Arc::new(Mutex::new(
+            HashMap::new(),
+        )));
+        data.insert::<features::soundboard::SoundboardCooldowns>(Arc::new(Mutex::new(
+            HashMap::new(),
+        )));
+        data.insert::<features::soundboard::SoundboardMixes>(Arc::new(Mutex::new(HashMap::new())));
+        data.insert::<features::clip::ClipBuffers>(Arc::new(Mutex::new(HashMap::new())));
+        data.insert::<features::voice_stats::SpeakingTrackers>(Arc::new(
+            Mutex::new(HashMap::new()),
+        ));
+        data.insert::<features::recording::Recordings>(Arc::new(Mutex::new(HashMap::new())));
         // Lavalink
-        data.insert::<Lavalink>(lavalink);
+        data.insert::<Lavalink>(Arc::new(RwLock::new(lavalink_nodes)));
+    }
+    for (index, host, port) in node_addresses {
+        start_node_connection(client.data.clone(), index, host, port);
     }
+    start_health_check(client.data.clone());
+    tokio::spawn(helpers::media_server::start_media_server());
 
     // Finally, start a single shard, and start listening to events.
     //
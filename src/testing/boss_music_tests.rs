@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use crate::features::boss_music::copy_legacy_themes;
+
+fn test_dir(name: &str) -> PathBuf {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    std::env::temp_dir().join(format!("boss_music_tests-{}-{}", name, nanos))
+}
+
+#[tokio::test]
+async fn copies_legacy_themes_still_in_use() {
+    let from = test_dir("from");
+    let to = test_dir("to");
+    tokio::fs::create_dir_all(&from).await.unwrap();
+    tokio::fs::write(from.join("old.ogg"), b"old")
+        .await
+        .unwrap();
+    tokio::fs::write(from.join("unused.ogg"), b"unused")
+        .await
+        .unwrap();
+
+    let songs = vec!["old.ogg".to_string(), "gone.ogg".to_string()];
+    assert_eq!(copy_legacy_themes(&songs, &from, &to).await, 1);
+    assert_eq!(tokio::fs::read(to.join("old.ogg")).await.unwrap(), b"old");
+    assert!(tokio::fs::metadata(to.join("unused.ogg")).await.is_err());
+    assert!(tokio::fs::metadata(from.join("old.ogg")).await.is_ok());
+
+    // Already there, nothing left to copy
+    assert_eq!(copy_legacy_themes(&songs, &from, &to).await, 0);
+
+    let _ = tokio::fs::remove_dir_all(&from).await;
+    let _ = tokio::fs::remove_dir_all(&to).await;
+}
+
+#[tokio::test]
+async fn skips_theme_names_leaving_the_directory() {
+    let from = test_dir("from");
+    let to = test_dir("to");
+    tokio::fs::create_dir_all(from.join("nested"))
+        .await
+        .unwrap();
+    tokio::fs::write(from.join("nested").join("song.ogg"), b"song")
+        .await
+        .unwrap();
+
+    let songs = vec!["nested/song.ogg".to_string(), "../song.ogg".to_string()];
+    assert_eq!(copy_legacy_themes(&songs, &from, &to).await, 0);
+
+    let _ = tokio::fs::remove_dir_all(&from).await;
+    let _ = tokio::fs::remove_dir_all(&to).await;
+}
@@ -0,0 +1,43 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::features::clip::mix_packets;
+
+#[test]
+fn mixes_speakers_where_they_spoke() {
+    let now = Instant::now() + Duration::from_secs(10);
+    let start = now - Duration::from_secs(1);
+    let half = start + Duration::from_millis(500);
+
+    let mut packets = HashMap::new();
+    packets.insert(
+        1,
+        VecDeque::from(vec![
+            // Older than the window
+            (now - Duration::from_secs(2), vec![1000, 1000]),
+            (start, vec![100, 200]),
+            (half, vec![i16::MAX, i16::MIN]),
+        ]),
+    );
+    packets.insert(
+        2,
+        VecDeque::from(vec![(start, vec![50, -300]), (half, vec![10, -10])]),
+    );
+
+    let mix = mix_packets(&packets, now, Duration::from_secs(1));
+    // One second of 48kHz stereo
+    assert_eq!(mix.len(), 96_000);
+    assert_eq!(&mix[..3], &[150, -100, 0]);
+    // Sums past the range of a sample are clamped
+    assert_eq!(&mix[48_000..48_003], &[i16::MAX, i16::MIN, 0]);
+    assert_eq!(mix.iter().filter(|sample| **sample != 0).count(), 4);
+}
+
+#[test]
+fn mixes_silence_without_packets() {
+    let mix = mix_packets(&HashMap::new(), Instant::now(), Duration::from_secs(2));
+    assert_eq!(mix.len(), 192_000);
+    assert!(mix.iter().all(|sample| *sample == 0));
+}
@@ -0,0 +1,33 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mysql_async::{prelude::*, Pool};
+
+/// A pool on TEST_DATABASE_URL, a database with the schema of the bot. Tests needing the database
+/// skip themselves when it is not set
+pub async fn test_pool() -> Option<Pool> {
+    match std::env::var("TEST_DATABASE_URL") {
+        Ok(url) => Some(Pool::new(url.as_str())),
+        Err(_) => {
+            println!("TEST_DATABASE_URL is not set, skipping");
+            None
+        }
+    }
+}
+
+/// A guild id no other test run uses, so tests can share the database
+pub fn test_guild_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+/// Runs a statement the tests need to set up or clean up rows
+pub async fn exec(pool: &Pool, query: &str, params: impl Into<mysql_async::Params> + Send) {
+    pool.get_conn()
+        .await
+        .expect("cannot connect to the test database")
+        .exec_drop(query, params)
+        .await
+        .expect("test query failed");
+}
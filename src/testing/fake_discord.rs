@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+};
+
+use super::harness::message_json;
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path without the api prefix, e.g. "/interactions/1/token/callback"
+    pub path: String,
+    /// `Value::Null` when there was no body
+    pub body: Value,
+}
+
+/// Stands in for the discord REST api. Every request is recorded and answered with the
+/// smallest payload serenity accepts
+pub struct FakeDiscord {
+    pub port: u16,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl FakeDiscord {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("cannot bind fake discord");
+        let port = listener.local_addr().expect("no local address").port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let accept_requests = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(stream, accept_requests.clone()));
+            }
+        });
+
+        Self { port, requests }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Bodies of the initial interaction responses ({ "type", "data" })
+    pub async fn responses(&self) -> Vec<Value> {
+        self.bodies(|request| request.method == "POST" && request.path.ends_with("/callback"))
+            .await
+    }
+
+    /// Bodies of the edits of the original interaction responses
+    pub async fn edits(&self) -> Vec<Value> {
+        self.bodies(|request| {
+            request.method == "PATCH" && request.path.ends_with("/messages/@original")
+        })
+        .await
+    }
+
+    async fn bodies(&self, filter: impl Fn(&RecordedRequest) -> bool) -> Vec<Value> {
+        self.requests
+            .lock()
+            .await
+            .iter()
+            .filter(|request| filter(request))
+            .map(|request| request.body.clone())
+            .collect()
+    }
+}
+
+/// The custom ids of the buttons of a message or response, one `Vec` per action row
+pub fn button_rows(payload: &Value) -> Vec<Vec<String>> {
+    // Responses nest the message under "data", edits do not
+    let components = match payload.get("data") {
+        Some(data) => &data["components"],
+        None => &payload["components"],
+    };
+
+    components
+        .as_array()
+        .map(|rows| {
+            rows.iter()
+                .map(|row| {
+                    row["components"]
+                        .as_array()
+                        .map(|buttons| {
+                            buttons
+                                .iter()
+                                .filter_map(|button| button["custom_id"].as_str())
+                                .map(|custom_id| custom_id.to_string())
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The content of a message or response
+pub fn content(payload: &Value) -> &str {
+    match payload.get("data") {
+        Some(data) => data["content"].as_str().unwrap_or(""),
+        None => payload["content"].as_str().unwrap_or(""),
+    }
+}
+
+/// Whether a response is only visible to the user
+pub fn is_ephemeral(payload: &Value) -> bool {
+    payload["data"]["flags"].as_u64().unwrap_or(0) & 64 != 0
+}
+
+async fn handle_connection(mut stream: TcpStream, requests: Arc<Mutex<Vec<RecordedRequest>>>) {
+    let mut buf = Vec::new();
+    let mut chunk = vec![0; 8192];
+
+    // Read the head, then as much body as Content-Length says
+    let head_end = loop {
+        let n = match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(position) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < head_end + content_length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let full_path = request_line.next().unwrap_or("/");
+    // Drop "/api/v8" and the query string
+    let path = full_path.split('?').next().unwrap_or_default();
+    let path = path.strip_prefix("/api").unwrap_or(path);
+    let path = match path.strip_prefix("/v") {
+        Some(rest) => rest.trim_start_matches(|c: char| c.is_ascii_digit()),
+        None => path,
+    }
+    .to_string();
+    let body = serde_json::from_slice(&buf[head_end..]).unwrap_or(Value::Null);
+
+    let (status, response) = if path.ends_with("/callback") {
+        ("204 No Content", String::new())
+    } else if path.starts_with("/webhooks/") && method != "DELETE" {
+        ("200 OK", message_json(&body).to_string())
+    } else {
+        ("200 OK", "{}".to_string())
+    };
+
+    requests
+        .lock()
+        .await
+        .push(RecordedRequest { method, path, body });
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
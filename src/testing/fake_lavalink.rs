@@ -0,0 +1,443 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_tungstenite::tungstenite::Message;
+use futures::{SinkExt, StreamExt};
+use lavalink_rs::{gateway::LavalinkEventHandler, LavalinkClient};
+use serde_json::{json, Value};
+use serenity::model::id::UserId;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex, Notify},
+};
+
+// The fake does not check it, this is only what the clients send
+pub const FAKE_PASSWORD: &str = "youshallnotpass";
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct FakeTrack {
+    pub title: String,
+    pub author: String,
+    pub uri: String,
+    pub length_ms: u64,
+}
+
+impl FakeTrack {
+    pub fn new(title: &str, uri: &str, length_ms: u64) -> Self {
+        Self {
+            title: title.to_string(),
+            author: "Fake artist".to_string(),
+            uri: uri.to_string(),
+            length_ms,
+        }
+    }
+
+    /// Real lavalink sends a base64 blob that clients never look into, any unique string works
+    pub fn encoded(&self) -> String {
+        format!("fake:{}", self.uri)
+    }
+
+    fn info(&self) -> Value {
+        json!({
+            "identifier": self.uri,
+            "isSeekable": true,
+            "author": self.author,
+            "length": self.length_ms,
+            "isStream": false,
+            "position": 0,
+            "title": self.title,
+            "uri": self.uri,
+            "sourceName": "fake",
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "track": self.encoded(),
+            "info": self.info(),
+        })
+    }
+}
+
+enum LoadResult {
+    Tracks(Vec<FakeTrack>),
+    Failed(String),
+}
+
+#[derive(Default)]
+struct FakeState {
+    /// identifier -> what `/loadtracks` answers
+    load_results: HashMap<String, LoadResult>,
+    /// encoded track -> track, for `/decodetrack`
+    known_tracks: HashMap<String, FakeTrack>,
+    /// Every op the clients sent over the websocket
+    received: Vec<Value>,
+    /// Index in `received` after the last op returned by `wait_for_op`
+    cursor: usize,
+    sockets: Vec<mpsc::UnboundedSender<String>>,
+}
+
+/// In-process lavalink server speaking just enough of the v3 protocol for lavalink-rs. Track
+/// results are scripted with `load_tracks` and events are pushed with `track_start`,
+/// `track_end`, `track_exception` and `player_update`
+pub struct FakeLavalink {
+    pub port: u16,
+    state: Arc<Mutex<FakeState>>,
+    notify: Arc<Notify>,
+}
+
+impl FakeLavalink {
+    /// Listens on a random local port
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("cannot bind fake lavalink");
+        let port = listener.local_addr().expect("no local address").port();
+        let state = Arc::new(Mutex::new(FakeState::default()));
+        let notify = Arc::new(Notify::new());
+
+        let (accept_state, accept_notify) = (state.clone(), notify.clone());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(handle_connection(
+                    stream,
+                    accept_state.clone(),
+                    accept_notify.clone(),
+                ));
+            }
+        });
+
+        Self {
+            port,
+            state,
+            notify,
+        }
+    }
+
+    /// A client connected to the fake, events go to `handler`
+    pub async fn client(
+        &self,
+        handler: impl LavalinkEventHandler + Send + Sync + 'static,
+    ) -> LavalinkClient {
+        let client = LavalinkClient::builder(UserId(1))
+            .set_host("127.0.0.1")
+            .set_port(self.port)
+            .set_password(FAKE_PASSWORD)
+            .build(handler)
+            .await
+            .expect("cannot connect to fake lavalink");
+        self.wait_for_client().await;
+
+        client
+    }
+
+    /// What `/loadtracks?identifier=<identifier>` answers. Searches from lavalink-rs use
+    /// identifiers like "ytsearch:<query>"
+    pub async fn load_tracks(&self, identifier: &str, tracks: Vec<FakeTrack>) {
+        let mut state = self.state.lock().await;
+        for track in &tracks {
+            state.known_tracks.insert(track.encoded(), track.clone());
+        }
+        state
+            .load_results
+            .insert(identifier.to_string(), LoadResult::Tracks(tracks));
+    }
+
+    /// Makes `/loadtracks?identifier=<identifier>` answer LOAD_FAILED
+    pub async fn load_failed(&self, identifier: &str, message: &str) {
+        self.state.lock().await.load_results.insert(
+            identifier.to_string(),
+            LoadResult::Failed(message.to_string()),
+        );
+    }
+
+    pub async fn wait_for_client(&self) {
+        self.wait_for(|state| state.sockets.first().map(|_| ()))
+            .await
+            .expect("no client connected to fake lavalink");
+    }
+
+    /// Waits for the next op named `op` sent after the previously awaited one and returns it
+    pub async fn wait_for_op(&self, op: &str) -> Value {
+        self.wait_for(|state| {
+            let found = state.received[state.cursor..]
+                .iter()
+                .position(|payload| payload["op"] == op)?;
+            let index = state.cursor + found;
+            state.cursor = index + 1;
+            Some(state.received[index].clone())
+        })
+        .await
+        .unwrap_or_else(|| panic!("lavalink client never sent {}", op))
+    }
+
+    /// Every op received so far
+    pub async fn received(&self) -> Vec<Value> {
+        self.state.lock().await.received.clone()
+    }
+
+    /// Sends a raw payload to every connected client
+    pub async fn send(&self, payload: Value) {
+        let text = payload.to_string();
+        self.state
+            .lock()
+            .await
+            .sockets
+            .retain(|socket| socket.send(text.clone()).is_ok());
+    }
+
+    pub async fn player_update(&self, guild_id: u64, position_ms: u64) {
+        self.send(json!({
+            "op": "playerUpdate",
+            "guildId": guild_id.to_string(),
+            "state": {
+                "time": chrono::Utc::now().timestamp_millis(),
+                "position": position_ms,
+                "connected": true,
+            },
+        }))
+        .await;
+    }
+
+    pub async fn track_start(&self, guild_id: u64, track: &FakeTrack) {
+        self.send(json!({
+            "op": "event",
+            "type": "TrackStartEvent",
+            "guildId": guild_id.to_string(),
+            "track": track.encoded(),
+        }))
+        .await;
+    }
+
+    /// `reason` is one of FINISHED, LOAD_FAILED, STOPPED, REPLACED or CLEANUP
+    pub async fn track_end(&self, guild_id: u64, track: &FakeTrack, reason: &str) {
+        self.send(json!({
+            "op": "event",
+            "type": "TrackEndEvent",
+            "guildId": guild_id.to_string(),
+            "track": track.encoded(),
+            "reason": reason,
+        }))
+        .await;
+    }
+
+    pub async fn track_exception(&self, guild_id: u64, track: &FakeTrack, message: &str) {
+        self.send(json!({
+            "op": "event",
+            "type": "TrackExceptionEvent",
+            "guildId": guild_id.to_string(),
+            "track": track.encoded(),
+            "error": message,
+            "exception": {
+                "message": message,
+                "severity": "COMMON",
+                "cause": message,
+            },
+        }))
+        .await;
+    }
+
+    pub async fn stats(&self, playing_players: u64) {
+        self.send(json!({
+            "op": "stats",
+            "players": playing_players,
+            "playingPlayers": playing_players,
+            "uptime": 1000,
+            "memory": { "free": 0, "used": 0, "allocated": 0, "reservable": 0 },
+            "cpu": { "cores": 1, "systemLoad": 0.0, "lavalinkLoad": 0.0 },
+        }))
+        .await;
+    }
+
+    async fn wait_for<T>(&self, mut check: impl FnMut(&mut FakeState) -> Option<T>) -> Option<T> {
+        let deadline = tokio::time::Instant::now() + WAIT_TIMEOUT;
+        loop {
+            // Created before checking so a notification in between is not missed
+            let notified = self.notify.notified();
+            if let Some(value) = check(&mut *self.state.lock().await) {
+                return Some(value);
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+/// Voice connection info good enough for `create_session_with_songbird`
+pub fn fake_connection_info(guild_id: u64) -> songbird::ConnectionInfo {
+    songbird::ConnectionInfo {
+        channel_id: Some(songbird::id::ChannelId(1)),
+        endpoint: "127.0.0.1".to_string(),
+        guild_id: songbird::id::GuildId(guild_id),
+        session_id: "fake_session".to_string(),
+        token: "fake_token".to_string(),
+        user_id: songbird::id::UserId(1),
+    }
+}
+
+async fn handle_connection(stream: TcpStream, state: Arc<Mutex<FakeState>>, notify: Arc<Notify>) {
+    // REST and the websocket share the port like on a real node
+    let head = match peek_head(&stream).await {
+        Some(head) => head,
+        None => return,
+    };
+
+    if head.to_ascii_lowercase().contains("upgrade: websocket") {
+        handle_websocket(stream, state, notify).await;
+    } else {
+        handle_http(stream, state).await;
+    }
+}
+
+/// The request head without consuming it, so the websocket handshake can still read it
+async fn peek_head(stream: &TcpStream) -> Option<String> {
+    let mut buf = vec![0; 8192];
+    for _ in 0..200 {
+        let n = stream.peek(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        let head = String::from_utf8_lossy(&buf[..n]);
+        if head.contains("\r\n\r\n") || n == buf.len() {
+            return Some(head.into_owned());
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    None
+}
+
+async fn handle_http(mut stream: TcpStream, state: Arc<Mutex<FakeState>>) {
+    let mut buf = vec![0; 8192];
+    let n = match stream.read(&mut buf).await {
+        Ok(n) => n,
+        Err(_) => return,
+    };
+    let request = String::from_utf8_lossy(&buf[..n]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+
+    let (status, body) = route(&*state.lock().await, path);
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn route(state: &FakeState, path: &str) -> (&'static str, String) {
+    let (route, query) = path.split_once('?').unwrap_or((path, ""));
+
+    match route {
+        "/loadtracks" => {
+            let identifier = query_param(query, "identifier").unwrap_or_default();
+            let body = match state.load_results.get(&identifier) {
+                Some(LoadResult::Tracks(tracks)) => {
+                    let load_type = if identifier.contains("search:") {
+                        "SEARCH_RESULT"
+                    } else if tracks.len() > 1 {
+                        "PLAYLIST_LOADED"
+                    } else {
+                        "TRACK_LOADED"
+                    };
+                    json!({
+                        "loadType": load_type,
+                        "playlistInfo": {},
+                        "tracks": tracks.iter().map(FakeTrack::to_json).collect::<Vec<_>>(),
+                    })
+                }
+                Some(LoadResult::Failed(message)) => json!({
+                    "loadType": "LOAD_FAILED",
+                    "playlistInfo": {},
+                    "tracks": [],
+                    "exception": { "message": message, "severity": "COMMON" },
+                }),
+                None => json!({
+                    "loadType": "NO_MATCHES",
+                    "playlistInfo": {},
+                    "tracks": [],
+                }),
+            };
+            ("200 OK", body.to_string())
+        }
+        "/decodetrack" => {
+            let track = query_param(query, "track").unwrap_or_default();
+            match state.known_tracks.get(&track) {
+                Some(track) => ("200 OK", track.info().to_string()),
+                None => ("400 Bad Request", "{}".to_string()),
+            }
+        }
+        _ => ("404 Not Found", "{}".to_string()),
+    }
+}
+
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| url_decode(value))
+}
+
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => match u8::from_str_radix(&value[i + 1..i + 3], 16) {
+                Ok(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                Err(_) => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+async fn handle_websocket(stream: TcpStream, state: Arc<Mutex<FakeState>>, notify: Arc<Notify>) {
+    let websocket = match async_tungstenite::tokio::accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(err) => {
+            println!("Fake lavalink handshake failed: {}", err);
+            return;
+        }
+    };
+    let (mut sink, mut source) = websocket.split();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    state.lock().await.sockets.push(sender);
+    notify.notify_waiters();
+
+    tokio::spawn(async move {
+        while let Some(text) = receiver.recv().await {
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(message)) = source.next().await {
+        if let Message::Text(text) = message {
+            if let Ok(payload) = serde_json::from_str::<Value>(&text) {
+                state.lock().await.received.push(payload);
+                notify.notify_waiters();
+            }
+        }
+    }
+}
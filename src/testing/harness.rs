@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use futures::channel::mpsc::{self, UnboundedReceiver};
+use serde_json::{json, Value};
+use serenity::{
+    cache::Cache,
+    client::{bridge::gateway::ShardMessenger, Context},
+    gateway::InterMessage,
+    http::HttpBuilder,
+    model::{event::GuildCreateEvent, id::GuildId, interactions::Interaction},
+    prelude::{Mutex, RwLock, TypeMap},
+};
+use songbird::{serenity::SongbirdKey, Songbird};
+
+use super::{
+    fake_discord::FakeDiscord,
+    fake_lavalink::{fake_connection_info, FakeLavalink},
+};
+use crate::{
+    connect_lavalink_node,
+    events::interactions::{
+        helpers::misc_handle, interactions::interaction_create, lavalink::nodes::LavalinkNodes,
+    },
+    GuildTrackMap, Lavalink, MysqlConnection,
+};
+
+pub const APPLICATION_ID: u64 = 1000;
+pub const GUILD_ID: u64 = 2000;
+pub const USER_ID: u64 = 3000;
+pub const TEXT_CHANNEL_ID: u64 = 4000;
+pub const VOICE_CHANNEL_ID: u64 = 5000;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(10_000);
+
+/// Runs interactions through `interaction_create` with discord replaced by `FakeDiscord`. The
+/// cache holds a single guild where the test user sits in a voice channel. Lavalink starts
+/// disconnected, see `connect_lavalink`
+pub struct InteractionHarness {
+    pub ctx: Context,
+    pub discord: FakeDiscord,
+    // Keeps the shard messenger usable
+    _shard_receiver: UnboundedReceiver<InterMessage>,
+}
+
+impl InteractionHarness {
+    pub async fn new() -> Self {
+        let discord = FakeDiscord::start().await;
+        let http = HttpBuilder::new("fake_token")
+            .proxy(discord.url())
+            .expect("invalid fake discord url")
+            .ratelimiter_disabled(true)
+            .application_id(APPLICATION_ID)
+            .build();
+
+        let cache = Cache::new();
+        let mut guild_create: GuildCreateEvent =
+            serde_json::from_value(guild_json()).expect("invalid guild fixture");
+        cache.update(&mut guild_create).await;
+
+        let songbird = Songbird::serenity();
+        songbird.initialise_client_data(1, APPLICATION_ID);
+
+        let mut data = TypeMap::new();
+        data.insert::<SongbirdKey>(songbird);
+        data.insert::<GuildTrackMap>(Arc::new(Mutex::new(HashMap::new())));
+        data.insert::<Lavalink>(Arc::new(RwLock::new(LavalinkNodes::default())));
+        // The pool only connects when a query runs, tests that need it set TEST_DATABASE_URL
+        data.insert::<MysqlConnection>(mysql_async::Pool::new(
+            std::env::var("TEST_DATABASE_URL")
+                .unwrap_or_else(|_| "mysql://root@127.0.0.1:3306/sakiot_test".to_string())
+                .as_str(),
+        ));
+
+        let (shard_sender, shard_receiver) = mpsc::unbounded();
+        let ctx = Context {
+            data: Arc::new(RwLock::new(data)),
+            shard: ShardMessenger::new(shard_sender),
+            shard_id: 0,
+            http: Arc::new(http),
+            cache: Arc::new(cache),
+        };
+
+        Self {
+            ctx,
+            discord,
+            _shard_receiver: shard_receiver,
+        }
+    }
+
+    /// Adds a lavalink node backed by a `FakeLavalink`, with the same event handler as the bot
+    pub async fn connect_lavalink(&self) -> FakeLavalink {
+        let fake = FakeLavalink::start().await;
+        let nodes = self
+            .ctx
+            .data
+            .read()
+            .await
+            .get::<Lavalink>()
+            .cloned()
+            .unwrap();
+
+        let index = nodes
+            .write()
+            .await
+            .add_node("127.0.0.1".to_string(), fake.port);
+        let client = connect_lavalink_node("127.0.0.1", fake.port, self.ctx.data.clone(), index)
+            .await
+            .expect("cannot connect to fake lavalink");
+        fake.wait_for_client().await;
+        nodes.write().await.set_client(index, client);
+
+        fake
+    }
+
+    /// Puts the bot in the voice channel of the test user without a voice gateway
+    pub async fn join_voice(&self) {
+        let manager = songbird::get(&self.ctx).await.expect("no songbird");
+        let _ = manager.get_or_insert(GuildId(GUILD_ID).into());
+        misc_handle(&self.ctx, fake_connection_info(GUILD_ID), GuildId(GUILD_ID)).await;
+    }
+
+    pub async fn run(&self, interaction: Interaction) {
+        interaction_create(self.ctx.clone(), interaction).await;
+    }
+}
+
+/// `/name` with the given options. See `string_option` and co
+pub fn command(name: &str, options: Vec<Value>) -> Interaction {
+    command_with_permissions(name, options, 0)
+}
+
+pub fn command_with_permissions(name: &str, options: Vec<Value>, permissions: u64) -> Interaction {
+    let id = next_id();
+    interaction(json!({
+        "id": id.to_string(),
+        "application_id": APPLICATION_ID.to_string(),
+        "type": 2,
+        "guild_id": GUILD_ID.to_string(),
+        "channel_id": TEXT_CHANNEL_ID.to_string(),
+        "member": member_json(permissions),
+        "token": format!("token_{}", id),
+        "version": 1,
+        "data": {
+            "id": "1",
+            "name": name,
+            "type": 1,
+            "options": options,
+            "resolved": {},
+        },
+    }))
+}
+
+/// A click on the button `custom_id` of a message sent by the bot
+pub fn button(custom_id: &str) -> Interaction {
+    let id = next_id();
+    interaction(json!({
+        "id": id.to_string(),
+        "application_id": APPLICATION_ID.to_string(),
+        "type": 3,
+        "guild_id": GUILD_ID.to_string(),
+        "channel_id": TEXT_CHANNEL_ID.to_string(),
+        "member": member_json(0),
+        "message": message_json(&Value::Null),
+        "token": format!("token_{}", id),
+        "version": 1,
+        "data": {
+            "custom_id": custom_id,
+            "component_type": 2,
+        },
+    }))
+}
+
+pub fn subcommand(name: &str, options: Vec<Value>) -> Value {
+    json!({ "name": name, "type": 1, "options": options })
+}
+
+pub fn string_option(name: &str, value: &str) -> Value {
+    json!({ "name": name, "type": 3, "value": value })
+}
+
+pub fn integer_option(name: &str, value: i64) -> Value {
+    json!({ "name": name, "type": 4, "value": value })
+}
+
+fn interaction(payload: Value) -> Interaction {
+    serde_json::from_value(payload).expect("invalid interaction payload")
+}
+
+fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+pub fn user_json(id: u64, username: &str) -> Value {
+    json!({
+        "id": id.to_string(),
+        "username": username,
+        "discriminator": "0001",
+        "avatar": null,
+        "bot": id == APPLICATION_ID,
+        "public_flags": 0,
+    })
+}
+
+fn member_json(permissions: u64) -> Value {
+    json!({
+        "user": user_json(USER_ID, "tester"),
+        "guild_id": GUILD_ID.to_string(),
+        "nick": null,
+        "roles": [],
+        "joined_at": "2021-01-01T00:00:00+00:00",
+        "deaf": false,
+        "mute": false,
+        "pending": false,
+        "permissions": permissions.to_string(),
+    })
+}
+
+/// A message sent by the bot. `body` is what was sent to discord, its content is reused
+pub fn message_json(body: &Value) -> Value {
+    json!({
+        "id": next_id().to_string(),
+        "channel_id": TEXT_CHANNEL_ID.to_string(),
+        "guild_id": GUILD_ID.to_string(),
+        "author": user_json(APPLICATION_ID, "sakiot_rouvas"),
+        "content": body["content"].as_str().unwrap_or(""),
+        "timestamp": "2021-01-01T00:00:00+00:00",
+        "edited_timestamp": null,
+        "tts": false,
+        "mention_everyone": false,
+        "mentions": [],
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": [],
+        "reactions": [],
+        "pinned": false,
+        "type": 0,
+        "flags": 0,
+        "components": [],
+    })
+}
+
+fn guild_json() -> Value {
+    json!({
+        "id": GUILD_ID.to_string(),
+        "name": "Test guild",
+        "icon": null,
+        "splash": null,
+        "discovery_splash": null,
+        "banner": null,
+        "description": null,
+        "owner_id": USER_ID.to_string(),
+        "application_id": null,
+        "region": "europe",
+        "afk_channel_id": null,
+        "afk_timeout": 300,
+        "verification_level": 0,
+        "default_message_notifications": 0,
+        "explicit_content_filter": 0,
+        "mfa_level": 0,
+        "nsfw": false,
+        "nsfw_level": 0,
+        "features": [],
+        "emojis": [],
+        "roles": [{
+            "id": GUILD_ID.to_string(),
+            "guild_id": GUILD_ID.to_string(),
+            "name": "@everyone",
+            "color": 0,
+            "hoist": false,
+            "position": 0,
+            "permissions": "0",
+            "managed": false,
+            "mentionable": false,
+        }],
+        "system_channel_id": null,
+        "system_channel_flags": 0,
+        "rules_channel_id": null,
+        "public_updates_channel_id": null,
+        "joined_at": "2021-01-01T00:00:00+00:00",
+        "large": false,
+        "unavailable": false,
+        "member_count": 1,
+        "max_members": 100,
+        "max_presences": null,
+        "max_video_channel_users": 25,
+        "vanity_url_code": null,
+        "premium_tier": 0,
+        "premium_subscription_count": 0,
+        "preferred_locale": "en-US",
+        "members": [member_json(0)],
+        "presences": [],
+        "threads": [],
+        "stage_instances": [],
+        "channels": [
+            {
+                "id": TEXT_CHANNEL_ID.to_string(),
+                "guild_id": GUILD_ID.to_string(),
+                "type": 0,
+                "name": "general",
+                "position": 0,
+                "permission_overwrites": [],
+                "nsfw": false,
+                "topic": null,
+                "last_message_id": null,
+                "parent_id": null,
+                "rate_limit_per_user": 0,
+            },
+            {
+                "id": VOICE_CHANNEL_ID.to_string(),
+                "guild_id": GUILD_ID.to_string(),
+                "type": 2,
+                "name": "Voice",
+                "position": 1,
+                "permission_overwrites": [],
+                "nsfw": false,
+                "parent_id": null,
+                "bitrate": 64000,
+                "user_limit": 0,
+                "rtc_region": null,
+            },
+        ],
+        "voice_states": [{
+            "guild_id": GUILD_ID.to_string(),
+            "channel_id": VOICE_CHANNEL_ID.to_string(),
+            "user_id": USER_ID.to_string(),
+            "session_id": "fake_session",
+            "deaf": false,
+            "mute": false,
+            "self_deaf": false,
+            "self_mute": false,
+            "self_stream": false,
+            "self_video": false,
+            "suppress": false,
+            "request_to_speak_timestamp": null,
+        }],
+    })
+}
@@ -0,0 +1,57 @@
+use crate::database::history::{add_play_history, finish_play_history, pick_radio_track};
+
+use super::database::{exec, test_guild_id, test_pool};
+
+#[tokio::test]
+async fn unfinished_plays_do_not_win_every_radio_draw() {
+    let pool = match test_pool().await {
+        Some(pool) => pool,
+        None => return,
+    };
+    let guild_id = test_guild_id();
+
+    // Still playing or never finished, `skipped` stays NULL
+    for _ in 0..3 {
+        add_play_history(
+            &pool,
+            guild_id,
+            None,
+            "Unfinished",
+            "https://youtu.be/1",
+            1_000,
+        )
+        .await;
+    }
+    for _ in 0..30 {
+        let id = add_play_history(
+            &pool,
+            guild_id,
+            None,
+            "Favourite",
+            "https://youtu.be/2",
+            1_000,
+        )
+        .await
+        .expect("cannot add play");
+        finish_play_history(&pool, id, 1_000, false).await;
+    }
+
+    // Weights 1 and 31, a NULL weight would win every draw
+    let mut unfinished = 0;
+    for _ in 0..60 {
+        let (title, _) = pick_radio_track(&pool, guild_id, 0)
+            .await
+            .expect("nothing picked");
+        if title == "Unfinished" {
+            unfinished += 1;
+        }
+    }
+    exec(
+        &pool,
+        "DELETE FROM play_history WHERE guild_id = ?",
+        (guild_id,),
+    )
+    .await;
+
+    assert!(unfinished < 30, "Unfinished won {} of 60 draws", unfinished);
+}
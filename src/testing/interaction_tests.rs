@@ -0,0 +1,217 @@
+use super::{
+    fake_discord::{button_rows, content, is_ephemeral},
+    fake_lavalink::FakeTrack,
+    harness::*,
+};
+use crate::{
+    events::interactions::lavalink::nodes::{lavalink_client_for_guild, MUSIC_BACKEND_UNAVAILABLE},
+    Lavalink,
+};
+
+async fn queue(harness: &InteractionHarness, tracks: &[FakeTrack]) {
+    let lavalink = lavalink_client_for_guild(&harness.ctx.data, GUILD_ID)
+        .await
+        .expect("no lavalink node");
+    for track in tracks {
+        let loaded = lavalink.get_tracks(&track.uri).await.expect("cannot load");
+        lavalink
+            .play(GUILD_ID, loaded.tracks[0].clone())
+            .queue()
+            .await
+            .expect("cannot queue");
+    }
+}
+
+#[tokio::test]
+async fn help_replies() {
+    let harness = InteractionHarness::new().await;
+    harness.run(command("help", vec![])).await;
+
+    let responses = harness.discord.responses().await;
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0]["type"], 4);
+    assert_eq!(content(&responses[0]), ":(");
+}
+
+#[tokio::test]
+async fn unknown_command_replies() {
+    let harness = InteractionHarness::new().await;
+    harness.run(command("nope", vec![])).await;
+
+    let responses = harness.discord.responses().await;
+    assert_eq!(
+        content(&responses[0]),
+        "No command with the name nope. This is probably not your fault"
+    );
+}
+
+#[tokio::test]
+async fn music_commands_are_refused_without_lavalink() {
+    let harness = InteractionHarness::new().await;
+    harness.run(command("que", vec![])).await;
+    harness.run(button("play")).await;
+
+    let responses = harness.discord.responses().await;
+    assert_eq!(responses.len(), 2);
+    for response in &responses {
+        assert_eq!(content(response), MUSIC_BACKEND_UNAVAILABLE);
+        assert!(is_ephemeral(response));
+    }
+}
+
+#[tokio::test]
+async fn queue_needs_the_bot_in_voice() {
+    let harness = InteractionHarness::new().await;
+    let _lavalink = harness.connect_lavalink().await;
+    harness.run(command("que", vec![])).await;
+
+    let responses = harness.discord.responses().await;
+    assert_eq!(
+        content(&responses[0]),
+        "bot is not present in a voice channel"
+    );
+    assert!(is_ephemeral(&responses[0]));
+}
+
+#[tokio::test]
+async fn queue_lists_tracks() {
+    let harness = InteractionHarness::new().await;
+    let lavalink = harness.connect_lavalink().await;
+    harness.join_voice().await;
+    let first = FakeTrack::new("First", "https://youtu.be/first", 60_000);
+    let second = FakeTrack::new("Second", "https://youtu.be/second", 60_000);
+    lavalink.load_tracks(&first.uri, vec![first.clone()]).await;
+    lavalink
+        .load_tracks(&second.uri, vec![second.clone()])
+        .await;
+    queue(&harness, &[first, second]).await;
+
+    harness.run(command("que", vec![])).await;
+
+    let responses = harness.discord.responses().await;
+    assert_eq!(
+        content(&responses[0]),
+        "Currently queued tracks\n1) First\n2) Second\n"
+    );
+}
+
+#[tokio::test]
+async fn search_plays_and_shows_controls() {
+    let harness = InteractionHarness::new().await;
+    let lavalink = harness.connect_lavalink().await;
+    harness.join_voice().await;
+    let track = FakeTrack::new("Never Gonna Give You Up", "https://youtu.be/1", 213_000);
+    lavalink
+        .load_tracks("ytsearch:never gonna", vec![track.clone()])
+        .await;
+
+    // The download /j queues runs in its own task and is left to fail without a database
+    harness
+        .run(command("j", vec![string_option("query", "never gonna")]))
+        .await;
+
+    // Deferred first, the player message comes as an edit
+    let responses = harness.discord.responses().await;
+    assert_eq!(responses[0]["type"], 5);
+    let edits = harness.discord.edits().await;
+    assert_eq!(
+        content(&edits[0]),
+        "Playing a jammer: Never Gonna Give You Up"
+    );
+    assert_eq!(
+        button_rows(&edits[0]),
+        [["play", "next", "stop", "ff", "jam_it"]]
+    );
+    assert_eq!(lavalink.wait_for_op("play").await["track"], track.encoded());
+}
+
+#[tokio::test]
+async fn play_button_pauses() {
+    let harness = InteractionHarness::new().await;
+    let lavalink = harness.connect_lavalink().await;
+    harness.join_voice().await;
+    let track = FakeTrack::new("First", "https://youtu.be/first", 60_000);
+    lavalink.load_tracks(&track.uri, vec![track.clone()]).await;
+    queue(&harness, &[track]).await;
+    lavalink.wait_for_op("play").await;
+
+    harness.run(button("play")).await;
+
+    assert_eq!(lavalink.wait_for_op("pause").await["pause"], true);
+    let responses = harness.discord.responses().await;
+    assert_eq!(content(&responses[0]), "Paused");
+}
+
+#[tokio::test]
+async fn sleep_timer_can_be_set_and_cancelled() {
+    let harness = InteractionHarness::new().await;
+    let _lavalink = harness.connect_lavalink().await;
+    harness.join_voice().await;
+
+    harness
+        .run(command(
+            "sleep",
+            vec![subcommand("duration", vec![integer_option("minutes", 10)])],
+        ))
+        .await;
+    harness
+        .run(command("sleep", vec![subcommand("cancel", vec![])]))
+        .await;
+    harness
+        .run(command("sleep", vec![subcommand("cancel", vec![])]))
+        .await;
+
+    let responses = harness.discord.responses().await;
+    assert!(content(&responses[0]).starts_with("Stopping <t:"));
+    assert_eq!(content(&responses[1]), "Sleep timer cancelled");
+    assert_eq!(content(&responses[2]), "No sleep timer set");
+}
+
+#[tokio::test]
+async fn admin_commands_need_manage_guild() {
+    let harness = InteractionHarness::new().await;
+    harness
+        .run(command(
+            "stats",
+            vec![subcommand("summary_channel", vec![])],
+        ))
+        .await;
+
+    let responses = harness.discord.responses().await;
+    assert_eq!(
+        content(&responses[0]),
+        "You need the Manage Server permission"
+    );
+    assert!(is_ephemeral(&responses[0]));
+}
+
+#[tokio::test]
+async fn stats_update_node_load() {
+    let harness = InteractionHarness::new().await;
+    let lavalink = harness.connect_lavalink().await;
+    lavalink.stats(3).await;
+
+    let nodes = harness
+        .ctx
+        .data
+        .read()
+        .await
+        .get::<Lavalink>()
+        .cloned()
+        .unwrap();
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+    loop {
+        let playing_players = nodes.read().await.nodes[0]
+            .stats
+            .as_ref()
+            .map(|stats| stats.playing_players);
+        if playing_players == Some(3) {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "stats never arrived"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+}
@@ -0,0 +1,107 @@
+use mysql_async::Pool;
+
+use crate::database::jam::{
+    get_jam_track, get_weighted_jam_track, mark_jam_played, rate_jam_track,
+};
+
+use super::database::{exec, test_guild_id, test_pool};
+
+async fn add_jam_track(pool: &Pool, guild_id: u64, audio_name: &str) -> u64 {
+    exec(
+        pool,
+        "INSERT INTO jam_it (guild_id, audio_name, ext) VALUES (?, ?, 'webm')",
+        (guild_id, audio_name),
+    )
+    .await;
+    get_jam_track(pool, guild_id, audio_name)
+        .await
+        .expect("cannot add jam track")
+        .id
+}
+
+async fn rate(pool: &Pool, guild_id: u64, id: u64, rating: i8, votes: u64) {
+    for user_id in 0..votes {
+        assert!(rate_jam_track(pool, guild_id, id, user_id, rating).await);
+    }
+}
+
+/// The titles picked in `draws` draws
+async fn draw(pool: &Pool, guild_id: u64, avoid_recent: u64, draws: usize) -> Vec<String> {
+    let mut picked = Vec::new();
+    for _ in 0..draws {
+        let track = get_weighted_jam_track(pool, guild_id, avoid_recent)
+            .await
+            .expect("nothing picked");
+        picked.push(track.audio_name);
+    }
+    picked
+}
+
+async fn remove_jam_tracks(pool: &Pool, guild_id: u64) {
+    exec(
+        pool,
+        "DELETE j, r FROM jam_it j LEFT JOIN jam_ratings r ON r.jam_id = j.id WHERE j.guild_id = ?",
+        (guild_id,),
+    )
+    .await;
+}
+
+fn count(picked: &[String], title: &str) -> usize {
+    picked.iter().filter(|picked| *picked == title).count()
+}
+
+#[tokio::test]
+async fn unrated_tracks_still_get_picked() {
+    let pool = match test_pool().await {
+        Some(pool) => pool,
+        None => return,
+    };
+    let guild_id = test_guild_id();
+    add_jam_track(&pool, guild_id, "Unrated").await;
+    let liked = add_jam_track(&pool, guild_id, "Liked").await;
+    rate(&pool, guild_id, liked, 1, 3).await;
+
+    // Weights 1 and 8
+    let picked = draw(&pool, guild_id, 0, 200).await;
+    remove_jam_tracks(&pool, guild_id).await;
+
+    assert!(count(&picked, "Unrated") > 0);
+}
+
+#[tokio::test]
+async fn recently_played_tracks_are_left_out() {
+    let pool = match test_pool().await {
+        Some(pool) => pool,
+        None => return,
+    };
+    let guild_id = test_guild_id();
+    for title in ["First", "Second"] {
+        let id = add_jam_track(&pool, guild_id, title).await;
+        mark_jam_played(&pool, id).await;
+    }
+    add_jam_track(&pool, guild_id, "Third").await;
+
+    let picked = draw(&pool, guild_id, 2, 20).await;
+    remove_jam_tracks(&pool, guild_id).await;
+
+    assert_eq!(count(&picked, "Third"), 20);
+}
+
+#[tokio::test]
+async fn negative_ratings_lower_the_odds() {
+    let pool = match test_pool().await {
+        Some(pool) => pool,
+        None => return,
+    };
+    let guild_id = test_guild_id();
+    add_jam_track(&pool, guild_id, "Unrated").await;
+    let disliked = add_jam_track(&pool, guild_id, "Disliked").await;
+    rate(&pool, guild_id, disliked, -1, 3).await;
+
+    // Weights 1 and 1/8, the disliked track wins about 22 of 200 draws
+    let picked = draw(&pool, guild_id, 0, 200).await;
+    remove_jam_tracks(&pool, guild_id).await;
+
+    let disliked = count(&picked, "Disliked");
+    assert!(disliked < 60, "Disliked won {} of 200 draws", disliked);
+}
@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use lavalink_rs::{gateway::*, model::*, LavalinkClient};
+use serenity::async_trait;
+use tokio::sync::mpsc;
+
+use super::fake_lavalink::{fake_connection_info, FakeLavalink, FakeTrack};
+use crate::events::interactions::lavalink::nodes::position_at_drop;
+
+const GUILD_ID: u64 = 42;
+
+/// Forwards the events it gets as short strings so tests can assert on their order
+struct RecordingHandler {
+    events: mpsc::UnboundedSender<String>,
+}
+
+#[async_trait]
+impl LavalinkEventHandler for RecordingHandler {
+    async fn player_update(&self, _client: LavalinkClient, event: PlayerUpdate) {
+        let _ = self.events.send(format!("update {}", event.state.position));
+    }
+    async fn track_start(&self, _client: LavalinkClient, event: TrackStart) {
+        let _ = self.events.send(format!("start {}", event.track));
+    }
+    async fn track_finish(&self, _client: LavalinkClient, event: TrackFinish) {
+        let _ = self
+            .events
+            .send(format!("finish {} {}", event.track, event.reason));
+    }
+    async fn track_exception(&self, _client: LavalinkClient, event: TrackException) {
+        let _ = self
+            .events
+            .send(format!("exception {} {}", event.track, event.error));
+    }
+}
+
+async fn setup() -> (
+    FakeLavalink,
+    LavalinkClient,
+    mpsc::UnboundedReceiver<String>,
+) {
+    let fake = FakeLavalink::start().await;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let client = fake.client(RecordingHandler { events: sender }).await;
+
+    client
+        .create_session_with_songbird(&fake_connection_info(GUILD_ID))
+        .await
+        .expect("cannot create session");
+    fake.wait_for_op("voiceUpdate").await;
+
+    (fake, client, receiver)
+}
+
+async fn next_event(receiver: &mut mpsc::UnboundedReceiver<String>) -> String {
+    tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+        .await
+        .expect("no event received")
+        .expect("event channel closed")
+}
+
+async fn queue_tracks(fake: &FakeLavalink, client: &LavalinkClient, tracks: &[FakeTrack]) {
+    for track in tracks {
+        fake.load_tracks(&track.uri, vec![track.clone()]).await;
+        let loaded = client.get_tracks(&track.uri).await.expect("cannot load");
+        client
+            .play(GUILD_ID, loaded.tracks[0].clone())
+            .queue()
+            .await
+            .expect("cannot queue");
+    }
+}
+
+#[tokio::test]
+async fn loads_scripted_tracks() {
+    let (fake, client, _events) = setup().await;
+    fake.load_tracks(
+        "ytsearch:never gonna",
+        vec![
+            FakeTrack::new("Never Gonna Give You Up", "https://youtu.be/1", 213_000),
+            FakeTrack::new("Never Gonna Stop", "https://youtu.be/2", 180_000),
+        ],
+    )
+    .await;
+    fake.load_failed("https://youtu.be/private", "This video is private")
+        .await;
+
+    let found = client
+        .search_tracks("never gonna")
+        .await
+        .expect("cannot search");
+    let titles: Vec<String> = found
+        .tracks
+        .iter()
+        .map(|track| track.info.as_ref().unwrap().title.clone())
+        .collect();
+    assert_eq!(titles, ["Never Gonna Give You Up", "Never Gonna Stop"]);
+    assert_eq!(found.tracks[0].info.as_ref().unwrap().length, 213_000);
+
+    let missing = client.get_tracks("https://youtu.be/missing").await.unwrap();
+    assert!(missing.tracks.is_empty());
+
+    let failed = client.get_tracks("https://youtu.be/private").await;
+    assert!(failed
+        .map(|tracks| tracks.tracks.is_empty())
+        .unwrap_or(true));
+}
+
+#[tokio::test]
+async fn queue_plays_tracks_in_order() {
+    let (fake, client, mut events) = setup().await;
+    let first = FakeTrack::new("First", "https://youtu.be/first", 1_000);
+    let second = FakeTrack::new("Second", "https://youtu.be/second", 1_000);
+    queue_tracks(&fake, &client, &[first.clone(), second.clone()]).await;
+
+    let play = fake.wait_for_op("play").await;
+    assert_eq!(play["guildId"], GUILD_ID.to_string());
+    assert_eq!(play["track"], first.encoded());
+
+    fake.track_start(GUILD_ID, &first).await;
+    assert_eq!(
+        next_event(&mut events).await,
+        format!("start {}", first.encoded())
+    );
+    fake.track_end(GUILD_ID, &first, "FINISHED").await;
+    assert_eq!(
+        next_event(&mut events).await,
+        format!("finish {} FINISHED", first.encoded())
+    );
+
+    // The queue loop moves on to the next track by itself
+    let play = fake.wait_for_op("play").await;
+    assert_eq!(play["track"], second.encoded());
+}
+
+#[tokio::test]
+async fn queue_loop_idles_when_empty_and_resumes() {
+    let (fake, client, mut events) = setup().await;
+    let first = FakeTrack::new("First", "https://youtu.be/first", 1_000);
+    let second = FakeTrack::new("Second", "https://youtu.be/second", 1_000);
+    queue_tracks(&fake, &client, &[first.clone()]).await;
+    assert_eq!(fake.wait_for_op("play").await["track"], first.encoded());
+
+    fake.track_end(GUILD_ID, &first, "FINISHED").await;
+    assert_eq!(
+        next_event(&mut events).await,
+        format!("finish {} FINISHED", first.encoded())
+    );
+    // Nothing is left, the loop must not replay the finished track
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let plays = fake
+        .received()
+        .await
+        .iter()
+        .filter(|payload| payload["op"] == "play")
+        .count();
+    assert_eq!(plays, 1);
+
+    queue_tracks(&fake, &client, &[second.clone()]).await;
+    assert_eq!(fake.wait_for_op("play").await["track"], second.encoded());
+}
+
+#[tokio::test]
+async fn skip_plays_next_track() {
+    let (fake, client, _events) = setup().await;
+    let first = FakeTrack::new("First", "https://youtu.be/first", 60_000);
+    let second = FakeTrack::new("Second", "https://youtu.be/second", 60_000);
+    queue_tracks(&fake, &client, &[first.clone(), second.clone()]).await;
+    assert_eq!(fake.wait_for_op("play").await["track"], first.encoded());
+
+    let skipped = client.skip(GUILD_ID).await.expect("nothing skipped");
+    assert_eq!(skipped.track.track, first.encoded());
+
+    assert_eq!(fake.wait_for_op("play").await["track"], second.encoded());
+}
+
+#[tokio::test]
+async fn seek_sends_position() {
+    let (fake, client, mut events) = setup().await;
+    let track = FakeTrack::new("Long", "https://youtu.be/long", 600_000);
+    queue_tracks(&fake, &client, &[track.clone()]).await;
+    fake.wait_for_op("play").await;
+
+    client
+        .seek(GUILD_ID, Duration::from_secs(90))
+        .await
+        .expect("cannot seek");
+    assert_eq!(fake.wait_for_op("seek").await["position"], 90_000);
+
+    fake.player_update(GUILD_ID, 90_000).await;
+    assert_eq!(next_event(&mut events).await, "update 90000");
+}
+
+#[tokio::test]
+async fn track_exception_reaches_handler() {
+    let (fake, client, mut events) = setup().await;
+    let track = FakeTrack::new("Broken", "https://youtu.be/broken", 1_000);
+    queue_tracks(&fake, &client, &[track.clone()]).await;
+    fake.wait_for_op("play").await;
+
+    fake.track_exception(GUILD_ID, &track, "Something broke")
+        .await;
+    assert_eq!(
+        next_event(&mut events).await,
+        format!("exception {} Something broke", track.encoded())
+    );
+}
+
+#[test]
+fn outage_does_not_count_as_playback() {
+    assert_eq!(position_at_drop(90_000, Duration::from_secs(2)), 92_000);
+    // Lavalink would have reported again if the track had kept playing
+    assert_eq!(position_at_drop(90_000, Duration::from_secs(600)), 95_000);
+    assert_eq!(position_at_drop(-1, Duration::ZERO), 0);
+}
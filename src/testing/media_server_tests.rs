@@ -0,0 +1,58 @@
+use crate::helpers::{
+    media_server::{parse_range, resolve_media_request},
+    media_store::media_root,
+};
+
+#[test]
+fn parses_ranges() {
+    assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+    assert_eq!(parse_range("bytes=900-", 1000), Some((900, 999)));
+    assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+    // The end is clamped to the file
+    assert_eq!(parse_range("bytes=500-5000", 1000), Some((500, 999)));
+    assert_eq!(parse_range("bytes=-5000", 1000), Some((0, 999)));
+}
+
+#[test]
+fn refuses_unsatisfiable_ranges() {
+    assert_eq!(parse_range("bytes=1000-", 1000), None);
+    assert_eq!(parse_range("bytes=50-10", 1000), None);
+    assert_eq!(parse_range("items=0-10", 1000), None);
+    assert_eq!(parse_range("bytes=a-b", 1000), None);
+    // Nothing can be served from an empty file
+    assert_eq!(parse_range("bytes=0-0", 0), None);
+    assert_eq!(parse_range("bytes=0-", 0), None);
+    assert_eq!(parse_range("bytes=-10", 0), None);
+}
+
+#[test]
+fn resolves_files_of_the_store() {
+    assert_eq!(
+        resolve_media_request("/media/ab/abcdef.webm"),
+        Some(media_root().join("ab").join("abcdef.webm"))
+    );
+    assert_eq!(
+        resolve_media_request("/media/bossmusic/my%20theme.ogg"),
+        Some(media_root().join("bossmusic").join("my theme.ogg"))
+    );
+}
+
+#[test]
+fn refuses_paths_leaving_the_store() {
+    assert_eq!(resolve_media_request("/media/../secret"), None);
+    assert_eq!(
+        resolve_media_request("/media/ab/%2E%2E/%2E%2E/secret"),
+        None
+    );
+    assert_eq!(resolve_media_request("/media/ab%2F..%2Fsecret"), None);
+    assert_eq!(resolve_media_request("/media/ab%5C..%5Csecret"), None);
+    assert_eq!(resolve_media_request("/media/ab//file.webm"), None);
+    assert_eq!(resolve_media_request("/other/ab/file.webm"), None);
+}
+
+#[test]
+fn refuses_staging_and_hidden_files() {
+    assert_eq!(resolve_media_request("/media/staging/download.webm"), None);
+    assert_eq!(resolve_media_request("/media/.hidden"), None);
+    assert_eq!(resolve_media_request("/media/ab/.partial.webm"), None);
+}
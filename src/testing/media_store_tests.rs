@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use crate::helpers::media_store::find_legacy_media_path;
+
+fn test_dir() -> PathBuf {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    std::env::temp_dir().join(format!("media_store_tests-{}", nanos))
+}
+
+#[test]
+fn finds_legacy_files_under_their_raw_title() {
+    let root = test_dir();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("Artist - Song?.webm"), b"raw").unwrap();
+    std::fs::write(root.join("Other_ Song.webm"), b"sanitized").unwrap();
+
+    assert_eq!(
+        find_legacy_media_path(&root, "Artist - Song?", "webm"),
+        Some(root.join("Artist - Song?.webm"))
+    );
+    // Renamed since, only the sanitized name is there
+    assert_eq!(
+        find_legacy_media_path(&root, "Other: Song", "webm"),
+        Some(root.join("Other_ Song.webm"))
+    );
+    assert_eq!(find_legacy_media_path(&root, "Missing", "webm"), None);
+    // Never looked up outside of the store
+    assert_eq!(
+        find_legacy_media_path(&root, "../Artist - Song?", "webm"),
+        None
+    );
+
+    let _ = std::fs::remove_dir_all(&root);
+}
@@ -0,0 +1,20 @@
+// Helpers to run the bot against local fakes instead of the real services
+pub mod database;
+pub mod fake_discord;
+pub mod fake_lavalink;
+pub mod harness;
+
+mod boss_music_tests;
+mod clip_tests;
+mod history_tests;
+mod interaction_tests;
+mod jam_tests;
+mod lavalink_tests;
+mod media_server_tests;
+mod media_store_tests;
+mod recording_tests;
+mod short_clip_tests;
+mod stats_tests;
+mod storage_tests;
+mod voice_log_tests;
+mod voice_stats_tests;
@@ -0,0 +1,58 @@
+use crate::features::recording::{per_user_file_names, silence_before};
+
+// 20ms of 48kHz stereo, the size of a decoded packet
+const PACKET: u64 = 1920;
+const SECOND: u64 = 96_000;
+
+#[test]
+fn pads_silence_between_packets() {
+    // Someone who starts speaking a second in
+    assert_eq!(silence_before(SECOND, 0, PACKET), SECOND - PACKET);
+    // Two seconds of silence after their first packet
+    assert_eq!(
+        silence_before(3 * SECOND, SECOND, PACKET),
+        2 * SECOND - PACKET
+    );
+}
+
+#[test]
+fn appends_late_and_jittery_packets() {
+    // Arrived a bit later than the previous packet ended, within the jitter
+    assert_eq!(silence_before(SECOND + 2 * PACKET, SECOND, PACKET), 0);
+    assert_eq!(silence_before(SECOND + PACKET + 3840, SECOND, PACKET), 0);
+    assert_eq!(silence_before(SECOND + PACKET + 3842, SECOND, PACKET), 3842);
+    // Arrived before the end of what was already written, never pads backwards
+    assert_eq!(silence_before(SECOND / 2, SECOND, PACKET), 0);
+}
+
+#[test]
+fn keeps_tracks_in_sync() {
+    // One speaker talks the whole time, the other only at the start and at the end
+    let mut talking = 0;
+    let mut quiet = 0;
+    for packet in 1..=250 {
+        let expected = packet * PACKET;
+        talking += silence_before(expected, talking, PACKET) + PACKET;
+        if packet <= 10 || packet > 240 {
+            quiet += silence_before(expected, quiet, PACKET) + PACKET;
+        }
+    }
+    assert_eq!(talking, 250 * PACKET);
+    assert_eq!(quiet, talking);
+}
+
+#[test]
+fn names_a_file_per_speaker_track() {
+    assert_eq!(
+        per_user_file_names("1-100", &[Some(7), Some(8), Some(7), None, None, Some(7)]),
+        [
+            "1-100-7.ogg",
+            "1-100-8.ogg",
+            "1-100-7-2.ogg",
+            "1-100-unknown.ogg",
+            "1-100-unknown-2.ogg",
+            "1-100-7-3.ogg",
+        ]
+    );
+    assert!(per_user_file_names("1-100", &[]).is_empty());
+}
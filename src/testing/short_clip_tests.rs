@@ -0,0 +1,110 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    features::{
+        boss_music::{boss_music_dir, is_short_clip_uri},
+        soundboard::soundboard_dir,
+    },
+    helpers::{
+        main::{cooldown_remaining, take_cooldown},
+        media_server::media_url,
+        media_store::media_root,
+    },
+};
+
+#[test]
+fn recognizes_short_clips() {
+    let theme = media_url(&boss_music_dir().join("1-2-3.ogg")).unwrap();
+    let sound = media_url(&soundboard_dir().join("1-3.ogg")).unwrap();
+    let track = media_url(&media_root().join("Youtube-abc.opus")).unwrap();
+
+    assert!(is_short_clip_uri(&theme));
+    assert!(is_short_clip_uri(&sound));
+    assert!(!is_short_clip_uri(&track));
+    assert!(!is_short_clip_uri("https://www.youtube.com/watch?v=abc"));
+}
+
+#[test]
+fn cooldowns_expire() {
+    let mut cooldowns = HashMap::new();
+    let cooldown = Duration::from_secs(10);
+    let start = Instant::now();
+
+    assert_eq!(take_cooldown(&mut cooldowns, (1, 2), cooldown, start), None);
+    assert_eq!(
+        take_cooldown(
+            &mut cooldowns,
+            (1, 2),
+            cooldown,
+            start + Duration::from_secs(4)
+        ),
+        Some(Duration::from_secs(6))
+    );
+    // Other members and guilds have their own cooldown
+    assert_eq!(
+        take_cooldown(
+            &mut cooldowns,
+            (1, 3),
+            cooldown,
+            start + Duration::from_secs(4)
+        ),
+        None
+    );
+    assert_eq!(
+        take_cooldown(
+            &mut cooldowns,
+            (2, 2),
+            cooldown,
+            start + Duration::from_secs(4)
+        ),
+        None
+    );
+
+    // Once expired the cooldown is forgotten and starts again
+    assert_eq!(
+        take_cooldown(&mut cooldowns, (1, 2), cooldown, start + cooldown),
+        None
+    );
+    assert_eq!(cooldowns.len(), 3);
+    assert_eq!(
+        take_cooldown(
+            &mut cooldowns,
+            (1, 2),
+            cooldown,
+            start + Duration::from_secs(11)
+        ),
+        Some(Duration::from_secs(9))
+    );
+}
+
+#[test]
+fn checking_a_cooldown_does_not_start_one() {
+    let mut cooldowns = HashMap::new();
+    let cooldown = Duration::from_secs(10);
+    let start = Instant::now();
+
+    assert_eq!(
+        cooldown_remaining(&mut cooldowns, (1, 2), cooldown, start),
+        None
+    );
+    assert!(cooldowns.is_empty());
+
+    take_cooldown(&mut cooldowns, (1, 2), cooldown, start);
+    assert_eq!(
+        cooldown_remaining(
+            &mut cooldowns,
+            (1, 2),
+            cooldown,
+            start + Duration::from_secs(3)
+        ),
+        Some(Duration::from_secs(7))
+    );
+    assert_eq!(
+        cooldown_remaining(&mut cooldowns, (1, 2), cooldown, start + cooldown),
+        None
+    );
+    assert!(cooldowns.is_empty());
+}
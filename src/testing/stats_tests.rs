@@ -0,0 +1,12 @@
+use crate::features::stats::is_permanent_channel_error;
+
+#[test]
+fn forgets_channels_the_bot_cannot_post_in() {
+    // Unknown Channel, Missing Access and Missing Permissions
+    assert!(is_permanent_channel_error(10003));
+    assert!(is_permanent_channel_error(50001));
+    assert!(is_permanent_channel_error(50013));
+    // Other errors pass, the summary is tried again the next hour
+    assert!(!is_permanent_channel_error(0));
+    assert!(!is_permanent_channel_error(130000));
+}
@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use serenity::{async_trait, prelude::Mutex};
+
+use crate::{
+    database::media::MediaFile,
+    features::storage::{evict_until, rows_to_forget, EvictionStore},
+};
+
+/// Files in least recently used order. Evicting the ones in `stuck` fails like a busy file would
+struct FakeStore {
+    files: Mutex<Vec<MediaFile>>,
+    stuck: HashSet<u64>,
+}
+
+impl FakeStore {
+    fn new(sizes: &[u64], stuck: &[u64]) -> Self {
+        FakeStore {
+            files: Mutex::new(
+                sizes
+                    .iter()
+                    .enumerate()
+                    .map(|(id, size)| media(id as u64, *size))
+                    .collect(),
+            ),
+            stuck: stuck.iter().copied().collect(),
+        }
+    }
+
+    async fn ids(&self) -> Vec<u64> {
+        self.files
+            .lock()
+            .await
+            .iter()
+            .map(|media| media.id)
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EvictionStore for FakeStore {
+    async fn usage(&self, _guild_id: Option<u64>) -> u64 {
+        self.files
+            .lock()
+            .await
+            .iter()
+            .map(|media| media.size_bytes)
+            .sum()
+    }
+
+    async fn evictable(&self, _guild_id: Option<u64>, limit: u64) -> Vec<MediaFile> {
+        self.files
+            .lock()
+            .await
+            .iter()
+            .take(limit as usize)
+            .cloned()
+            .collect()
+    }
+
+    async fn evict(&self, media: &MediaFile) -> bool {
+        if self.stuck.contains(&media.id) {
+            return false;
+        }
+        self.files.lock().await.retain(|file| file.id != media.id);
+        true
+    }
+}
+
+fn media(id: u64, size_bytes: u64) -> MediaFile {
+    MediaFile {
+        id,
+        source_id: id.to_string(),
+        source_url: String::new(),
+        title: format!("track {}", id),
+        duration_ms: 0,
+        codec: "opus".to_string(),
+        ext: "ogg".to_string(),
+        size_bytes,
+        checksum: format!("{:02x}", id),
+    }
+}
+
+#[tokio::test]
+async fn evicts_least_recently_used_until_under_quota() {
+    let store = FakeStore::new(&[10, 10, 10, 10], &[]);
+
+    assert!(evict_until(&store, None, 25).await);
+    assert_eq!(store.ids().await, [2, 3]);
+}
+
+#[tokio::test]
+async fn skips_files_that_cannot_be_removed() {
+    let store = FakeStore::new(&[10, 10, 10, 10], &[0]);
+
+    assert!(evict_until(&store, None, 25).await);
+    assert_eq!(store.ids().await, [0, 3]);
+}
+
+#[tokio::test]
+async fn gives_up_when_nothing_can_be_removed() {
+    let store = FakeStore::new(&[10, 10], &[0, 1]);
+
+    let result = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        evict_until(&store, None, 5),
+    )
+    .await;
+    assert_eq!(result.ok(), Some(false));
+    assert_eq!(store.ids().await, [0, 1]);
+}
+
+fn ids(ids: &[u64]) -> HashSet<u64> {
+    ids.iter().copied().collect()
+}
+
+#[test]
+fn forgets_rows_missing_twice_in_a_row() {
+    // Missing for the first time, kept until the next run
+    assert_eq!(rows_to_forget(10, &ids(&[1, 2]), &ids(&[])), Some(vec![]));
+    assert_eq!(
+        rows_to_forget(10, &ids(&[1, 2]), &ids(&[2, 3])),
+        Some(vec![2])
+    );
+}
+
+#[test]
+fn forgets_nothing_when_most_files_are_missing() {
+    assert_eq!(rows_to_forget(4, &ids(&[1, 2, 3]), &ids(&[1, 2, 3])), None);
+    assert_eq!(
+        rows_to_forget(4, &ids(&[1, 2]), &ids(&[1, 2])),
+        Some(vec![1, 2])
+    );
+    assert_eq!(rows_to_forget(0, &ids(&[]), &ids(&[])), Some(vec![]));
+}
@@ -0,0 +1,92 @@
+use serde_json::json;
+use serenity::model::{id::ChannelId, prelude::VoiceState};
+
+use crate::features::voice_log::{voice_changes, VoiceChange};
+
+fn voice_state(channel_id: Option<u64>, self_mute: bool) -> VoiceState {
+    serde_json::from_value(json!({
+        "channel_id": channel_id.map(|id| id.to_string()),
+        "deaf": false,
+        "guild_id": "1",
+        "mute": false,
+        "self_deaf": false,
+        "self_mute": self_mute,
+        "self_stream": false,
+        "self_video": false,
+        "session_id": "session",
+        "suppress": false,
+        "user_id": "2",
+    }))
+    .unwrap()
+}
+
+#[test]
+fn logs_joins_moves_and_leaves() {
+    let outside = voice_state(None, false);
+    let first = voice_state(Some(10), false);
+    let second = voice_state(Some(20), false);
+
+    assert_eq!(
+        voice_changes(Some(&outside), None, &first),
+        vec![VoiceChange::Join(ChannelId(10))]
+    );
+    assert_eq!(
+        voice_changes(Some(&first), None, &second),
+        vec![VoiceChange::Move {
+            from: ChannelId(10),
+            to: ChannelId(20)
+        }]
+    );
+    assert_eq!(
+        voice_changes(Some(&second), None, &outside),
+        vec![VoiceChange::Leave(ChannelId(20))]
+    );
+}
+
+#[test]
+fn logs_toggles_only_inside_a_channel() {
+    assert_eq!(
+        voice_changes(
+            Some(&voice_state(Some(10), false)),
+            None,
+            &voice_state(Some(10), true)
+        ),
+        vec![VoiceChange::Toggle("self_mute", true)]
+    );
+    // A join brings whatever flags the member had
+    assert_eq!(
+        voice_changes(
+            Some(&voice_state(None, false)),
+            None,
+            &voice_state(Some(10), true)
+        ),
+        vec![VoiceChange::Join(ChannelId(10))]
+    );
+}
+
+#[test]
+fn uses_the_open_session_without_old_state() {
+    let muted = voice_state(Some(10), true);
+
+    // A toggle after a restart is not a join
+    assert_eq!(voice_changes(None, Some(ChannelId(10)), &muted), Vec::new());
+    assert_eq!(
+        voice_changes(None, Some(ChannelId(20)), &muted),
+        vec![VoiceChange::Move {
+            from: ChannelId(20),
+            to: ChannelId(10)
+        }]
+    );
+    assert_eq!(
+        voice_changes(None, Some(ChannelId(10)), &voice_state(None, false)),
+        vec![VoiceChange::Leave(ChannelId(10))]
+    );
+    assert_eq!(
+        voice_changes(None, None, &muted),
+        vec![VoiceChange::Join(ChannelId(10))]
+    );
+    assert_eq!(
+        voice_changes(None, None, &voice_state(None, false)),
+        Vec::new()
+    );
+}
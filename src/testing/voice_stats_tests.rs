@@ -0,0 +1,59 @@
+use crate::features::voice_stats::{format_duration, TrackerState};
+
+// 20ms of 48kHz stereo
+const PACKET_SAMPLES: usize = 1920;
+
+#[test]
+fn formats_durations() {
+    assert_eq!(format_duration(0), "0m 00s");
+    assert_eq!(format_duration(59), "0m 59s");
+    assert_eq!(format_duration(61), "1m 01s");
+    assert_eq!(format_duration(3599), "59m 59s");
+    // Seconds stop mattering past an hour
+    assert_eq!(format_duration(3600), "1h 00m");
+    assert_eq!(format_duration(3 * 3600 + 5 * 60 + 59), "3h 05m");
+    assert_eq!(format_duration(100 * 3600), "100h 00m");
+}
+
+#[test]
+fn credits_audio_once_its_ssrc_is_mapped() {
+    let mut state = TrackerState::default();
+    for _ in 0..50 {
+        state.add_audio(7, PACKET_SAMPLES);
+    }
+    assert_eq!(state.speaking_ms(1), None);
+
+    state.map_ssrc(7, 1);
+    assert_eq!(state.speaking_ms(1), Some(1000));
+    state.add_audio(7, PACKET_SAMPLES);
+    assert_eq!(state.speaking_ms(1), Some(1020));
+}
+
+#[test]
+fn packet_gaps_are_not_speech() {
+    let mut state = TrackerState::default();
+    state.map_ssrc(7, 1);
+    state.add_audio(7, PACKET_SAMPLES);
+    // The packet after ten seconds of silence carries the audio of the ones that never came
+    state.add_audio(7, PACKET_SAMPLES * 500);
+    assert_eq!(state.speaking_ms(1), Some(40));
+}
+
+#[test]
+fn waits_for_a_bounded_number_of_ssrcs() {
+    let mut state = TrackerState::default();
+    for ssrc in 0..1000 {
+        state.add_audio(ssrc, PACKET_SAMPLES);
+    }
+    // Too late to be waited for, its audio was dropped
+    state.map_ssrc(999, 1);
+    assert_eq!(state.speaking_ms(1), Some(0));
+    state.map_ssrc(0, 2);
+    assert_eq!(state.speaking_ms(2), Some(20));
+
+    // The channel emptied, nobody is left to claim the others
+    state.leave(1);
+    state.leave(2);
+    state.map_ssrc(1, 3);
+    assert_eq!(state.speaking_ms(3), Some(0));
+}